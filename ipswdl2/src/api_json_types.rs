@@ -3,15 +3,20 @@ use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use serde::Serialize;
 
+/// An Apple device covered by the API.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Device {
     pub name: String,
     pub identifier: String,
     pub platform: String,
+    /// Board of the device, ex. "d22ap". Empty in device lists cached before it was read.
+    #[serde(default)]
+    pub boardconfig: String,
     pub cpid: u32,
     pub bdid: u32,
 }
 
+/// A single firmware release for a device.
 //only used in firmware listing
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Firmware {
@@ -22,9 +27,18 @@ pub struct Firmware {
     pub md5sum: String,
     pub filesize: u64,
     pub url: String,
-    pub uploaddate: DateTime<Utc>
+    pub uploaddate: DateTime<Utc>,
+    /// True if Apple is still signing this firmware, meaning it can be restored.
+    pub signed: bool,
+    /// True for OTA updates, which are downloaded from `url` rather than as an ipsw.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ota: bool,
+    /// True for beta firmware, which is downloaded from `url` as ipsw.me doesn't list it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub beta: bool
 }
 
+/// A device along with every firmware released for it.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct FirmwareListing {
     pub name: String,
@@ -36,5 +50,70 @@ pub struct FirmwareListing {
     pub firmwares: Vec<Firmware> //Chrono ordered by api
 }
 
+/// A device along with its OTA updates, for devices without ipsw files such as the Apple Watch.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OtaListing {
+    pub name: String,
+    pub identifier: String,
+    pub platform: String,
+    pub boardconfig: String,
+    pub cpid: u32,
+    pub bdid: u32,
+    pub firmwares: Vec<OtaFirmware>
+}
+
+/// A single OTA update for a device.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OtaFirmware {
+    pub identifier: String,
+    pub version: String,
+    pub buildid: String,
+    #[serde(default)]
+    pub sha1sum: Option<String>,
+    #[serde(default)]
+    pub md5sum: Option<String>,
+    pub filesize: u64,
+    pub url: String,
+    pub uploaddate: Option<DateTime<Utc>>,
+    pub releasedate: Option<DateTime<Utc>>,
+    pub signed: bool,
+    /// Build this update applies to. Only set for delta updates, which can't be installed on their own.
+    #[serde(default)]
+    pub prerequisitebuildid: Option<String>
+}
+
+/// Decryption keys known for the images of a firmware build.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FirmwareKeys {
+    pub identifier: String,
+    pub buildid: String,
+    /// Apple's internal name for the build, ex. "Sydney".
+    pub codename: Option<String>,
+    pub baseband: Option<String>,
+    pub keys: Vec<ImageKey>,
+}
 
+/// Decryption key of a single image in a firmware, ex. iBoot.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ImageKey {
+    /// Kind of image, ex. "iBSS" or "RestoreRamDisk".
+    pub image: String,
+    pub filename: String,
+    pub kbag: Option<String>,
+    pub key: Option<String>,
+    pub iv: Option<String>,
+}
 
+/// A release of iTunes, used to manage devices on Windows and older Macs.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ITunesRelease {
+    pub platform: String,
+    pub version: String,
+    /// Installer for 32 bit systems, if there is one.
+    pub url: Option<String>,
+    /// Installer for 64 bit systems, if there is one.
+    #[serde(rename = "64biturl")]
+    pub url_64bit: Option<String>,
+    pub releasedate: Option<DateTime<Utc>>,
+    pub uploaddate: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,157 @@
+//! Beta firmware, which ipsw.me doesn't list, read from an [AppleDB](https://appledb.dev) style source.
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::api_json_types::Firmware;
+use crate::version::newest_first;
+
+/// Source of beta builds used if none is configured.
+pub const DEFAULT_BETA_SOURCE: &str = "https://api.appledb.dev/ios/main.json";
+
+/// Directory under the download directory beta firmware is saved to, keeping it apart from releases.
+pub const BETA_DIR: &str = "beta";
+
+/// Which firmware to download.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    /// Releases listed by ipsw.me.
+    Release,
+    /// Developer and public betas, and release candidates, from the beta source.
+    Beta,
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "release" => Ok(Channel::Release),
+            "beta" => Ok(Channel::Beta),
+            _ => Err(format!("unknown channel: {}, expected release or beta", s)),
+        }
+    }
+}
+
+/// A build of an OS in the beta source.
+#[derive(Deserialize, Debug, Clone)]
+pub struct BetaBuild {
+    /// Version including the beta, ex. "18.0 beta 3".
+    pub version: String,
+    pub build: String,
+    #[serde(default)]
+    pub beta: bool,
+    /// True for release candidates.
+    #[serde(default)]
+    pub rc: bool,
+    /// Release date, ex. "2024-07-08".
+    pub released: Option<String>,
+    #[serde(default)]
+    pub sources: Vec<BetaSource>,
+}
+
+/// A file a build can be downloaded as.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BetaSource {
+    /// Kind of file, ex. "ipsw" or "ota".
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Identifiers of the devices the file is for.
+    #[serde(default)]
+    pub device_map: Vec<String>,
+    #[serde(default)]
+    pub links: Vec<BetaLink>,
+    /// Checksums by algorithm, ex. "sha1".
+    #[serde(default)]
+    pub hashes: BTreeMap<String, String>,
+    pub size: Option<u64>,
+}
+
+/// A place a file can be downloaded from.
+#[derive(Deserialize, Debug, Clone)]
+pub struct BetaLink {
+    pub url: String,
+    #[serde(default)]
+    pub preferred: bool,
+}
+
+/// Gets the beta ipsw files of a device from the beta source, newest first, as firmware marked [`Firmware::beta`].
+pub fn beta_firmware(builds: &[BetaBuild], identifier: &str) -> Vec<Firmware> {
+    let mut firmwares: Vec<Firmware> = builds
+        .iter()
+        .filter(|build| build.beta || build.rc)
+        .flat_map(|build| {
+            build
+                .sources
+                .iter()
+                .filter(|source| source.kind == "ipsw" && source.device_map.iter().any(|d| d == identifier))
+                .filter_map(move |source| {
+                    let link = source.links.iter().find(|link| link.preferred).or_else(|| source.links.first())?;
+
+                    Some(Firmware {
+                        identifier: identifier.to_string(),
+                        version: build.version.clone(),
+                        buildid: build.build.clone(),
+                        sha1sum: source.hashes.get("sha1").cloned().unwrap_or_default(),
+                        md5sum: source.hashes.get("md5").cloned().unwrap_or_default(),
+                        filesize: source.size.unwrap_or(0),
+                        url: link.url.clone(),
+                        uploaddate: released_date(build.released.as_deref()),
+                        signed: false,
+                        ota: false,
+                        beta: true,
+                    })
+                })
+        })
+        .collect();
+
+    firmwares.sort_by(newest_first);
+    firmwares
+}
+
+/// Parses a release date like "2024-07-08", or the epoch if unknown.
+fn released_date(released: Option<&str>) -> DateTime<Utc> {
+    released
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+        .map(|date| Utc.from_utc_datetime(&date.and_hms(0, 0, 0)))
+        .unwrap_or_else(|| Utc.timestamp(0, 0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_beta_ipsws_of_a_device() {
+        let builds: Vec<BetaBuild> = serde_json::from_str(
+            r#"[
+                {"version": "18.0", "build": "22A3354", "released": "2024-09-16", "sources": [
+                    {"type": "ipsw", "deviceMap": ["iPhone15,2"], "links": [{"url": "https://example.com/release.ipsw"}]}
+                ]},
+                {"version": "18.0 beta 2", "build": "22A5297f", "beta": true, "released": "2024-06-24", "sources": [
+                    {"type": "ota", "deviceMap": ["iPhone15,2"], "links": [{"url": "https://example.com/ota.zip"}]},
+                    {"type": "ipsw", "deviceMap": ["iPhone15,2"], "hashes": {"sha1": "abc"}, "size": 42, "links": [
+                        {"url": "https://example.com/mirror.ipsw"},
+                        {"url": "https://example.com/beta2.ipsw", "preferred": true}
+                    ]}
+                ]},
+                {"version": "18.0 beta 3", "build": "22A5307f", "beta": true, "released": "2024-07-08", "sources": [
+                    {"type": "ipsw", "deviceMap": ["iPhone15,2"], "links": [{"url": "https://example.com/beta3.ipsw"}]}
+                ]}
+            ]"#,
+        )
+        .unwrap();
+
+        let firmware = beta_firmware(&builds, "iPhone15,2");
+        assert_eq!(firmware.len(), 2);
+        assert_eq!(firmware[0].buildid, "22A5307f");
+        assert_eq!(firmware[1].url, "https://example.com/beta2.ipsw");
+        assert_eq!(firmware[1].sha1sum, "abc");
+        assert!(firmware.iter().all(|f| f.beta));
+
+        assert!(beta_firmware(&builds, "iPhone10,1").is_empty());
+    }
+}
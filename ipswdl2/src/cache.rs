@@ -0,0 +1,86 @@
+//! On disk cache of API responses, so repeated runs don't fetch everything again.
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use tracing::{debug, error};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// A cached API response.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Cached<T> {
+    /// ETag the server sent with the response, used to ask if it changed.
+    pub etag: Option<String>,
+    /// When the response was last fetched or confirmed unchanged.
+    pub fetched: DateTime<Utc>,
+    pub value: T,
+}
+
+/// Directory of cached API responses, stored as JSON files.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// Uses `dir` to store the cache. It is created when first written to.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Cache { dir: dir.into() }
+    }
+
+    /// The users cache directory, ex. `~/.cache/ipswdl2` on Linux.
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("ipswdl2"))
+    }
+
+    /// Reads a cached response. Missing or unreadable entries are `None`, as they can just be fetched again.
+    pub fn read<T: DeserializeOwned>(&self, name: &str) -> Option<Cached<T>> {
+        let path = self.dir.join(format!("{}.json", name));
+        let json = fs::read(&path).ok()?;
+
+        match serde_json::from_slice(&json) {
+            Ok(cached) => Some(cached),
+            Err(why) => {
+                debug!("ignoring unreadable cache entry {}: {}", path.display(), why);
+                None
+            }
+        }
+    }
+
+    /// Writes a response to the cache. Failures are only logged, as the cache is an optimization.
+    pub fn write<T: Serialize>(&self, name: &str, cached: &Cached<T>) {
+        let path = self.dir.join(format!("{}.json", name));
+
+        let written = fs::create_dir_all(&self.dir)
+            .and_then(|_| serde_json::to_vec(cached).map_err(Into::into))
+            .and_then(|json| fs::write(&path, json));
+
+        if let Err(why) = written {
+            error!("failed to write cache entry {}: {}", path.display(), why);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_then_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path().join("cache"));
+
+        assert!(cache.read::<Vec<u32>>("numbers").is_none());
+
+        cache.write("numbers", &Cached {
+            etag: Some("\"abc\"".to_string()),
+            fetched: Utc::now(),
+            value: vec![1, 2, 3],
+        });
+        let cached = cache.read::<Vec<u32>>("numbers").unwrap();
+
+        assert_eq!(cached.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(cached.value, vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,71 @@
+//! Cancelling running downloads from outside the downloader.
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Cancels the downloads of a [`Downloader`](crate::Downloader) once [`cancel`](CancellationToken::cancel) is called.
+///
+/// Clones share the same state, so one clone can be handed to a signal handler while another is given to the
+/// downloader. Interrupted downloads are kept as `.part` files, so they resume on the next run.
+#[derive(Clone, Debug)]
+pub struct CancellationToken {
+    tx: Arc<watch::Sender<bool>>,
+    rx: watch::Receiver<bool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        let (tx, rx) = watch::channel(false);
+
+        CancellationToken { tx: Arc::new(tx), rx }
+    }
+
+    /// Cancels everything using this token. Can be called from any thread, ex. a signal handler.
+    pub fn cancel(&self) {
+        //Only fails if every receiver is gone, but this token holds one
+        let _ = self.tx.send(true);
+    }
+
+    /// True once the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Completes once the token has been cancelled, immediately if it already was.
+    pub async fn cancelled(&self) {
+        let mut rx = self.rx.clone();
+
+        while !*rx.borrow() {
+            //The sender lives as long as this token, so this never fails
+            if rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn clones_share_cancellation() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+
+        let waiter = tokio::spawn(async move { clone.cancelled().await });
+        token.cancel();
+        waiter.await.unwrap();
+
+        assert!(token.is_cancelled());
+        //Already cancelled tokens complete right away
+        token.cancelled().await;
+    }
+}
@@ -1,71 +1,568 @@
 //! Provides a client to access the IPSW.me API.
+use std::ops::Range;
+use std::time::Duration;
+
 use bytes::Bytes;
-use futures::Stream;
-use reqwest::*;
+use futures::{Stream, StreamExt};
+use chrono::{DateTime, TimeZone, Utc};
+use tracing::{debug, error, info, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_RANGE, ETAG, IF_NONE_MATCH, RANGE, RETRY_AFTER};
+use reqwest::{ClientBuilder, Response, StatusCode};
 
 use crate::api_json_types::*;
+use crate::beta::BetaBuild;
+use crate::cache::{Cache, Cached};
+use crate::error::{Error, Result};
+use crate::paths::sanitize_file_name;
+use crate::version::newest_first;
+
+/// Options for how a [`Client`] makes requests.
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// Base URL of the ipsw.me API, or of a mirror of it, ex. "https://api.ipsw.me/v4".
+    pub api_url: String,
+    /// Time to wait for a connection to be made before giving up. Waits forever if `None`.
+    pub connect_timeout: Option<Duration>,
+    /// Time to wait for an API response, or for the next chunk of a download, before giving up. Waits forever if `None`.
+    pub read_timeout: Option<Duration>,
+    /// User-Agent sent with every request.
+    pub user_agent: String,
+    /// Extra headers sent with every request, as name and value.
+    pub headers: Vec<(String, String)>,
+    /// Caches API responses between runs. Nothing is cached if `None`.
+    pub cache: Option<Cache>,
+    /// Use cached responses younger than this without asking the API. Cached responses are only used as a fallback
+    /// if `None`.
+    pub cache_ttl: Option<Duration>,
+    /// Ignore the TTL, asking the API for every response.
+    pub refresh: bool,
+    /// Idle connections kept open to each host, so back to back downloads skip the TLS handshake.
+    pub pool_size: usize,
+    /// Time an idle connection is kept open for. Kept open forever if `None`.
+    pub pool_idle_timeout: Option<Duration>,
+    /// Interval of TCP keepalive probes, which keep idle and slow connections from being dropped. Not sent if `None`.
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            api_url: DEFAULT_API_URL.to_string(),
+            connect_timeout: Some(Duration::from_secs(30)),
+            read_timeout: Some(Duration::from_secs(60)),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: Vec::new(),
+            cache: Cache::default_dir().map(Cache::new),
+            cache_ttl: None,
+            refresh: false,
+            pool_size: DEFAULT_POOL_SIZE,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            tcp_keepalive: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// User-Agent sent if none is configured, ex. "ipswdl2/0.1.1".
+pub const DEFAULT_USER_AGENT: &str = concat!("ipswdl2/", env!("CARGO_PKG_VERSION"));
+
+/// Base URL of the ipsw.me API used if none is configured.
+pub const DEFAULT_API_URL: &str = "https://api.ipsw.me/v4";
+
+/// URL on Apple's CDN that `status-api` checks if none is given. Firmware is served from this host.
+pub const DEFAULT_CDN_URL: &str = "https://updates.cdn-apple.com/";
+
+/// Idle connections kept open to each host if not configured.
+pub const DEFAULT_POOL_SIZE: usize = 8;
 
 /// Client to access the ipsw.me API.
 pub struct Client {
     internal: reqwest::Client,
+    opt: ClientOptions,
 }
 
 impl Client {
-    pub fn new() -> Self {
-        let internal = ClientBuilder::new().build().unwrap();
+    /// Creates a client with the default configuration.
+    ///
+    /// # Returns
+    /// * Err - The TLS backend failed to initialize.
+    pub fn new() -> Result<Self> {
+        Self::with_options(ClientOptions::default())
+    }
 
-        Client { internal }
+    /// Creates a client with the passed configuration.
+    ///
+    /// # Returns
+    /// * Err(InvalidHeader) - A header name or value isn't valid in HTTP.
+    /// * Err - The TLS backend failed to initialize.
+    pub fn with_options(opt: ClientOptions) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &opt.headers {
+            let invalid = || Error::InvalidHeader(format!("{}: {}", name, value));
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+            let value = HeaderValue::from_str(value).map_err(|_| invalid())?;
+            headers.append(name, value);
+        }
+
+        //HTTP/2 is negotiated where servers support it, multiplexing parallel requests over one connection
+        let mut builder = ClientBuilder::new()
+            .user_agent(opt.user_agent.as_str())
+            .default_headers(headers)
+            .pool_max_idle_per_host(opt.pool_size)
+            .pool_idle_timeout(opt.pool_idle_timeout)
+            .tcp_keepalive(opt.tcp_keepalive)
+            .tcp_nodelay(true);
+        if let Some(connect_timeout) = opt.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        let internal = builder.build()?;
+
+        Ok(Client { internal, opt })
+    }
+
+    /// True if a cached response is younger than the TTL, and so can be used without asking the API.
+    fn is_fresh<T>(&self, cached: &Cached<T>) -> bool {
+        match self.opt.cache_ttl {
+            Some(ttl) if !self.opt.refresh => {
+                let age = Utc::now() - cached.fetched;
+                age.to_std().map_or(true, |age| age < ttl)
+            }
+            _ => false,
+        }
+    }
+
+    /// Gets the URL of an API endpoint, ex. "devices", under the configured base URL.
+    fn api_url(&self, endpoint: &str) -> String {
+        format!("{}/{}", self.opt.api_url.trim_end_matches('/'), endpoint)
+    }
+
+    /// Starts a GET request. API requests time out as a whole after the read timeout,
+    /// as their responses are small.
+    fn get_api(&self, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        let request = self.internal.get(url);
+
+        match self.opt.read_timeout {
+            Some(read_timeout) => request.timeout(read_timeout),
+            None => request,
+        }
+    }
+
+    /// Times how long the API takes to answer a request for the device list. See [`Client::probe`].
+    pub async fn probe_api(&self) -> Probe {
+        self.probe(&self.api_url("devices")).await
+    }
+
+    /// Times how long `url` takes to answer, for telling network problems apart from server problems.
+    /// Any response counts as an answer, even an error status, as the server was reached. Never cached.
+    pub async fn probe(&self, url: &str) -> Probe {
+        //HEAD is not supported everywhere, so a GET is sent and only its headers are waited for
+        let started = std::time::Instant::now();
+        let answer = self.get_api(url).send().await.map(|response| (response.status(), started.elapsed()));
+        Probe {
+            url: url.to_string(),
+            answer: answer.map_err(Error::from),
+        }
     }
 
     /// Gets a list of all Apple devices covered by this API.
     ///
+    /// If cached, the list is only downloaded again if it changed, or used as is if younger than the TTL.
+    /// Should the request fail, the cached list is used.
+    ///
     /// # Returns
     /// * Ok(Vec< Value >) - Vec of device json objects.
-    /// * Err - The request failed somehow.
+    /// * Err - The request failed somehow, and nothing was cached.
     pub async fn get_all_devices(&self) -> Result<Vec<Device>> {
-        let response = self.internal.get("https://api.ipsw.me/v4/devices").send().await?;
+        let cached: Option<Cached<Vec<Device>>> = self.opt.cache.as_ref().and_then(|c| c.read("devices"));
+        if let Some(cached) = cached.as_ref().filter(|c| self.is_fresh(c)) {
+            debug!("using device list cached at {}", cached.fetched);
+            return Ok(cached.value.clone());
+        }
+
+        match self.fetch_all_devices(cached.as_ref().and_then(|c| c.etag.as_deref())).await {
+            Ok(Some(fetched)) => {
+                if let Some(cache) = &self.opt.cache {
+                    cache.write("devices", &fetched);
+                }
+                Ok(fetched.value)
+            }
+            //Not modified, so the cached list is still current
+            Ok(None) => {
+                let mut cached = cached.expect("device list not modified, but wasn't cached");
+                info!("device list unchanged since {}", cached.fetched);
 
-        Ok(response.json::<Vec<Device>>().await?)
+                cached.fetched = Utc::now();
+                if let Some(cache) = &self.opt.cache {
+                    cache.write("devices", &cached);
+                }
+                Ok(cached.value)
+            }
+            Err(why) => match cached {
+                Some(cached) => {
+                    error!("failed to get device list, using list cached at {}: {}", cached.fetched, why);
+                    Ok(cached.value)
+                }
+                None => Err(why),
+            },
+        }
+    }
+
+    /// Downloads the device list, unless it still has the ETag `etag`.
+    ///
+    /// # Returns
+    /// * Ok(None) - The list has not changed.
+    async fn fetch_all_devices(&self, etag: Option<&str>) -> Result<Option<Cached<Vec<Device>>>> {
+        let mut request = self.get_api(self.api_url("devices"));
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        let response = request.send().await?;
+        if etag.is_some() && response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = check_status(response, "device list")?;
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+
+        Ok(Some(Cached {
+            etag,
+            fetched: Utc::now(),
+            value: response.json::<Vec<Device>>().await?,
+        }))
     }
 
     /// Gets a firmware listing for a given device.
     ///
+    /// Listings cached younger than the TTL are used without asking the API. Should the request fail, the cached
+    /// listing is used.
+    ///
     /// # Returns
     /// * Ok(FirmwareListing) - info about a device along with its firmware entries. Device name has / and \ replaced with 'z' for use in directories.
-    /// * Err - The request failed somehow.
+    /// * Err - The request failed somehow, and nothing was cached.
     pub async fn get_device_firmware(&self, device: &Device) -> Result<FirmwareListing> {
-        let response = self.internal.get(format!("https://api.ipsw.me/v4/device/{}?type=ipsw", device.identifier)).send().await?;
+        let cache_name = format!("listing-{}", device.identifier);
+        let cached: Option<Cached<FirmwareListing>> = self.opt.cache.as_ref().and_then(|c| c.read(&cache_name));
+        if let Some(cached) = cached.as_ref().filter(|c| self.is_fresh(c)) {
+            debug!("using {} listing cached at {}", device.identifier, cached.fetched);
+            return Ok(cached.value.clone());
+        }
+
+        match self.fetch_device_firmware(device).await {
+            Ok(firmware) => {
+                if let Some(cache) = &self.opt.cache {
+                    cache.write(&cache_name, &Cached {
+                        etag: None,
+                        fetched: Utc::now(),
+                        value: &firmware,
+                    });
+                }
+                Ok(firmware)
+            }
+            Err(why) => match cached {
+                Some(cached) if why.is_transient() => {
+                    error!("failed to get {} listing, using listing cached at {}: {}", device.identifier, cached.fetched, why);
+                    Ok(cached.value)
+                }
+                _ => Err(why),
+            },
+        }
+    }
+
+    /// Downloads the firmware listing of a device.
+    async fn fetch_device_firmware(&self, device: &Device) -> Result<FirmwareListing> {
+        let response = self.get_api(self.api_url(&format!("device/{}?type=ipsw", device.identifier))).send().await?;
+        let response = check_status(response, &format!("firmware listing of {}", device.identifier))?;
         let mut firmware = response.json::<FirmwareListing>().await?;
 
         //Sanitize device name for use in directories
-        firmware.name = firmware.name.replace('/', "z");
-        firmware.name = firmware.name.replace('\\', "z");
+        firmware.name = sanitize_file_name(&firmware.name);
 
         Ok(firmware)
     }
 
-    /// Begins to download the ipsw file referenced by this firmware.
+    /// Gets the full OTA updates of a device as a firmware listing, for devices without ipsw files.
+    /// Delta updates, which need an earlier build installed, are left out.
+    ///
+    /// # Returns
+    /// * Ok(FirmwareListing) - The device, and every full OTA update marked as [`Firmware::ota`], newest first.
+    /// * Err - The request failed somehow.
+    pub async fn get_device_ota(&self, device: &Device) -> Result<FirmwareListing> {
+        let response = self.get_api(self.api_url(&format!("device/{}?type=ota", device.identifier))).send().await?;
+        let response = check_status(response, &format!("OTA listing of {}", device.identifier))?;
+        let listing = response.json::<OtaListing>().await?;
+
+        let mut firmwares: Vec<Firmware> = listing
+            .firmwares
+            .into_iter()
+            .filter(|ota| ota.prerequisitebuildid.is_none())
+            .map(|ota| Firmware {
+                identifier: ota.identifier,
+                version: ota.version,
+                buildid: ota.buildid,
+                sha1sum: ota.sha1sum.unwrap_or_default(),
+                md5sum: ota.md5sum.unwrap_or_default(),
+                filesize: ota.filesize,
+                url: ota.url,
+                uploaddate: ota.uploaddate.or(ota.releasedate).unwrap_or_else(|| Utc.timestamp(0, 0)),
+                signed: ota.signed,
+                ota: true,
+                beta: false,
+            })
+            .collect();
+        firmwares.sort_by(newest_first);
+
+        Ok(FirmwareListing {
+            //Sanitize device name for use in directories
+            name: sanitize_file_name(&listing.name),
+            identifier: listing.identifier,
+            platform: listing.platform,
+            boardconfig: listing.boardconfig,
+            cpid: listing.cpid,
+            bdid: listing.bdid,
+            firmwares,
+        })
+    }
+
+    /// Gets every build from an [AppleDB](https://appledb.dev) style beta source, ex. [`DEFAULT_BETA_SOURCE`](crate::beta::DEFAULT_BETA_SOURCE).
+    pub async fn get_beta_builds(&self, source: &str) -> Result<Vec<BetaBuild>> {
+        let response = self.get_api(source).send().await?;
+        let response = check_status(response, &format!("beta builds from {}", source))?;
+
+        Ok(response.json::<Vec<BetaBuild>>().await?)
+    }
+
+    /// Gets the decryption keys known for a firmware build.
     ///
     /// # Returns
-    /// * Ok(stream, dl_size) - The ipsw file being downloaded as an async byte stream, and the length in bytes of that stream.
-    /// * Err - Errored when hitting Apples API. This can happen for old ipsw files.
-    pub async fn download_ipsw(&self, fw: &Firmware) -> Result<(impl Stream<Item = Result<Bytes>>, u64)> {
-        let response = self.internal.get(format!("https://api.ipsw.me/v4/ipsw/download/{}/{}", fw.identifier, fw.buildid)).send().await?;
+    /// * Err(NotFound) - No keys are known for the build, which is the case for most builds.
+    /// * Err - The request failed somehow.
+    pub async fn get_firmware_keys(&self, identifier: &str, buildid: &str) -> Result<FirmwareKeys> {
+        let response = self.get_api(self.api_url(&format!("keys/ipsw/{}/{}", identifier, buildid))).send().await?;
+        let response = check_status(response, &format!("keys of {} {}", identifier, buildid))?;
+
+        Ok(response.json::<FirmwareKeys>().await?)
+    }
+
+    /// Begins to download the ipsw file referenced by this firmware, starting at byte `from`.
+    ///
+    /// Servers not supporting ranges send the whole file instead, so check where the stream starts.
+    ///
+    /// The stream errors with [`Error::Stalled`] if no data arrives for the read timeout, so a hung connection doesn't
+    /// hang forever.
+    ///
+    /// # Returns
+    /// * Ok(stream, start, dl_size) - The ipsw file being downloaded as an async byte stream, the offset in the file the
+    ///   stream starts at, and the length in bytes of the whole file.
+    /// * Err(NotFound | Forbidden) - Apple no longer serves the file. This can happen for old ipsw files.
+    /// * Err - Errored when hitting Apples API.
+    pub async fn download_ipsw(&self, fw: &Firmware, from: u64) -> Result<(impl Stream<Item = Result<Bytes>> + Unpin, u64, u64)> {
+        self.download_url(&self.ipsw_url(fw), &format!("{} {}", fw.identifier, fw.buildid), from).await
+    }
+
+    /// Begins to download the bytes in `range` of an ipsw file, ex. one segment of a segmented download.
+    ///
+    /// The stream behaves like the one of [`Client::download_ipsw`].
+    ///
+    /// # Returns
+    /// * Err(RangeNotSupported) - The server sent the whole file or another range instead of the range.
+    /// * Err - See [`Client::download_ipsw`].
+    pub async fn download_ipsw_range(
+        &self,
+        fw: &Firmware,
+        range: Range<u64>,
+    ) -> Result<impl Stream<Item = Result<Bytes>> + Unpin> {
+        let what = format!("{} {}", fw.identifier, fw.buildid);
+
+        let response = self
+            .internal
+            .get(self.ipsw_url(fw))
+            .header(RANGE, format!("bytes={}-{}", range.start, range.end.saturating_sub(1)))
+            .send()
+            .await?;
+        let response = check_status(response, &what)?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(Error::RangeNotSupported(what));
+        }
 
-        //TODO check for non-200 code
+        //The bytes are written at the offset that was asked for, so they must be exactly that range
+        let sent = response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_content_range);
+        if sent != Some(range.clone()) {
+            warn!("asked for bytes {:?} of {}, but got {:?}", range, what, sent);
+            return Err(Error::RangeNotSupported(what));
+        }
 
-        let len = response.content_length().unwrap();
-        Ok((response.bytes_stream(), len))
+        Ok(self.chunks(response))
     }
+
+    /// Gets the URL an ipsw file is downloaded from.
+    fn ipsw_url(&self, fw: &Firmware) -> String {
+        //OTA updates and betas have no download endpoint, only Apple's URL
+        if fw.ota || fw.beta {
+            fw.url.clone()
+        } else {
+            self.api_url(&format!("ipsw/download/{}/{}", fw.identifier, fw.buildid))
+        }
+    }
+
+    /// Gets every iTunes release for a platform, ex. "windows" or "macos".
+    pub async fn get_itunes_releases(&self, platform: &str) -> Result<Vec<ITunesRelease>> {
+        let response = self.get_api(self.api_url(&format!("itunes/{}", platform))).send().await?;
+        let response = check_status(response, &format!("iTunes releases for {}", platform))?;
+
+        Ok(response.json::<Vec<ITunesRelease>>().await?)
+    }
+
+    /// Begins to download a file, starting at byte `from`. `what` describes the file in errors.
+    ///
+    /// See [`Client::download_ipsw`] for how the stream behaves.
+    pub async fn download_url(
+        &self,
+        url: &str,
+        what: &str,
+        from: u64,
+    ) -> Result<(impl Stream<Item = Result<Bytes>> + Unpin, u64, u64)> {
+        let mut request = self.internal.get(url);
+        if from > 0 {
+            request = request.header(RANGE, format!("bytes={}-", from));
+        }
+        let response = request.send().await?;
+        //An error page streamed into an ipsw would look like a successful download
+        let response = check_status(response, what)?;
+
+        let len = response.content_length().ok_or_else(|| Error::NoContentLength(what.to_string()))?;
+        let start = if response.status() == StatusCode::PARTIAL_CONTENT { from } else { 0 };
+
+        Ok((self.chunks(response), start, start + len))
+    }
+
+    /// Streams the body of a response, erroring with [`Error::Stalled`] if no data arrives for the read timeout.
+    fn chunks(&self, response: Response) -> impl Stream<Item = Result<Bytes>> + Unpin {
+        //Time out each chunk rather than the whole request, as downloads can take hours
+        let read_timeout = self.opt.read_timeout;
+        let stream = futures::stream::unfold(Box::pin(response.bytes_stream()), move |mut chunks| async move {
+            let chunk = match read_timeout {
+                Some(read_timeout) => match tokio::time::timeout(read_timeout, chunks.next()).await {
+                    Ok(chunk) => chunk.map(|c| c.map_err(Error::from)),
+                    Err(_) => Some(Err(Error::Stalled(read_timeout))),
+                },
+                None => chunks.next().await.map(|c| c.map_err(Error::from)),
+            };
+
+            chunk.map(|chunk| (chunk, chunks))
+        });
+
+        Box::pin(stream)
+    }
+}
+
+/// Parses a header passed as `name:value`, trimming whitespace around both.
+pub fn parse_header(s: &str) -> std::result::Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("header must look like name:value, got: {}", s)),
+    }
+}
+
+/// How a server answered a request timed by [`Client::probe`].
+#[derive(Debug)]
+pub struct Probe {
+    pub url: String,
+    /// Status of the response and the time until its headers arrived, or why the server could not be reached.
+    pub answer: Result<(StatusCode, Duration)>,
+}
+
+/// Turns a non-success response into an error describing what was requested.
+fn check_status(response: Response, what: &str) -> Result<Response> {
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+
+    match response.status() {
+        status if status.is_success() => Ok(response),
+        StatusCode::NOT_FOUND => Err(Error::NotFound(what.to_string())),
+        StatusCode::FORBIDDEN => Err(Error::Forbidden(what.to_string())),
+        StatusCode::TOO_MANY_REQUESTS => Err(Error::RateLimited {
+            what: what.to_string(),
+            retry_after,
+        }),
+        status => Err(Error::Status {
+            what: what.to_string(),
+            status,
+            retry_after,
+        }),
+    }
+}
+
+/// Parses a Retry-After header, which is either a number of seconds or an HTTP date.
+/// Dates in the past mean no wait at all.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some((date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
+
+/// Parses the range of a Content-Range header, ex. "bytes 0-499/1234" is `0..500`.
+fn parse_content_range(value: &str) -> Option<Range<u64>> {
+    let (range, _total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+
+    (start <= end).then(|| start..end + 1)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn api_url_joins_endpoints() {
+        let client = Client::with_options(ClientOptions {
+            api_url: "http://localhost:8080/v4/".to_string(),
+            ..ClientOptions::default()
+        })
+        .unwrap();
+
+        assert_eq!(client.api_url("devices"), "http://localhost:8080/v4/devices");
+    }
+
+    #[test]
+    fn parse_retry_after_works() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::from_secs(0)));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn parse_content_range_works() {
+        assert_eq!(parse_content_range("bytes 0-499/1234"), Some(0..500));
+        assert_eq!(parse_content_range("bytes 500-1233/*"), Some(500..1234));
+        assert_eq!(parse_content_range("bytes */1234"), None);
+        assert_eq!(parse_content_range("bytes 9-3/1234"), None);
+    }
+
+    #[test]
+    fn parse_header_works() {
+        assert_eq!(parse_header("X-Token: abc:def").unwrap(), ("X-Token".to_string(), "abc:def".to_string()));
+        assert!(parse_header("no colon").is_err());
+        assert!(parse_header(":value").is_err());
+    }
+
     #[tokio::test]
     async fn get_all_devices_works() {
-        let client = Client::new();
+        let client = Client::new().unwrap();
 
         let response = client.get_all_devices().await.expect("Couldnt hit API!");
 
@@ -77,7 +574,7 @@ mod test {
 
     #[tokio::test]
     async fn get_device_firmware_works() {
-        let client = Client::new();
+        let client = Client::new().unwrap();
 
         let response = client.get_all_devices().await.expect("Couldnt hit API!");
 
@@ -0,0 +1,81 @@
+//! The config file, holding settings that don't fit on the command line, like SMTP credentials.
+//!
+//! It is TOML, read from `ipswdl2/config.toml` in the users config directory unless another path is passed.
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+use serde::Deserialize;
+
+use crate::email::EmailConfig;
+use crate::error::{Error, Result};
+
+/// Settings read from the config file. Every section is optional.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Email a summary after each run.
+    pub email: Option<EmailConfig>,
+}
+
+impl Config {
+    /// The config file in the users config directory, ex. `~/.config/ipswdl2/config.toml` on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("ipswdl2").join("config.toml"))
+    }
+
+    /// Reads the config file at `path`.
+    ///
+    /// # Returns
+    /// * Err(Io) - The file could not be read.
+    /// * Err(Config) - The file isn't a valid config.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|why| Error::io("could not read config file", path, why))?;
+
+        toml::from_str(&text).map_err(|source| Error::Config {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Reads the config file at the default path. Having no config file is the same as an empty one.
+    ///
+    /// # Returns
+    /// See [`load`](Config::load).
+    pub fn load_default() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(&path),
+            _ => {
+                debug!("no config file found, using defaults");
+                Ok(Config::default())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_email_section() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [email]
+            smtp_host = "smtp.example.com"
+            from = "ipswdl2 <archive@example.com>"
+            to = ["me@example.com"]
+            "#,
+        )
+        .unwrap();
+
+        let email = Config::load(&path).unwrap().email.unwrap();
+        assert_eq!(email.smtp_host, "smtp.example.com");
+        assert_eq!(email.to, vec!["me@example.com".to_string()]);
+
+        std::fs::write(&path, "[emial]").unwrap();
+        assert!(matches!(Config::load(&path), Err(Error::Config { .. })));
+    }
+}
@@ -0,0 +1,89 @@
+//! Devices attached over USB, found through usbmuxd.
+//!
+//! Devices are read with the `idevice_id` and `ideviceinfo` tools of libimobiledevice, so they must be installed
+//! and each device must trust the computer.
+use std::process::Stdio;
+
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::error::{Error, Result};
+
+/// A device attached over USB.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectedDevice {
+    /// Unique id of the device, ex. `00008030-001A35E22E08802E`.
+    pub udid: String,
+    /// Identifier of the model, ex. `iPhone12,1`.
+    pub identifier: String,
+    /// Version of the installed OS, ex. `17.4.1`.
+    pub version: String,
+}
+
+/// Finds every device attached over USB.
+///
+/// # Returns
+/// * Ok(Vec) - The attached devices, empty if there are none.
+/// * Err(ConnectedDevices) - libimobiledevice isn't installed, or a device could not be read, ex. it isn't trusted.
+pub async fn connected_devices() -> Result<Vec<ConnectedDevice>> {
+    let udids = parse_udids(&run("idevice_id", &["-l"]).await?);
+
+    let mut devices = Vec::new();
+    for udid in udids {
+        let identifier = run("ideviceinfo", &["-u", &udid, "-k", "ProductType"]).await?.trim().to_string();
+        let version = run("ideviceinfo", &["-u", &udid, "-k", "ProductVersion"]).await?.trim().to_string();
+        debug!("found connected {} on {} ({})", identifier, version, udid);
+
+        devices.push(ConnectedDevice { udid, identifier, version });
+    }
+
+    Ok(devices)
+}
+
+/// Runs a libimobiledevice tool, returning its output.
+async fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|why| {
+            Error::ConnectedDevices(format!("could not run {}, is libimobiledevice installed? {}", program, why))
+        })?;
+
+    if !output.status.success() {
+        return Err(Error::ConnectedDevices(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Gets the UDIDs `idevice_id -l` lists, one per line. Devices attached over the network are listed as well on newer
+/// versions, so UDIDs seen twice are only kept once.
+fn parse_udids(output: &str) -> Vec<String> {
+    let mut udids = Vec::new();
+    for line in output.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        //Newer versions suffix network devices with " (Network)"
+        let udid = line.split_whitespace().next().unwrap_or(line).to_string();
+        if !udids.contains(&udid) {
+            udids.push(udid);
+        }
+    }
+    udids
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_udids() {
+        let output = "00008030-001A35E22E08802E\n\n00008030-001A35E22E08802E (Network)\nf0b3c7a2d1e4\n";
+
+        assert_eq!(parse_udids(output), vec!["00008030-001A35E22E08802E", "f0b3c7a2d1e4"]);
+    }
+}
@@ -0,0 +1,80 @@
+//! Native desktop notifications (Notification Center, Windows toasts or libnotify) for failed downloads and
+//! finished runs, for runs left unattended in the background.
+use tracing::{debug, error};
+
+use crate::output::DownloadResult;
+use crate::webhook::WebhookEvent;
+
+/// Name notifications are shown under.
+const APP_NAME: &str = "ipswdl2";
+
+/// Gets the title of the notification for an event, or `None` if the event isn't worth a notification.
+fn title(event: &WebhookEvent) -> Option<&'static str> {
+    match event {
+        WebhookEvent::Download(DownloadResult::Failed { .. }) => Some("Download failed"),
+        WebhookEvent::Download(_) => None,
+        WebhookEvent::RunCompleted(summary) if summary.failed > 0 => Some("Finished with failures"),
+        WebhookEvent::RunCompleted(_) => Some("Finished downloading"),
+    }
+}
+
+/// Shows a desktop notification if a download failed or a run completed. Failures are only logged, as a missing
+/// notification server shouldn't stop downloads.
+pub async fn notify_desktop(event: &WebhookEvent<'_>) {
+    let title = match title(event) {
+        Some(title) => title,
+        None => return,
+    };
+    let body = event.message();
+    debug!("showing desktop notification: {}", body);
+
+    //Showing blocks on the notification server
+    let shown = tokio::task::spawn_blocking(move || {
+        notify_rust::Notification::new()
+            .appname(APP_NAME)
+            .summary(title)
+            .body(&body)
+            .show()
+            .map(|_| ())
+            .map_err(|why| why.to_string())
+    })
+    .await;
+
+    match shown {
+        Ok(Ok(())) => {}
+        Ok(Err(why)) => error!("Failed to show desktop notification: {}", why),
+        Err(why) => error!("Failed to show desktop notification: {}", why),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::output::Summary;
+
+    #[test]
+    fn only_failures_and_runs_notify() {
+        let failed = DownloadResult::Failed {
+            device: "iPhone 8".to_string(),
+            version: None,
+            error: "oops".to_string(),
+        };
+        let skipped = DownloadResult::Skipped {
+            device: "iPhone 8".to_string(),
+            version: None,
+            path: None,
+            reason: "already downloaded".to_string(),
+        };
+        let summary = Summary {
+            downloaded: 1,
+            skipped: 0,
+            failed: 0,
+            minutes: 3,
+            deferred: Vec::new(),
+        };
+
+        assert_eq!(title(&WebhookEvent::Download(&failed)), Some("Download failed"));
+        assert_eq!(title(&WebhookEvent::Download(&skipped)), None);
+        assert_eq!(title(&WebhookEvent::RunCompleted(&summary)), Some("Finished downloading"));
+    }
+}
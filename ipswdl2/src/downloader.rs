@@ -1,362 +1,2508 @@
 //! Logic for downloading files.
-use std::error::Error;
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs::*;
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use chrono::*;
-use indicatif::ProgressStyle;
-use log::{debug, error, info};
+use futures::stream::{self, StreamExt};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use tracing::field::Empty;
+use tracing::{debug, error, info, instrument, warn, Span};
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
 use tokio::sync::watch;
 use tokio::sync::watch::Receiver;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
 
-use crate::api_json_types::{Device, FirmwareListing};
-use crate::{CliOpts, Client};
+use crate::api_json_types::{Device, Firmware, FirmwareListing};
+use crate::cancel::CancellationToken;
+use crate::beta::{beta_firmware, BetaBuild, Channel, BETA_DIR, DEFAULT_BETA_SOURCE};
+use crate::output::{print_json, DownloadResult, OutputFormat, ProgressEvent, ProgressFormat, Summary};
+use crate::rate_limit::RateLimiter;
+use crate::remote::{self, Remote};
+use crate::shsh::{self, BlobOptions};
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::filter::{DeviceFilter, Pin};
+use crate::index::{Index, IndexEntry};
+use crate::journal::{save_failures, DeviceState, FailedDevice, Journal};
+use crate::lock::DirLock;
+use crate::metrics::{serve_metrics, Metrics, METRICS_FILE_INTERVAL};
+use crate::metadata::{metadata_path, FirmwareMetadata, MetadataFormat};
+use crate::ipsw::extract_manifests;
+use crate::part::PartFile;
+use crate::paths::{self, PathTemplate};
+use crate::sums::record_sha1;
+use crate::version::{at_most, compare_versions, newest_first};
+use crate::verify::{check_zip, verify_firmware, Checksum, Digests, DownloadHasher, VerifyStatus};
+use crate::desktop::notify_desktop;
+use crate::email::{Email, EmailConfig};
+use crate::webhook::{Webhook, WebhookEvent, WebhookFormat};
 use colored::Colorize;
 
+/// Options controlling which firmware a [`Downloader`] downloads, and how.
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    /// Directory to download .ipsw files to.
+    pub download_path: PathBuf,
+    /// Upload finished downloads here, removing them from `download_path`. Firmware already on the remote is skipped.
+    pub remote: Option<Remote>,
+    /// Layout of downloaded files under `download_path`.
+    pub path_template: PathTemplate,
+    /// Delete old ipsw files next to a newer version, once it has been downloaded and verified.
+    pub delete_old_fw: bool,
+    /// After downloading, delete all but this many of the newest downloaded versions of each device.
+    pub keep: Option<usize>,
+    /// Check already downloaded files against their checksums, re-downloading any that don't match.
+    pub repair: bool,
+    /// Delete and re-download files that are already downloaded or uploaded, even if they aren't corrupt.
+    pub force: bool,
+    /// API checksums downloads are compared against.
+    pub checksum: Checksum,
+    /// Extract the restore manifests of each download into a sidecar directory next to it.
+    pub extract_manifests: bool,
+    /// Save the decryption keys of each download, if any are known, to a `.keys.json` file next to it.
+    pub save_keys: bool,
+    /// Save what is known about each download to a sidecar file next to it in this format.
+    pub metadata: Option<MetadataFormat>,
+    /// Download the newest OTA update of devices without ipsw files, like the Apple Watch, instead of leaving them out.
+    pub ota_fallback: bool,
+    /// Save SHSH blobs of each signed firmware downloaded, or already downloaded, for a device.
+    pub blobs: Option<BlobOptions>,
+    /// Download releases, or betas from `beta_source` into the beta directory.
+    pub channel: Channel,
+    /// URL of the AppleDB style source of beta builds.
+    pub beta_source: String,
+    /// Selects which devices to download.
+    pub filter: DeviceFilter,
+    /// Amount of devices to download concurrently.
+    pub jobs: usize,
+    /// Order to download devices in.
+    pub order: DownloadOrder,
+    /// Connections to download each ipsw over, each fetching its own byte range.
+    pub segments: usize,
+    /// Download this firmware version instead of the newest.
+    pub version: Option<String>,
+    /// Download the firmware with this build id instead of the newest.
+    pub buildid: Option<String>,
+    /// Only consider firmware of this version or newer, ex. "17".
+    pub min_version: Option<String>,
+    /// Only consider firmware of this version or older. "17" allows every 17.x.
+    pub max_version: Option<String>,
+    /// Only consider firmware uploaded on or after this day.
+    pub since: Option<NaiveDate>,
+    /// Only download firmware Apple is currently signing.
+    pub signed_only: bool,
+    /// Firmware to download by identifier instead of what the version, build and signing options select, ex. from a
+    /// device list.
+    pub pins: HashMap<String, Pin>,
+    /// Versions installed on devices by identifier, ex. those attached over USB. Devices with one are only downloaded
+    /// for if there is a newer firmware.
+    pub installed_versions: HashMap<String, String>,
+    /// Download every matching firmware for each device instead of only the newest.
+    pub all_versions: bool,
+    /// Times to retry each step of a device before giving up on it: fetching its firmware listing, starting a
+    /// download, and resuming one cut off mid stream.
+    pub retries: u32,
+    /// Seconds to wait before the first retry. Doubles with each following retry.
+    pub backoff_base: f64,
+    /// Wait this long before every retry instead of doubling `backoff_base`.
+    pub retry_delay: Option<Duration>,
+    /// Maximum bytes per second to download at, shared between all concurrent downloads. Unlimited if `None`.
+    pub limit_rate: Option<u64>,
+    /// Format to print results in.
+    pub output: OutputFormat,
+    /// Only print errors, hiding progress bars and status messages.
+    pub quiet: bool,
+    /// How download progress is shown.
+    pub progress_format: ProgressFormat,
+    /// indicatif template for the progress bar of each download.
+    pub progress_template: String,
+    /// Instead of drawing progress bars, print a progress line this often (and every 10 percent). For logs and CI,
+    /// where bars can't be drawn. Bars are drawn if `None`.
+    pub progress_log_interval: Option<Duration>,
+    /// URL to POST to when firmware is downloaded, fails to download, or a run completes.
+    pub webhook: Option<String>,
+    /// Payload format for the webhook. Guessed from the URL if `None`.
+    pub webhook_format: Option<WebhookFormat>,
+    /// Show a desktop notification when a download fails or a run completes.
+    pub notify: bool,
+    /// Email a summary of each run with these SMTP settings.
+    pub email: Option<EmailConfig>,
+    /// Serve Prometheus metrics at `/metrics` on this address while running.
+    pub metrics_addr: Option<SocketAddr>,
+    /// Write Prometheus metrics to this file while running, for node_exporter's textfile collector.
+    pub metrics_file: Option<PathBuf>,
+    /// Resume the run an earlier process left unfinished, skipping the devices it finished.
+    pub resume_run: bool,
+    /// Stop starting downloads once this long has passed since the downloader was created. Downloads in progress are
+    /// finished, and the devices left are kept in the journal for the next run.
+    pub max_duration: Option<Duration>,
+    /// Bytes each pass may download at most. Devices whose firmware would go over it are kept in the journal for the
+    /// next run.
+    pub max_total_size: Option<u64>,
+}
+
+/// Order devices are downloaded in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownloadOrder {
+    /// The order of the API.
+    Api,
+    /// Devices with the least left to download first.
+    SmallestFirst,
+    /// By device name.
+    Alphabetical,
+    /// Devices whose newest selected firmware was released most recently first.
+    NewestReleaseFirst,
+}
+
+impl FromStr for DownloadOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "api" => Ok(DownloadOrder::Api),
+            "smallest-first" => Ok(DownloadOrder::SmallestFirst),
+            "alphabetical" => Ok(DownloadOrder::Alphabetical),
+            "newest-release-first" => Ok(DownloadOrder::NewestReleaseFirst),
+            _ => Err(format!("unknown order: {}", s)),
+        }
+    }
+}
+
+impl Default for DownloadOptions {
+    /// Downloads the newest firmware for every device into `./ipsw`.
+    fn default() -> Self {
+        DownloadOptions {
+            download_path: PathBuf::from("./ipsw"),
+            remote: None,
+            path_template: PathTemplate::default(),
+            delete_old_fw: false,
+            keep: None,
+            repair: false,
+            force: false,
+            checksum: Checksum::Sha1,
+            extract_manifests: false,
+            save_keys: false,
+            metadata: None,
+            ota_fallback: false,
+            blobs: None,
+            channel: Channel::Release,
+            beta_source: DEFAULT_BETA_SOURCE.to_string(),
+            filter: DeviceFilter::default(),
+            jobs: 1,
+            order: DownloadOrder::Api,
+            segments: 1,
+            version: None,
+            buildid: None,
+            min_version: None,
+            max_version: None,
+            since: None,
+            signed_only: false,
+            pins: HashMap::new(),
+            installed_versions: HashMap::new(),
+            all_versions: false,
+            retries: 3,
+            backoff_base: 2.0,
+            retry_delay: None,
+            limit_rate: None,
+            output: OutputFormat::Text,
+            quiet: false,
+            progress_format: ProgressFormat::Bars,
+            progress_template: DEFAULT_PROGRESS_TEMPLATE.to_string(),
+            progress_log_interval: None,
+            webhook: None,
+            webhook_format: None,
+            notify: false,
+            email: None,
+            metrics_addr: None,
+            metrics_file: None,
+            resume_run: false,
+            max_duration: None,
+            max_total_size: None,
+        }
+    }
+}
+
+/// Downloads ipsw files for a list of devices.
 pub struct Downloader {
     /// Client to access IPSW API.
     client: Client,
     /// List of all devices to download.
     devices: Vec<Device>,
-    /// Options passed to the command line.
-    opt: CliOpts,
-    /// Time the Downloader object was made.
+    /// Options controlling what is downloaded.
+    opt: DownloadOptions,
+    /// Stops downloads once cancelled. Interrupted downloads are kept to be resumed.
+    cancel: CancellationToken,
+    /// Set to `true` while downloads are paused.
+    pause_tx: watch::Sender<bool>,
+    /// Receives changes to whether downloads are paused.
+    pause_rx: Receiver<bool>,
+    /// Caps the throughput of all downloads, if a rate limit was set.
+    rate_limiter: Option<RateLimiter>,
+    /// Notified of downloads and finished runs, if a webhook was set.
+    webhook: Option<Webhook>,
+    /// Sent a summary of each run, if email was configured.
+    email: Option<Email>,
+    /// Counters of everything downloaded, for monitoring.
+    metrics: Arc<Metrics>,
+    /// Set until the first pass, if it should resume an interrupted run.
+    resume: AtomicBool,
+    /// When to stop starting downloads, if `max_duration` was set.
+    deadline: Option<std::time::Instant>,
+    /// Index of the firmware in the download directory.
+    index: Index,
+    /// Held while rewriting a `SHA1SUMS` file, as devices can share a directory.
+    sums_lock: Mutex<()>,
+    /// Lock on the download directory, released when dropped.
+    _lock: DirLock,
+}
+
+/// State of a single download pass over every device.
+struct Pass {
+    /// Time the pass started.
     start_time: DateTime<chrono::offset::Local>,
-    /// Devices processed thus far.
-    total_done: u32,
-    /// Devices to be processed.
-    total_todo: u32,
-    /// Async channel that receives `true` when ctrlc is passed.
-    ctrlc_received: Receiver<bool>,
-    /// `true` if program should abort when the next download starts.
-    /// Currently only used for the ctlc handle, but could also be used to make an error fatal.
-    kill_program: bool,
+    /// Devices processed thus far. Shared between all download tasks.
+    total_done: AtomicU32,
+    /// Devices to be processed. Shrinks once devices without firmware are left out.
+    total_todo: AtomicU32,
+    /// Draws every progress bar, so concurrent downloads don't draw over each other.
+    progress: Arc<MultiProgress>,
+    /// Progress bar tracking the amount of devices processed.
+    devices_bar: ProgressBar,
+    /// Progress bar tracking bytes downloaded, out of the size of every firmware left to download.
+    bytes_bar: ProgressBar,
+    /// Firmware downloaded thus far.
+    total_downloaded: AtomicU32,
+    /// Firmware or devices skipped thus far.
+    total_skipped: AtomicU32,
+    /// Firmware or devices that failed thus far.
+    total_failed: AtomicU32,
+    /// Result of every firmware or device processed thus far, for the summary email.
+    results: std::sync::Mutex<Vec<DownloadResult>>,
+    /// Devices finished thus far, so the pass can be resumed if the process dies.
+    journal: Journal,
+    /// Devices that failed thus far, saved at the end of the pass so they can be retried.
+    failures: std::sync::Mutex<Vec<FailedDevice>>,
+    /// Identifier and name of each device left for the next run, as the deadline passed or the size limit was reached
+    /// before it was finished.
+    deferred: std::sync::Mutex<Vec<(String, String)>>,
+    /// Bytes of the firmware being or already downloaded, counted against `max_total_size`.
+    planned_bytes: AtomicU64,
+}
+
+/// Bytes of a download counted against `max_total_size`. Given back when dropped, unless the download is kept.
+struct PlannedBytes<'a> {
+    planned: &'a AtomicU64,
+    size: u64,
+}
+
+impl PlannedBytes<'_> {
+    /// Keeps counting the bytes for the rest of the pass, as the download finished.
+    fn keep(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for PlannedBytes<'_> {
+    fn drop(&mut self) {
+        self.planned.fetch_sub(self.size, Ordering::SeqCst);
+    }
+}
+
+impl Pass {
+    /// Starts a pass over `total_todo` devices, recorded in `journal`. Bars are hidden if `hide_bars` is set.
+    fn new(total_todo: u32, hide_bars: bool, journal: Journal) -> Self {
+        let progress = Arc::new(MultiProgress::new());
+        if hide_bars {
+            progress.set_draw_target(ProgressDrawTarget::hidden());
+        }
+
+        let devices_bar = progress.add(ProgressBar::new(total_todo as u64));
+        devices_bar.set_style(
+            ProgressStyle::default_bar()
+                .template("Devices [{bar:40.cyan/blue}] {pos}/{len}")
+                .progress_chars("#>-"),
+        );
+
+        //Length is set once listings are fetched, and the size of the pass is known
+        let bytes_bar = progress.add(ProgressBar::new(0));
+        bytes_bar.set_style(
+            ProgressStyle::default_bar()
+                .template("Total   [{bar:40.green/blue}] {bytes}/{total_bytes} ({eta})")
+                .progress_chars("#>-"),
+        );
+
+        Pass {
+            start_time: Local::now(),
+            total_done: AtomicU32::new(0),
+            total_todo: AtomicU32::new(total_todo),
+            progress,
+            devices_bar,
+            bytes_bar,
+            total_downloaded: AtomicU32::new(0),
+            total_skipped: AtomicU32::new(0),
+            total_failed: AtomicU32::new(0),
+            results: std::sync::Mutex::new(Vec::new()),
+            journal,
+            failures: std::sync::Mutex::new(Vec::new()),
+            deferred: std::sync::Mutex::new(Vec::new()),
+            planned_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Records that a device was left for the next run.
+    fn defer(&self, identifier: &str, device: &str) {
+        let mut deferred = self.deferred.lock().expect("deferred lock poisoned");
+        if !deferred.iter().any(|(deferred, _)| deferred == identifier) {
+            deferred.push((identifier.to_string(), device.to_string()));
+        }
+    }
+
+    /// Counts `size` bytes against the `max` the pass may download, until the returned reservation is dropped.
+    ///
+    /// # Returns
+    /// `None` if that would go over `max`, in which case nothing is counted.
+    fn plan_bytes(&self, size: u64, max: u64) -> Option<PlannedBytes<'_>> {
+        self.planned_bytes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |planned| {
+                Some(planned + size).filter(|planned| *planned <= max)
+            })
+            .ok()
+            .map(|_| PlannedBytes {
+                planned: &self.planned_bytes,
+                size,
+            })
+    }
+
+    /// True if the device with `identifier` was left for the next run.
+    fn is_deferred(&self, identifier: &str) -> bool {
+        let deferred = self.deferred.lock().expect("deferred lock poisoned");
+        deferred.iter().any(|(deferred, _)| deferred == identifier)
+    }
+
+    /// Records that a device failed. Only the first failure of each device is kept.
+    fn record_failure(&self, identifier: &str, device: &str, err: &Error) {
+        let mut failures = self.failures.lock().expect("failures lock poisoned");
+        if failures.iter().any(|failure| failure.identifier == identifier) {
+            return;
+        }
+
+        failures.push(FailedDevice {
+            identifier: identifier.to_string(),
+            device: device.to_string(),
+            error_kind: err.kind().to_string(),
+            error: err.to_string(),
+        });
+    }
 }
 
-/// True if there is a downloader instance currently alive in any scope.
-static mut DOWNLOADER_CREATED: bool = false;
+/// Why streaming an ipsw into its part file stopped early.
+enum StreamError {
+    /// Errored on Apples API, either when starting the download or mid stream. Worth retrying.
+    Network(Error),
+    /// Failed to write to the part file.
+    Io(std::io::Error),
+    /// The downloader was cancelled mid download.
+    Killed,
+}
+
+/// Bytes buffered before writing to disk. Large writes keep the blocking pool from being hit for every chunk.
+const WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Progress bar template used for each download if none is configured.
+pub const DEFAULT_PROGRESS_TEMPLATE: &str =
+    "{spinner:.green} {prefix} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} ({eta})";
+
+/// Percent of a download between progress lines, when printing progress lines instead of bars.
+const PROGRESS_LOG_PERCENT: u64 = 10;
+
+/// Smallest byte range of a segmented download, so small files aren't split into many tiny requests.
+const MIN_SEGMENT_SIZE: u64 = 8 * 1024 * 1024;
+
+/// When the last progress line of a segmented download was printed and at what percent, and the percent of the last
+/// progress event. Shared between its segments.
+struct ProgressMarks {
+    last_log: (std::time::Instant, u64),
+    last_event_percent: u64,
+}
+
+/// Firmware listings fetched from the API at once.
+const LISTING_FETCHES: usize = 8;
+
+/// Times a device waits out rate limiting before giving up. These don't count against the retries.
+const RATE_LIMIT_WAITS: u32 = 10;
+
+/// Longest a Retry-After header may make us wait, so a bogus value can't stall the run for days.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// Directory under the download directory that downloads which aren't valid ipsw files are moved to.
+pub const QUARANTINE_DIR: &str = ".quarantine";
 
 impl Downloader {
-    /// Creates a new downloader.
+    /// Creates a new downloader, which can be cancelled through [`cancellation_token`](Downloader::cancellation_token).
     ///
-    /// # panics
+    /// # Returns
+    /// * Err(Locked) - Another process is downloading into the download directory.
+    /// * Err(Http) - The webhook client failed to initialize.
+    /// * Err(Email) - The SMTP settings aren't valid.
+    /// * Err(Io | Index) - The index in the download directory could not be opened.
+    pub fn new(client: Client, devices: Vec<Device>, opt: DownloadOptions) -> Result<Self> {
+        Self::with_cancellation(client, devices, opt, CancellationToken::new())
+    }
+
+    /// Creates a new downloader that stops once `cancel` is cancelled, ex. by a ctrl-c handler.
     ///
-    /// This panics if more than one downloader is alive at the same time, due to multi binding ctrl-c handlers.
-    pub fn new(client: Client, devices: Vec<Device>, opt: CliOpts) -> Self {
-        //Ensure downloader is singleton
-        unsafe {
-            if DOWNLOADER_CREATED {
-                panic!("Created two Downloader instances! This would cause an error when binding ctrlc. Please use a lazy_static instead.");
-            }
-            DOWNLOADER_CREATED = true;
-        }
-
-        //bind ctrlc to a channel
-        let (ctrlc_tx, ctrlc_rx) = watch::channel(false);
-        ctrlc::set_handler(move || {
-            println!("{}", "ctrlc received, exiting...".on_bright_red());
-            error!("Killed by ctrlc");
-            ctrlc_tx.send(true).unwrap();
-        })
-        .expect("Failed to make the ctrlc handle");
+    /// # Returns
+    /// See [`new`](Downloader::new).
+    pub fn with_cancellation(client: Client, devices: Vec<Device>, opt: DownloadOptions, cancel: CancellationToken) -> Result<Self> {
+        let lock = DirLock::acquire(&opt.download_path)?;
+
+        let webhook = match &opt.webhook {
+            Some(url) => Some(Webhook::new(url.clone(), opt.webhook_format)?),
+            None => None,
+        };
+        let email = opt.email.as_ref().map(Email::new).transpose()?;
+
+        let index = Index::open(&opt.download_path)?;
+        let (pause_tx, pause_rx) = watch::channel(false);
 
-        Downloader {
+        //If filters are set, only download devices that match them
+        debug!("using filter: {:?}", opt.filter);
+        let devices: Vec<Device> = devices
+            .into_iter()
+            .filter(|d| opt.filter.matches(d))
+            .collect();
+
+        Ok(Downloader {
             client,
-            total_todo: devices.len() as u32,
             devices,
+            cancel,
+            pause_tx,
+            pause_rx,
+            rate_limiter: opt.limit_rate.map(RateLimiter::new),
+            webhook,
+            email,
+            metrics: Arc::new(Metrics::new()),
+            resume: AtomicBool::new(opt.resume_run),
+            deadline: opt.max_duration.map(|max_duration| std::time::Instant::now() + max_duration),
+            index,
+            sums_lock: Mutex::new(()),
+            _lock: lock,
             opt,
-            start_time: Local::now(),
-            total_done: 0,
-            ctrlc_received: ctrlc_rx,
-            kill_program: false,
-        }
+        })
+    }
+
+    /// Gets a token cancelling this downloader, shared with the one it was created with.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Gets the metrics of this downloader, which are updated while it runs.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
     }
 
     /// Begins to download ipsw files using the configured Downloader.
-    pub async fn begin(mut self) {
-        //If filter is set
-        if let Some(filter) = self.opt.filter_term.take() {
-            debug!("using filter: {}", filter);
+    ///
+    /// Up to `jobs` devices are downloaded at once, each in its own tokio task.
+    pub async fn begin(self) {
+        let this = Arc::new(self);
+        let mut background = this.spawn_pause_listeners();
+        background.extend(this.spawn_metrics_tasks());
 
-            //Update total with filter
-            {
-                let filtered_total_devices = self
-                    .devices
-                    .iter()
-                    .filter(|d| d.name.contains(&filter))
-                    .count();
-                self.total_todo = filtered_total_devices as u32;
+        this.run_pass().await;
+
+        background.iter().for_each(JoinHandle::abort);
+    }
+
+    /// Downloads ipsw files like [`begin`](Self::begin), then repeats every `interval` until cancelled.
+    ///
+    /// The device list is reused between passes, while firmware listings are fetched again to find new releases.
+    pub async fn watch(self, interval: Duration) {
+        let this = Arc::new(self);
+        let mut background = this.spawn_pause_listeners();
+        background.extend(this.spawn_metrics_tasks());
+
+        this.watch_passes(interval).await;
+
+        background.iter().for_each(JoinHandle::abort);
+    }
+
+    /// Runs a pass every `interval` until cancelled.
+    async fn watch_passes(self: &Arc<Self>, interval: Duration) {
+        loop {
+            self.run_pass().await;
+
+            //Return early if told to die, or out of time
+            if self.cancel.is_cancelled() || self.past_deadline() {
+                return;
             }
 
-            //Download each device that matches filter
-            for device in std::mem::take(&mut self.devices)
-                .into_iter()
-                .filter(|d| d.name.contains(&filter))
-            {
+            if let Ok(interval) = chrono::Duration::from_std(interval) {
+                let next_pass = Local::now() + interval;
+                self.print(format!("Next pass at {}.", next_pass.format("%Y-%m-%d %H:%M")));
+                info!("Next pass at {}", next_pass);
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = self.cancel.cancelled() => {
+                    return;
+                }
+            }
+        }
+    }
 
-                let fw = self.client.get_device_firmware(&device).await;
+    /// Spawns tasks serving metrics, or periodically writing them to the metrics file, if either was set.
+    fn spawn_metrics_tasks(self: &Arc<Self>) -> Vec<JoinHandle<()>> {
+        let mut tasks = Vec::new();
 
-                match fw {
-                    Ok(fw) => self.download_firmware(fw).await,
-                    Err(why) => Self::report_err(why, &device.name),
+        if let Some(addr) = self.opt.metrics_addr {
+            let this = self.clone();
+            tasks.push(tokio::spawn(async move {
+                if let Err(why) = serve_metrics(this.metrics.clone(), addr, this.cancel.clone()).await {
+                    error!("serving metrics failed: {}", why);
                 }
+            }));
+        }
 
-                //Return early if told to die
-                if self.kill_program {
-                    return;
+        if self.opt.metrics_file.is_some() {
+            let this = self.clone();
+            tasks.push(tokio::spawn(async move {
+                loop {
+                    this.write_metrics_file();
+                    tokio::time::sleep(METRICS_FILE_INTERVAL).await;
+                }
+            }));
+        }
+
+        tasks
+    }
+
+    /// Writes the metrics file, if one was set. Failures are only logged, as monitoring shouldn't stop downloads.
+    fn write_metrics_file(&self) {
+        if let Some(path) = &self.opt.metrics_file {
+            if let Err(why) = self.metrics.write_file(path) {
+                error!("{}", why);
+            }
+        }
+    }
+
+    /// Spawns tasks toggling whether downloads are paused when `p` is entered, or SIGUSR1 is received.
+    fn spawn_pause_listeners(self: &Arc<Self>) -> Vec<JoinHandle<()>> {
+        let mut listeners = Vec::new();
+
+        //Only listen to people, as piped input isn't meant for us
+        if atty::is(atty::Stream::Stdin) {
+            let this = self.clone();
+            listeners.push(tokio::spawn(async move {
+                let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line.trim().eq_ignore_ascii_case("p") {
+                        this.toggle_pause();
+                    }
                 }
+            }));
+        }
 
-                self.after_fw_download(&device);
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            match signal(SignalKind::user_defined1()) {
+                Ok(mut sigusr1) => {
+                    let this = self.clone();
+                    listeners.push(tokio::spawn(async move {
+                        while sigusr1.recv().await.is_some() {
+                            this.toggle_pause();
+                        }
+                    }));
+                }
+                Err(why) => error!("failed to listen for SIGUSR1: {}", why),
             }
+        }
+
+        listeners
+    }
+
+    /// Pauses downloads if running, or resumes them if paused.
+    fn toggle_pause(&self) {
+        let paused = !*self.pause_rx.borrow();
+        //Only fails if every receiver is gone, but we hold one
+        let _ = self.pause_tx.send(paused);
+
+        if paused {
+            self.print("Paused downloading, enter p to resume...".yellow());
+            info!("Paused downloading");
         } else {
-            //Download all
-            for device in std::mem::take(&mut self.devices) {
+            self.print("Resumed downloading".yellow());
+            info!("Resumed downloading");
+        }
+    }
 
-                let fw = self.client.get_device_firmware(&device).await;
+    /// True once `max_duration` has passed, so no more downloads should start.
+    fn past_deadline(&self) -> bool {
+        self.deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline)
+    }
+
+    /// Waits until downloads are no longer paused.
+    ///
+    /// # Returns
+    /// `false` if cancelled while waiting.
+    async fn wait_while_paused(&self) -> bool {
+        let mut paused = self.pause_rx.clone();
 
-                match fw {
-                    Ok(fw) => self.download_firmware(fw).await,
-                    Err(why) => Self::report_err(why, &device.name),
+        while *paused.borrow() {
+            tokio::select! {
+                _ = paused.changed() => {}
+                _ = self.cancel.cancelled() => {
+                    return false;
                 }
+            }
+        }
 
-                //Return early if told to die
-                if self.kill_program {
+        true
+    }
+
+    /// Downloads ipsw files for every device once.
+    async fn run_pass(self: &Arc<Self>) {
+        //Betas come from their own source, which is fetched once for every device
+        let betas = match self.opt.channel {
+            Channel::Release => None,
+            Channel::Beta => match self.client.get_beta_builds(&self.opt.beta_source).await {
+                Ok(betas) => Some(betas),
+                Err(why) => {
+                    error!("Getting beta builds errored: {}", why);
                     return;
                 }
+            },
+        };
+
+        //Devices an interrupted run finished aren't queried again
+        let journal = self.open_journal();
+        let devices: Vec<Device> = self.devices.iter().filter(|d| !journal.is_done(&d.identifier)).cloned().collect();
+
+        let hide_bars = self.opt.quiet
+            || self.opt.progress_log_interval.is_some()
+            || self.opt.progress_format == ProgressFormat::Json;
+        let pass = Arc::new(Pass::new(devices.len() as u32, hide_bars, journal));
+
+        let jobs = self.opt.jobs.max(1);
+        debug!("downloading with {} jobs", jobs);
 
-                self.after_fw_download(&device);
+        //MultiProgress only draws while joined, so this needs its own thread
+        let draw_thread = {
+            let progress = pass.progress.clone();
+            tokio::task::spawn_blocking(move || progress.join())
+        };
+
+        //Fetch every listing up front, as one request at a time is slow with many devices
+        let mut listings: Vec<(usize, Device, Result<FirmwareListing>)> =
+            stream::iter(devices.into_iter().enumerate())
+            .map(|(i, device)| async move {
+                let listing = self.fetch_listing(&device).await;
+                (i, device, listing)
+            })
+            .buffer_unordered(LISTING_FETCHES)
+            .collect()
+            .await;
+        //Keep downloads in device order, regardless of which listing came back first
+        listings.sort_by_key(|(i, _, _)| *i);
+        let mut listings = self.handle_ota_only(&pass, listings).await;
+        if let Some(betas) = &betas {
+            self.use_beta_firmware(&mut listings, betas);
+        }
+        self.order_listings(&mut listings);
+
+        let mut total_bytes = 0;
+        for fw in listings.iter().filter_map(|(_, _, listing)| listing.as_ref().ok()) {
+            for firmware in self.selected_firmware(fw) {
+                if self.downloaded_path(firmware, &self.firmware_path(fw, firmware)).is_none() {
+                    total_bytes += firmware.filesize;
+                }
             }
         }
+        pass.bytes_bar.set_length(total_bytes);
 
-        println!(
-            "Finished in {} minutes.",
-            (Local::now() - self.start_time).num_minutes()
-        );
-        info!(
-            "Finished in {} minutes.",
-            (Local::now() - self.start_time).num_minutes()
-        )
-    }
+        //Limits the amount of devices being downloaded at once
+        let job_slots = Arc::new(Semaphore::new(jobs));
+        let mut tasks = Vec::with_capacity(listings.len());
 
-    /// Downloads the newest firmware contained in the passed firmware listing.
-    ///
-    /// details
-    /// -------
-    ///
-    /// The download will begin in an OS temp file, and then copied to the final directory indicated by the CLI options.
-    /// All errors occurred in the download process will be handled by it. Should the ctrl-c signal be received,
-    /// the function will abort unless copying the temp file to the final destination, ensuring only valid files are
-    /// left in the destination folder.
-    async fn download_firmware(&mut self, fw: FirmwareListing) {
-        if fw.firmwares.is_empty() {
-            println!(
-                "{}",
-                format!("{} has no firmware for download", fw.name).cyan()
-            );
-            info!("{} has no firmware for download", fw.name);
-            return;
+        for (_, device, listing) in listings {
+            let permit = job_slots
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("job semaphore closed");
+
+            //Stop handing out work if told to die
+            if self.cancel.is_cancelled() {
+                break;
+            }
+
+            //Out of time, so the rest is left to the next run
+            if self.past_deadline() {
+                pass.defer(&device.identifier, &device.name);
+                continue;
+            }
+
+            let this = self.clone();
+            let pass = pass.clone();
+            tasks.push(tokio::spawn(async move {
+                pass.journal.set(&device.identifier, DeviceState::InProgress);
+                this.download_device(&pass, &device, listing).await;
+                //Interrupted and deferred devices are resumed
+                if !this.cancel.is_cancelled() && !pass.is_deferred(&device.identifier) {
+                    pass.journal.set(&device.identifier, DeviceState::Done);
+                }
+                drop(permit);
+            }));
         }
 
-        //Path to file were fw will be
-        let mut file_path = self
-            .opt
-            .download_path
-            .join(fw.name.clone());
-            file_path.push(format!("{}.ipsw",fw.firmwares[0].version.clone()));//Needed to ensure all numbers in version are used in path
-        
-        debug!("Using path {:?}", file_path);
+        for task in tasks {
+            if let Err(why) = task.await {
+                error!("download task failed to complete: {}", why);
+            }
+        }
 
-        //Skip download if file is already downloaded
-        if file_path.exists() {
-            println!(
-                "{}",
-                format!("{} is already downloaded, skipping", fw.name).dimmed()
-            );
-            info!("{} is already downloaded", fw.name);
+        pass.devices_bar.finish();
+        pass.bytes_bar.finish();
+        if let Ok(Err(why)) = draw_thread.await {
+            debug!("progress bars failed to draw: {}", why);
+        }
+
+        //Return early if told to die, leaving the journal to resume from
+        if self.cancel.is_cancelled() {
             return;
         }
 
-        //Delete old files if enabled
-        if self.opt.delete_old_fw {
-            if let Ok(dir) = read_dir(file_path.parent().unwrap()) {
-                dir.filter_map(|e| e.ok())
-                    .for_each(|e| match remove_file(e.path()) {
-                        Ok(_) => {
-                            println!(
-                                "deleted old file {}",
-                                e.file_name().to_str().unwrap().purple().dimmed()
-                            );
-                            info!("deleted old file {}", e.file_name().to_str().unwrap());
-                        }
-                        Err(why) => {
-                            println!(
-                                "{}",
-                                format!(
-                                    "failed to delete old file {}",
-                                    e.file_name().to_str().unwrap()
-                                )
-                                .red()
-                            );
-                            error!(
-                                "failed to delete old file {} because: {}",
-                                e.file_name().to_str().unwrap(),
-                                why
-                            );
-                        }
-                    });
+        //Devices left for the next run are resumed from the journal
+        let deferred: Vec<String> = pass
+            .deferred
+            .lock()
+            .expect("deferred lock poisoned")
+            .iter()
+            .map(|(_, device)| device.clone())
+            .collect();
+        if deferred.is_empty() {
+            pass.journal.finish();
+        } else {
+            self.print(
+                format!(
+                    "Out of time or space, {} devices are left for the next run with --resume-run: {}",
+                    deferred.len(),
+                    deferred.join(", ")
+                )
+                .yellow(),
+            );
+            warn!("left {} devices for the next run", deferred.len());
+        }
+        let failures = pass.failures.lock().expect("failures lock poisoned").clone();
+        if let Err(why) = save_failures(&self.opt.download_path, &failures) {
+            error!("{}", why);
+        }
+        self.metrics.pass_completed();
+        self.write_metrics_file();
+
+        let minutes = (Local::now() - pass.start_time).num_minutes();
+        self.print(format!("Finished in {} minutes.", minutes));
+        info!("Finished in {} minutes.", minutes);
+
+        let summary = Summary {
+            downloaded: pass.total_downloaded.load(Ordering::SeqCst),
+            skipped: pass.total_skipped.load(Ordering::SeqCst),
+            failed: pass.total_failed.load(Ordering::SeqCst),
+            minutes,
+            deferred,
+        };
+
+        if self.opt.progress_format == ProgressFormat::Json {
+            print_json(&ProgressEvent::RunCompleted(&summary));
+        } else if self.opt.output == OutputFormat::Json {
+            print_json(&summary);
+        }
+        if let Some(webhook) = &self.webhook {
+            webhook.notify(&WebhookEvent::RunCompleted(&summary)).await;
+        }
+        if self.opt.notify {
+            notify_desktop(&WebhookEvent::RunCompleted(&summary)).await;
+        }
+        if let Some(email) = &self.email {
+            //Taken out of the lock, as it can't be held across sending
+            let results = std::mem::take(&mut *pass.results.lock().expect("results lock poisoned"));
+            email.send_summary(&summary, &results).await;
+        }
+    }
+
+    /// Opens the journal of a pass. On the first pass, the journal of an interrupted run is resumed if asked to.
+    fn open_journal(&self) -> Journal {
+        if self.resume.swap(false, Ordering::SeqCst) {
+            match Journal::resume(&self.opt.download_path) {
+                Ok(Some(journal)) => {
+                    let started = journal.started().with_timezone(&Local).format("%Y-%m-%d %H:%M");
+                    self.print(format!("Resuming the run started at {}.", started));
+                    info!("resuming the run started at {}", started);
+                    return journal;
+                }
+                Ok(None) => info!("no interrupted run to resume, starting a new one"),
+                Err(why) => error!("could not resume the interrupted run: {}", why),
             }
         }
 
-        println!("{}",
-            format!("Beginning to download {} {}...", fw.name, fw.firmwares[0].version).bold()
-        );
-        info!("downloading {} {}", fw.name, fw.firmwares[0].version);
+        Journal::start(&self.opt.download_path, self.devices.iter().map(|d| d.identifier.as_str()))
+    }
+
+    /// Handles devices without ipsw files, such as the Apple Watch, which only update over the air.
+    ///
+    /// With `ota_fallback` their listings are replaced by their OTA updates. Otherwise, they are left out of the pass.
+    async fn handle_ota_only(
+        &self,
+        pass: &Pass,
+        listings: Vec<(usize, Device, Result<FirmwareListing>)>,
+    ) -> Vec<(usize, Device, Result<FirmwareListing>)> {
+        let mut kept = Vec::with_capacity(listings.len());
+        let mut ota_only = Vec::new();
 
-        //Create streams
+        for (i, device, listing) in listings {
+            match listing {
+                Ok(fw) if fw.firmwares.is_empty() => {
+                    if self.opt.ota_fallback {
+                        debug!("{} has no ipsw files, using its OTA updates", device.name);
+                        let listing = self.client.get_device_ota(&device).await;
+                        kept.push((i, device, listing));
+                    } else {
+                        ota_only.push(device.name);
+                    }
+                }
+                listing => kept.push((i, device, listing)),
+            }
+        }
 
-        //Temp file to dl to first. This avoids leaving a bad file if program is killed
-        let temp_file_stream = tempfile::NamedTempFile::new().unwrap();
-        //Copy file handle for reading later
-        let temp_file_read = temp_file_stream.reopen().unwrap();
-        let mut temp_file_stream = std::io::BufWriter::new(temp_file_stream);
+        if !ota_only.is_empty() {
+            pass.total_todo.fetch_sub(ota_only.len() as u32, Ordering::SeqCst);
+            pass.devices_bar.set_length(kept.len() as u64);
 
-        //Get the stream to download
-        let dl_stream = self.client.download_ipsw(&fw.firmwares[0]).await;
-        if dl_stream.is_err() {
-            println!(
-                "{}",
+            self.print(
                 format!(
-                    "Downloading {} {} errored on Apples API. Skipping download...",
-                    fw.name, fw.firmwares[0].identifier
+                    "Leaving out {} devices without ipsw files, which only update over the air. \
+                    Pass --ota-fallback to download their OTA updates.",
+                    ota_only.len()
                 )
-                .red()
+                .dimmed()
             );
-            error!(
-                "Downloading {} {} errored on Apples API",
-                fw.name, fw.firmwares[0].identifier
-            );
-            return;
+            info!("left out OTA only devices: {}", ota_only.join(", "));
         }
-        let (mut dl_stream, dl_size) = dl_stream.unwrap();
 
-        //Set up progress bar
-        let download_progress_bar = indicatif::ProgressBar::new(dl_size);
-        download_progress_bar.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .progress_chars("#>-"));
+        kept
+    }
 
-        use futures::stream::StreamExt; // for `next`
+    /// Sorts devices into the configured download order. Sorting is stable, so devices that tie keep the order of the
+    /// API. Devices whose listing failed have nothing to download, and no release.
+    fn order_listings(&self, listings: &mut [(usize, Device, Result<FirmwareListing>)]) {
+        match self.opt.order {
+            DownloadOrder::Api => {}
+            DownloadOrder::SmallestFirst => listings.sort_by_cached_key(|(_, _, listing)| {
+                listing.as_ref().map_or(0, |fw| {
+                    self.selected_firmware(fw)
+                        .into_iter()
+                        .filter(|firmware| self.downloaded_path(firmware, &self.firmware_path(fw, firmware)).is_none())
+                        .map(|firmware| firmware.filesize)
+                        .sum::<u64>()
+                })
+            }),
+            DownloadOrder::Alphabetical => listings.sort_by_cached_key(|(_, device, _)| device.name.to_lowercase()),
+            DownloadOrder::NewestReleaseFirst => listings.sort_by_cached_key(|(_, _, listing)| {
+                let newest = listing.as_ref().ok().and_then(|fw| {
+                    self.selected_firmware(fw).into_iter().map(|firmware| firmware.uploaddate).max()
+                });
+                std::cmp::Reverse(newest)
+            }),
+        }
+    }
 
-        //Actually download file
-        loop {
-            //Select over the download and being interrupted
-            tokio::select! {
-                //Packets for file
-                byte = dl_stream.next() => {
-                    if let Some(byte) = byte {
-                        //Catch errors
-                        match byte {
-                            Ok(_) => {}
-                            Err(_) => {
-                                println!("{}",
-                                    format!("Error writing file: {} skipping download...", file_path.to_str().unwrap()).red()
-                                );
-                                error!("Error writing file: {}", file_path.to_str().unwrap());
-                            }
-                        }
+    /// Replaces the firmware of every listing with its betas.
+    fn use_beta_firmware(&self, listings: &mut [(usize, Device, Result<FirmwareListing>)], betas: &[BetaBuild]) {
+        for listing in listings.iter_mut().filter_map(|(_, _, listing)| listing.as_mut().ok()) {
+            listing.firmwares = beta_firmware(betas, &listing.identifier);
+        }
+    }
 
-                        //Update dl bar
-                        let byte = byte.unwrap();
-                        download_progress_bar.inc(byte.len() as u64);
-
-                        match temp_file_stream.write_all(byte.as_ref()) {
-                            Ok(_) => {}
-                            Err(_) => {
-                                println!("{}",
-                                    format!("Could not create file: {} skipping download...",file_path.to_str().unwrap()).red()
-                                );
-                                error!("Error writing file: {}", file_path.to_str().unwrap());
-                            }
-                        }
-                    } else { //Stream done
-
-                        //Create final file now
-                        let dir_creation_result = create_dir_all(file_path.parent().unwrap());
-                        let file_stream = File::create(&file_path);
-
-                        if file_stream.is_err() || dir_creation_result.is_err() {
-                            println!("{}",
-                                format!("Could not create file: {} skipping download...",file_path.to_str().unwrap()).red()
-                            );
-                            error!("Could not create file: {}", file_path.to_str().unwrap());
-                            return;
-                        }
-                        //The file stream to the final file
-                        let file_stream = file_stream.unwrap();
-                        let mut end_file_stream = std::io::BufWriter::new(file_stream);
-
-                        //Copy the downloaded file to the final path now that the dl is done.
-                        debug!("Copying from temp file to end file");
-                        match std::io::copy(&mut std::io::BufReader::new(temp_file_read), &mut end_file_stream) {
-                            Err(why) => {
-                                println!("{}",
-                                    format!("Could not create file: {} skipping download... {}",file_path.to_str().unwrap(),why).red()
-                                );
-                                error!("Could not copy temp to file: {} err: {}", file_path.to_str().unwrap(), why);
-                                return;
-                            },
-                            Ok(bytes) if bytes == 0 => log::warn!("Didn't copy any bytes to final file!"),
-                            Ok(bytes) => debug!("Copied {} bytes to final file", bytes)
-                        }
+    /// Fetches the firmware listing for a device, retrying if Apples API fails us.
+    ///
+    /// If cancelled while waiting to retry, the last error is returned.
+    async fn fetch_listing(&self, device: &Device) -> Result<FirmwareListing> {
+        let mut attempt = 0;
+        let mut rate_limited = 0;
+        loop {
+            match self.client.get_device_firmware(device).await {
+                Err(why @ Error::RateLimited { .. })
+                    if rate_limited < RATE_LIMIT_WAITS && !self.cancel.is_cancelled() =>
+                {
+                    rate_limited += 1;
 
-                        break;
+                    if !self.wait_out_rate_limit(&why, &device.name, rate_limited).await {
+                        return Err(why);
                     }
                 }
+                Err(why) if why.is_transient() && attempt < self.opt.retries && !self.cancel.is_cancelled() => {
+                    attempt += 1;
+                    self.report_retry(&why, &device.name, attempt);
 
-                //break if ctrl-c passed
-                _ = self.ctrlc_received.changed() => {
-                    self.kill_program = true;
-                    break;
+                    if !self.backoff(attempt, why.retry_after()).await {
+                        return Err(why);
+                    }
                 }
+                fw => return fw,
             }
         }
     }
 
-    /// Reports a device firmware download error.
-    fn report_err(err: impl Error, device: &str) {
-        error!("Getting device firmware errored: {}", err);
-
-        println!(
-            "{}",
-            format!(
-                "Process errored when downloading firmware for {}. Description: {}",
-                device, err
-            )
-            .red()
-        )
+    /// Downloads the firmware of a device selected from its listing.
+    #[instrument(name = "device", skip_all, fields(device = %device.name))]
+    async fn download_device(&self, pass: &Pass, device: &Device, fw: Result<FirmwareListing>) {
+        //Don't start new downloads if told to die
+        if self.cancel.is_cancelled() {
+            return;
+        }
+        self.emit(&ProgressEvent::DeviceStarted { device: &device.name });
+
+        match fw {
+            Ok(fw) => self.download_listing(pass, fw).await,
+            Err(why) => self.report_err(pass, why, device).await,
+        }
+
+        //Don't report progress if killed mid download, or if the device is left unfinished
+        if self.cancel.is_cancelled() || pass.is_deferred(&device.identifier) {
+            return;
+        }
+
+        self.after_fw_download(pass, device);
     }
 
-    /// Performs tasks after a failed or successful download. total done increment, progress bar ect.
-    fn after_fw_download(&mut self, device: &Device) {
-        self.total_done += 1;
+    /// Downloads the firmware selected by the options from the passed firmware listing.
+    ///
+    /// This is the newest firmware, unless a specific version, build or signing state was requested. In archive mode, this
+    /// is every firmware matching the requested version, build and signing state instead.
+    async fn download_listing(&self, pass: &Pass, fw: FirmwareListing) {
+        if fw.firmwares.is_empty() {
+            self.print(format!("{} has no firmware for download", fw.name).cyan());
+            info!("{} has no firmware for download", fw.name);
+            self.report(pass, DownloadResult::Skipped {
+                device: fw.name.clone(),
+                version: None,
+                path: None,
+                reason: "no firmware for download".to_string(),
+            }).await;
+            return;
+        }
 
-        let done_str = format!(
-            "{}{}/{}{}",
-            "(".bold().italic(),
-            self.total_done.to_string().cyan().italic(),
-            self.total_todo.to_string().cyan().italic(),
-            ")".bold().italic(),
-        );
+        let mut selected = self.selected_firmware(&fw);
+        if selected.is_empty() {
+            self.report_no_match(pass, &fw).await;
+            return;
+        }
+
+        //Devices only need firmware newer than what they already run
+        if let Some(installed) = self.opt.installed_versions.get(&fw.identifier) {
+            selected.retain(|f| compare_versions(&f.version, installed) == std::cmp::Ordering::Greater);
+
+            if selected.is_empty() {
+                self.print(format!("{} is up to date on {}", fw.name, installed).cyan());
+                info!("{} is up to date on {}", fw.name, installed);
+                self.report(pass, DownloadResult::Skipped {
+                    device: fw.name.clone(),
+                    version: Some(installed.clone()),
+                    path: None,
+                    reason: format!("installed version {} is up to date", installed),
+                }).await;
+                return;
+            }
+        }
+
+        //Archive every matching firmware
+        if self.opt.all_versions {
+            for firmware in selected {
+                //Return early if told to die
+                if self.cancel.is_cancelled() {
+                    return;
+                }
+
+                //Out of time, so the rest of the device is left to the next run
+                if self.past_deadline() {
+                    pass.defer(&fw.identifier, &fw.name);
+                    return;
+                }
+
+                let file_path = self.firmware_path(&fw, firmware);
+                self.download_firmware(pass, &fw.name, firmware, file_path.clone()).await;
+                self.save_blobs(&fw, firmware, &file_path).await;
+            }
+            return;
+        }
 
-        println!("Ended work on: {} {}", device.name, done_str);
+        let firmware = selected[0];
+        let file_path = self.firmware_path(&fw, firmware);
+        self.download_firmware(pass, &fw.name, firmware, file_path.clone()).await;
+        self.save_blobs(&fw, firmware, &file_path).await;
+
+        //Only prune once the new firmware is safely on disk
+        if let Some(keep) = self.opt.keep {
+            if self.existing_path(firmware, &file_path).is_some() {
+                self.prune_old_versions(&fw, keep, &file_path);
+            }
+        }
     }
-}
 
-impl Drop for Downloader {
-    fn drop(&mut self) {
-        //Reset instance count, as we no longer exist.
-        unsafe {
-            DOWNLOADER_CREATED = false;
+    /// Saves SHSH blobs of a downloaded firmware next to it, if enabled and Apple still signs it.
+    /// Failures are only reported, as the download is fine.
+    async fn save_blobs(&self, fw: &FirmwareListing, firmware: &Firmware, file_path: &Path) {
+        let blobs = match &self.opt.blobs {
+            Some(blobs) if firmware.signed && !self.cancel.is_cancelled() => blobs,
+            _ => return,
+        };
+        if self.downloaded_path(firmware, file_path).is_none() {
+            return;
         }
+
+        let dir = shsh::blobs_dir(file_path);
+        match shsh::save_blobs(blobs, &fw.boardconfig, firmware, &dir).await {
+            Ok(()) => {
+                self.print(format!("Saved SHSH blobs of {} {} to {}", fw.name, firmware.version, dir.display()).green());
+                info!("saved SHSH blobs of {} {} to {}", fw.name, firmware.buildid, dir.display());
+            }
+            Err(why) => {
+                error!("could not save SHSH blobs of {} {}: {}", fw.name, firmware.buildid, why);
+            }
+        }
+    }
+
+    /// Gets the firmware to download from a listing.
+    ///
+    /// This is the newest firmware matching the version, build id and signing options, or every match in archive mode.
+    fn selected_firmware<'a>(&self, fw: &'a FirmwareListing) -> Vec<&'a Firmware> {
+        let mut matches = fw.firmwares.iter().filter(|f| self.matches_selection(f));
+
+        if self.opt.all_versions {
+            matches.collect()
+        } else {
+            //Api orders firmware newest first, so the first match is the newest
+            matches.next().into_iter().collect()
+        }
+    }
+
+    /// Deletes every downloaded firmware of the device in `fw` except the `keep` newest, and `current`.
+    fn prune_old_versions(&self, fw: &FirmwareListing, keep: usize, current: &Path) {
+        let mut firmwares: Vec<&Firmware> = fw.firmwares.iter().collect();
+        firmwares.sort_by(|a, b| newest_first(a, b));
+
+        //Builds sharing a version may share a file, so only count each file once
+        let mut downloaded = Vec::new();
+        for firmware in firmwares {
+            if let Some(path) = self.existing_path(firmware, &self.firmware_path(fw, firmware)) {
+                if !downloaded.contains(&path) {
+                    downloaded.push(path);
+                }
+            }
+        }
+
+        for path in downloaded.iter().skip(keep).filter(|p| p.as_path() != current) {
+            self.delete_old_file(path);
+        }
+    }
+
+    /// Deletes every ipsw file next to `file_path`, other than `file_path` itself.
+    ///
+    /// If the path template puts several devices in one directory, ex. the flat layout, only files the index records
+    /// for the same device are deleted.
+    fn delete_old_firmware(&self, firmware: &Firmware, file_path: &Path) {
+        let dir = match file_path.parent() {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        if self.opt.path_template.has_device_dirs() {
+            if let Ok(entries) = read_dir(dir) {
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.path())
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "ipsw") && path != file_path)
+                    .for_each(|path| self.delete_old_file(&path));
+            }
+            return;
+        }
+
+        match self.index.entries() {
+            Ok(entries) => entries
+                .into_iter()
+                .filter(|entry| entry.identifier == firmware.identifier)
+                .map(|entry| self.opt.download_path.join(entry.path))
+                .filter(|path| path.parent() == Some(dir) && path != file_path && path.exists())
+                .for_each(|path| self.delete_old_file(&path)),
+            Err(why) => error!("failed to search index: {}", why),
+        }
+    }
+
+    /// Deletes a file replaced by a newer firmware.
+    fn delete_old_file(&self, path: &Path) {
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        match remove_file(path) {
+            Ok(_) => {
+                self.forget(path);
+                self.print(format!("deleted old file {}", file_name.purple().dimmed()));
+                info!("deleted old file {}", file_name);
+            }
+            Err(why) => {
+                error!("failed to delete old file {} because: {}", file_name, why);
+            }
+        }
+    }
+
+    /// True if the firmware matches the version, build id and signing options.
+    fn matches_selection(&self, firmware: &Firmware) -> bool {
+        //Pinned devices get exactly the pinned firmware
+        match self.opt.pins.get(&firmware.identifier) {
+            Some(Pin::Version(version)) => return &firmware.version == version,
+            Some(Pin::Build(buildid)) => return &firmware.buildid == buildid,
+            Some(Pin::Latest) | None => {}
+        }
+
+        self.opt.version.as_ref().is_none_or(|v| &firmware.version == v)
+            && self.opt.buildid.as_ref().is_none_or(|b| &firmware.buildid == b)
+            && self.opt.min_version.as_ref().is_none_or(|min| {
+                compare_versions(&firmware.version, min) != std::cmp::Ordering::Less
+            })
+            && self.opt.max_version.as_ref().is_none_or(|max| at_most(&firmware.version, max))
+            && self.opt.since.is_none_or(|since| firmware.uploaddate.date().naive_utc() >= since)
+            && (!self.opt.signed_only || firmware.signed)
+    }
+
+    /// Reports that a device has no firmware matching its pin, or the version, build id and signing options.
+    async fn report_no_match(&self, pass: &Pass, fw: &FirmwareListing) {
+        let (selection, version) = match self.opt.pins.get(&fw.identifier) {
+            Some(pin @ Pin::Version(version)) => (format!("pinned {}", pin), Some(version.clone())),
+            Some(pin @ Pin::Build(_)) => (format!("pinned {}", pin), None),
+            Some(Pin::Latest) | None => (self.selection_str(), self.opt.version.clone()),
+        };
+
+        error!("{} has no firmware matching {}", fw.name, selection);
+        self.report(pass, DownloadResult::Failed {
+            device: fw.name.clone(),
+            version,
+            error: format!("no firmware matching {}", selection),
+        }).await;
+    }
+
+    /// Gets the path a firmware from the listing will be downloaded to.
+    fn firmware_path(&self, fw: &FirmwareListing, firmware: &Firmware) -> PathBuf {
+        //Builds may share a version with another build, so keep them apart when it matters
+        let version_shared = fw.firmwares.iter().filter(|f| f.version == firmware.version).count() > 1;
+        let pinned_build = matches!(self.opt.pins.get(&firmware.identifier), Some(Pin::Build(_)));
+        let with_buildid = self.opt.buildid.is_some() || pinned_build || (self.opt.all_versions && version_shared);
+
+        //Betas go in their own directory, so they are never mistaken for releases
+        let download_path = if firmware.beta {
+            self.opt.download_path.join(BETA_DIR)
+        } else {
+            self.opt.download_path.clone()
+        };
+        let file_path = paths::firmware_path(&download_path, &self.opt.path_template, fw, firmware, with_buildid);
+
+        //OTA updates are zips, not ipsw files
+        if firmware.ota {
+            file_path.with_extension("zip")
+        } else {
+            file_path
+        }
+    }
+
+    /// Describes the requested firmware for messages, ex. "signed version 16.7.8 build 20H343".
+    fn selection_str(&self) -> String {
+        let mut parts = Vec::new();
+
+        if self.opt.signed_only {
+            parts.push("signed".to_string());
+        }
+        match &self.opt.version {
+            Some(version) => parts.push(format!("version {}", version)),
+            None => parts.push("any version".to_string()),
+        }
+        if let Some(min) = &self.opt.min_version {
+            parts.push(format!("from {}", min));
+        }
+        if let Some(max) = &self.opt.max_version {
+            parts.push(format!("up to {}", max));
+        }
+        if let Some(buildid) = &self.opt.buildid {
+            parts.push(format!("build {}", buildid));
+        }
+        if let Some(since) = &self.opt.since {
+            parts.push(format!("uploaded since {}", since));
+        }
+
+        parts.join(" ")
+    }
+
+    /// Downloads a single firmware for the device named `device_name` to `file_path`.
+    ///
+    /// details
+    /// -------
+    ///
+    /// The download will begin in a `.part` file next to `file_path`, and is then renamed to `file_path` once finished.
+    /// All errors occurred in the download process will be handled by it. Should the downloader be cancelled,
+    /// the function will abort and keep the part file to resume later, ensuring only valid files are left at `file_path`.
+    ///
+    /// Runs in a `download` span, which records the speed in bytes per second and outcome of the download once known.
+    #[instrument(
+        name = "download",
+        skip_all,
+        fields(
+            device = device_name,
+            version = %firmware.version,
+            buildid = %firmware.buildid,
+            size = firmware.filesize,
+            speed = Empty,
+            outcome = Empty,
+        )
+    )]
+    async fn download_firmware(&self, pass: &Pass, device_name: &str, firmware: &Firmware, file_path: PathBuf) {
+        debug!("Using path {:?}", file_path);
+
+        //Firmware already on the remote was downloaded and uploaded before
+        if let Some(remote) = self.opt.remote.as_ref().filter(|_| !self.opt.force) {
+            let path = self.remote_path(&file_path);
+
+            match remote.exists(&path).await {
+                Ok(false) => {}
+                Ok(true) => {
+                    self.print(format!("{} {} is already uploaded, skipping", device_name, firmware.version).dimmed());
+                    info!("{} {} is already at {}", device_name, firmware.version, remote.url(&path));
+                    self.report(pass, DownloadResult::Skipped {
+                        device: device_name.to_string(),
+                        version: Some(firmware.version.clone()),
+                        path: Some(PathBuf::from(remote.url(&path))),
+                        reason: "already uploaded".to_string(),
+                    }).await;
+                    return;
+                }
+                Err(why) => {
+                    self.report_failed_firmware(pass, device_name, firmware, why).await;
+                    return;
+                }
+            }
+        }
+
+        //Skip download if file is already downloaded, unless forced, truncated, or it's corrupt and being repaired
+        if let Some(existing_path) = self.existing_path(firmware, &file_path) {
+            if self.opt.force
+                || is_truncated(firmware, &existing_path)
+                || (self.opt.repair && self.is_corrupt(device_name, firmware, &existing_path).await)
+            {
+                self.forget(&existing_path);
+
+                if let Err(why) = remove_file(&existing_path) {
+                    let why = Error::io("could not delete existing file", &existing_path, why);
+                    self.report_failed_firmware(pass, device_name, firmware, why).await;
+                    return;
+                }
+            } else if let Some(remote) = &self.opt.remote {
+                //Downloaded before, but the upload failed
+                let size = metadata(&existing_path).map(|m| m.len()).unwrap_or_default();
+                self.upload(pass, remote, device_name, firmware, &existing_path, size).await;
+                return;
+            } else {
+                self.print(format!("{} {} is already downloaded, skipping", device_name, firmware.version).dimmed());
+                info!(
+                    device = device_name,
+                    version = firmware.version.as_str(),
+                    "{} {} is already downloaded", device_name, firmware.version
+                );
+                self.report(pass, DownloadResult::Skipped {
+                    device: device_name.to_string(),
+                    version: Some(firmware.version.clone()),
+                    path: Some(existing_path),
+                    reason: "already downloaded".to_string(),
+                }).await;
+                return;
+            }
+        } else if let Some(shared_path) = self.shared_path(firmware).filter(|_| !self.opt.force && self.opt.remote.is_none())
+        {
+            //Another firmware's file, so it's only reused and never deleted or replaced
+            self.print(
+                format!("{} {} is already downloaded as {}, skipping", device_name, firmware.version, shared_path.display())
+                    .dimmed(),
+            );
+            info!(
+                device = device_name,
+                version = firmware.version.as_str(),
+                "{} {} is already downloaded as {}", device_name, firmware.version, shared_path.display()
+            );
+            self.report(pass, DownloadResult::Skipped {
+                device: device_name.to_string(),
+                version: Some(firmware.version.clone()),
+                path: Some(shared_path),
+                reason: "already downloaded".to_string(),
+            }).await;
+            return;
+        }
+
+        //Over the size limit, so the device is left to the next run. Failed downloads give their bytes back
+        let mut planned = None;
+        if let Some(max_total_size) = self.opt.max_total_size {
+            planned = pass.plan_bytes(firmware.filesize, max_total_size);
+            if planned.is_none() {
+                self.print(
+                    format!(
+                        "{} {} ({}) would go over the maximum total size, leaving it for the next run",
+                        device_name,
+                        firmware.version,
+                        HumanBytes(firmware.filesize)
+                    )
+                    .yellow(),
+                );
+                info!("{} {} would go over the maximum total size", device_name, firmware.version);
+                pass.defer(&firmware.identifier, device_name);
+                return;
+            }
+        }
+
+        self.print(format!("Beginning to download {} {}...", device_name, firmware.version).bold());
+        info!(
+            device = device_name,
+            version = firmware.version.as_str(),
+            buildid = firmware.buildid.as_str(),
+            bytes = firmware.filesize,
+            "downloading {} {}", device_name, firmware.version
+        );
+        let started = std::time::Instant::now();
+        self.emit(&ProgressEvent::DownloadStarted {
+            device: device_name,
+            version: &firmware.version,
+            buildid: &firmware.buildid,
+            size: firmware.filesize,
+        });
+
+        //Deep paths with long device names can pass MAX_PATH on Windows
+        let long_file_path = paths::long_path(&file_path);
+
+        //Part file to dl to first. This avoids leaving a bad file if program is killed
+        let mut part_file = match PartFile::open(&long_file_path) {
+            Ok(part_file) => part_file,
+            Err(why) => {
+                self.report_failed_firmware(pass, device_name, firmware, why).await;
+                return;
+            }
+        };
+
+        //Download into the part file, retrying if Apples API fails us
+        let mut attempt = 0;
+        let mut rate_limited = 0;
+        let mut segmented = self.opt.segments > 1 && firmware.filesize > MIN_SEGMENT_SIZE;
+        let digests = loop {
+            let streamed = if segmented {
+                let part_path = part_file.path().to_path_buf();
+                self.segments_to_part(pass, firmware, device_name, part_file.as_file_mut(), &part_path).await
+            } else {
+                self.stream_to_part(pass, firmware, device_name, part_file.as_file_mut()).await
+            };
+
+            match streamed {
+                Ok(digests) => break digests,
+                Err(StreamError::Network(why @ Error::RangeNotSupported(_))) => {
+                    warn!("{}, downloading {} {} over one connection", why, device_name, firmware.version);
+                    segmented = false;
+                }
+                Err(StreamError::Killed) => {
+                    self.keep_part_file(device_name, firmware, part_file);
+                    return;
+                }
+                Err(StreamError::Network(why @ Error::RateLimited { .. })) if rate_limited < RATE_LIMIT_WAITS => {
+                    rate_limited += 1;
+
+                    if !self.wait_out_rate_limit(&why, device_name, rate_limited).await {
+                        self.keep_part_file(device_name, firmware, part_file);
+                        return;
+                    }
+                }
+                Err(StreamError::Network(why)) if why.is_transient() && attempt < self.opt.retries => {
+                    attempt += 1;
+                    self.report_retry(&why, device_name, attempt);
+
+                    if !self.backoff(attempt, why.retry_after()).await {
+                        self.keep_part_file(device_name, firmware, part_file);
+                        return;
+                    }
+                }
+                Err(StreamError::Network(why)) => {
+                    self.report_failed_firmware(pass, device_name, firmware, why).await;
+                    return;
+                }
+                Err(StreamError::Io(why)) => {
+                    let why = Error::io("error writing part file", part_file.path(), why);
+                    self.report_failed_firmware(pass, device_name, firmware, why).await;
+                    return;
+                }
+            }
+        };
+
+        //IPSWs are zips, so anything else is broken even if the API had no checksum to catch it
+        if let Err(why) = check_zip(part_file.path().to_path_buf()).await {
+            self.quarantine(part_file, &file_path);
+            self.report_failed_firmware(pass, device_name, firmware, why).await;
+            return;
+        }
+
+        //Never keep a download that doesn't match the API checksums
+        match digests.check(firmware) {
+            Err(why) => {
+                self.report_failed_firmware(pass, device_name, firmware, Error::BadChecksum(why)).await;
+                return;
+            }
+            Ok(false) => debug!("no checksum to compare {} {} against", device_name, firmware.version),
+            Ok(true) => self.emit(&ProgressEvent::Verified {
+                device: device_name,
+                version: &firmware.version,
+                path: &file_path,
+            }),
+        }
+
+        //Move the downloaded file to the final path now that the dl is done.
+        match part_file.persist(&long_file_path) {
+            Err(why) => {
+                self.report_failed_firmware(pass, device_name, firmware, why).await;
+            },
+            Ok(bytes) => {
+                if let Some(planned) = planned {
+                    planned.keep();
+                }
+                if bytes == 0 {
+                    warn!("Downloaded file is empty!");
+                } else {
+                    debug!("Moved {} bytes to final file", bytes);
+                }
+                Span::current().record("speed", (bytes as f64 / started.elapsed().as_secs_f64()) as u64);
+                info!(
+                    device = device_name,
+                    version = firmware.version.as_str(),
+                    buildid = firmware.buildid.as_str(),
+                    bytes = bytes,
+                    duration = started.elapsed().as_secs_f64(),
+                    "downloaded {} {} in {:.1?}", device_name, firmware.version, started.elapsed()
+                );
+
+                self.record(device_name, firmware, &digests.sha1, bytes, &file_path);
+
+                //Uploaded files leave the directory, so aren't listed
+                if self.opt.remote.is_none() {
+                    let _guard = self.sums_lock.lock().expect("SHA1SUMS lock poisoned");
+                    if let Err(why) = record_sha1(&file_path, &digests.sha1) {
+                        error!("{}", why);
+                    }
+                }
+
+                //Delete old files if enabled, now that the new one is safely in place
+                if self.opt.delete_old_fw {
+                    self.delete_old_firmware(firmware, &file_path);
+                }
+
+                if self.opt.extract_manifests {
+                    self.extract_manifests(&file_path).await;
+                }
+                if self.opt.save_keys {
+                    self.save_keys(device_name, firmware, &file_path).await;
+                }
+                if let Some(format) = self.opt.metadata {
+                    self.save_metadata(device_name, firmware, &file_path, format);
+                }
+
+                if let Some(remote) = &self.opt.remote {
+                    self.upload(pass, remote, device_name, firmware, &file_path, bytes).await;
+                    return;
+                }
+
+                self.report(pass, DownloadResult::Downloaded {
+                    device: device_name.to_string(),
+                    version: firmware.version.clone(),
+                    buildid: firmware.buildid.clone(),
+                    size: bytes,
+                    path: file_path,
+                }).await;
+            }
+        }
+    }
+
+    /// Uploads a finished download to the remote, then deletes the local copy.
+    ///
+    /// Should the upload fail, the local copy is kept to be uploaded on the next run.
+    async fn upload(&self, pass: &Pass, remote: &Remote, device_name: &str, firmware: &Firmware, file_path: &Path, size: u64) {
+        let path = self.remote_path(file_path);
+        self.print(format!("Uploading {} {} to {}...", device_name, firmware.version, remote.url(&path)));
+
+        if let Err(why) = remote.upload(file_path, &path).await {
+            self.report_failed_firmware(pass, device_name, firmware, why).await;
+            return;
+        }
+
+        match remove_file(file_path) {
+            Ok(()) => self.forget(file_path),
+            Err(why) => error!("could not delete uploaded file {}: {}", file_path.display(), why),
+        }
+
+        self.report(pass, DownloadResult::Downloaded {
+            device: device_name.to_string(),
+            version: firmware.version.clone(),
+            buildid: firmware.buildid.clone(),
+            size,
+            path: PathBuf::from(remote.url(&path)),
+        }).await;
+    }
+
+    /// Gets the path a file in the download directory is uploaded to on the remote.
+    fn remote_path(&self, file_path: &Path) -> String {
+        remote::remote_path(file_path.strip_prefix(&self.opt.download_path).unwrap_or(file_path))
+    }
+
+    /// Keeps the part file of an interrupted download, so the data isn't lost.
+    fn keep_part_file(&self, device_name: &str, firmware: &Firmware, part_file: PartFile) {
+        let path = part_file.path().to_path_buf();
+
+        match part_file.keep() {
+            //Nothing worth keeping
+            Ok(0) => {
+                let _ = remove_file(&path);
+            }
+            Ok(bytes) => {
+                warn!("Kept {} of {} {} in {}", HumanBytes(bytes), device_name, firmware.version, path.display());
+            }
+            Err(why) => error!("{}", why),
+        }
+    }
+
+    /// Extracts the restore manifests of a download next to it. Failures are only reported, as the download is fine.
+    async fn extract_manifests(&self, file_path: &Path) {
+        match extract_manifests(file_path).await {
+            Ok(extracted) => debug!("extracted {:?}", extracted),
+            Err(why) => {
+                error!("could not extract manifests of {}: {}", file_path.display(), why);
+            }
+        }
+    }
+
+    /// Saves the decryption keys of a download next to it. Failures are only reported, as the download is fine.
+    async fn save_keys(&self, device_name: &str, firmware: &Firmware, file_path: &Path) {
+        let keys = match self.client.get_firmware_keys(&firmware.identifier, &firmware.buildid).await {
+            Ok(keys) => keys,
+            Err(Error::NotFound(_)) => {
+                debug!("no keys are known for {} {}", device_name, firmware.buildid);
+                return;
+            }
+            Err(why) => {
+                error!("could not get keys of {} {}: {}", device_name, firmware.buildid, why);
+                return;
+            }
+        };
+
+        let keys_path = file_path.with_extension("keys.json");
+        let saved = serde_json::to_vec_pretty(&keys)
+            .map_err(Error::from)
+            .and_then(|json| write(&keys_path, json).map_err(|why| Error::io("could not write", &keys_path, why)));
+
+        match saved {
+            Ok(()) => debug!("saved keys to {}", keys_path.display()),
+            Err(why) => {
+                error!("{}", why);
+            }
+        }
+    }
+
+    /// Saves what is known about a download next to it. Failures are only reported, as the download is fine.
+    fn save_metadata(&self, device_name: &str, firmware: &Firmware, file_path: &Path, format: MetadataFormat) {
+        let metadata_path = metadata_path(file_path, format);
+        let saved = FirmwareMetadata::new(device_name, firmware).render(format).and_then(|metadata| {
+            write(&metadata_path, metadata).map_err(|why| Error::io("could not write", &metadata_path, why))
+        });
+
+        if let Err(why) = saved {
+            error!("{}", why);
+        }
+    }
+
+    /// Moves a broken download into the quarantine directory, so it can be inspected rather than lost.
+    fn quarantine(&self, part_file: PartFile, file_path: &Path) {
+        let relative_path = file_path.strip_prefix(&self.opt.download_path).unwrap_or(file_path);
+        let quarantine_path = self.opt.download_path.join(QUARANTINE_DIR).join(relative_path);
+
+        let moved = quarantine_path
+            .parent()
+            .map_or(Ok(()), create_dir_all)
+            .map_err(|why| Error::io("could not create directory for", &quarantine_path, why))
+            .and_then(|_| part_file.persist(&quarantine_path));
+
+        match moved {
+            Ok(_) => warn!("Moved the broken download to {}", quarantine_path.display()),
+            Err(why) => error!("failed to quarantine {}: {}", file_path.display(), why),
+        }
+    }
+
+    /// Gets the path a firmware was already downloaded to, if it still exists.
+    ///
+    /// This is the [`existing_path`](Self::existing_path) of the firmware, or else a [`shared_path`](Self::shared_path)
+    /// with the same contents. Only use this to decide whether to download, as shared paths belong to other firmware.
+    fn downloaded_path(&self, firmware: &Firmware, file_path: &Path) -> Option<PathBuf> {
+        self.existing_path(firmware, file_path).or_else(|| self.shared_path(firmware))
+    }
+
+    /// Gets the path this exact firmware was downloaded to, if it still exists. Safe to overwrite, prune or delete.
+    ///
+    /// This is `file_path` if it exists, or else wherever the index says the build was downloaded to.
+    fn existing_path(&self, firmware: &Firmware, file_path: &Path) -> Option<PathBuf> {
+        if file_path.exists() {
+            return Some(file_path.to_path_buf());
+        }
+
+        match self.index.find(&firmware.identifier, &firmware.buildid) {
+            Ok(Some(entry)) => {
+                let path = self.opt.download_path.join(entry.path);
+                if path.exists() {
+                    return Some(path);
+                }
+            }
+            Ok(None) => {}
+            Err(why) => error!("failed to search index: {}", why),
+        }
+
+        None
+    }
+
+    /// Gets a complete file in the library with the same SHA1 as the firmware, which may be stored under another name,
+    /// layout or device. Shared IPSWs have the same SHA1 for every device, so the file may belong to another device.
+    fn shared_path(&self, firmware: &Firmware) -> Option<PathBuf> {
+        if firmware.sha1sum.is_empty() {
+            return None;
+        }
+
+        match self.index.find_sha1(&firmware.sha1sum) {
+            Ok(entries) => entries
+                .into_iter()
+                .map(|entry| self.opt.download_path.join(entry.path))
+                .find(|path| path.exists() && !is_truncated(firmware, path)),
+            Err(why) => {
+                error!("failed to search index: {}", why);
+                None
+            }
+        }
+    }
+
+    /// Records a finished download in the index. Failures are only logged, as the file itself is fine.
+    fn record(&self, device_name: &str, firmware: &Firmware, sha1: &str, size: u64, file_path: &Path) {
+        let entry = IndexEntry {
+            device: device_name.to_string(),
+            identifier: firmware.identifier.clone(),
+            version: firmware.version.clone(),
+            buildid: firmware.buildid.clone(),
+            sha1: sha1.to_string(),
+            size,
+            path: file_path.strip_prefix(&self.opt.download_path).unwrap_or(file_path).to_path_buf(),
+            downloaded: Utc::now(),
+        };
+
+        if let Err(why) = self.index.record(&entry) {
+            error!("failed to record {} in index: {}", file_path.display(), why);
+        }
+    }
+
+    /// Removes a deleted file from the index.
+    fn forget(&self, file_path: &Path) {
+        let relative_path = file_path.strip_prefix(&self.opt.download_path).unwrap_or(file_path);
+
+        if let Err(why) = self.index.remove_path(relative_path) {
+            error!("failed to remove {} from index: {}", file_path.display(), why);
+        }
+    }
+
+    /// Checks an already downloaded firmware against its checksum.
+    ///
+    /// # Returns
+    /// `true` if the file doesn't match the checksum. Files that have no checksum or can't be read are not corrupt.
+    async fn is_corrupt(&self, device_name: &str, firmware: &Firmware, file_path: &Path) -> bool {
+        self.print(format!("Verifying {} {}...", device_name, firmware.version).dimmed());
+
+        match verify_firmware(file_path, firmware).await {
+            VerifyStatus::Mismatch { expected, actual } => {
+                warn!("{} is corrupt, expected {} but got {}, re-downloading", file_path.display(), expected, actual);
+                true
+            }
+            VerifyStatus::Error { error } => {
+                error!("could not verify {}: {}", file_path.display(), error);
+                false
+            }
+            VerifyStatus::Ok | VerifyStatus::NoChecksum => false,
+        }
+    }
+
+    /// Streams the ipsw referenced by `fw` into the part file, continuing from whatever previous attempts or runs left.
+    async fn stream_to_part(
+        &self,
+        pass: &Pass,
+        fw: &Firmware,
+        device_name: &str,
+        part_file: &mut File,
+    ) -> std::result::Result<Digests, StreamError> {
+        //A part file larger than the firmware can't be a prefix of it
+        let mut existing = part_file.metadata().map_err(StreamError::Io)?.len();
+        if fw.filesize > 0 && existing > fw.filesize {
+            debug!("part file for {} {} is larger than the firmware, starting over", device_name, fw.version);
+            existing = 0;
+        }
+
+        //Hash while downloading, so the index gets a checksum without reading the file again.
+        //Anything already downloaded is hashed first, so the checksum still covers the whole file
+        let mut hasher = if existing > 0 {
+            hash_prefix(part_file, existing, self.opt.checksum).await.map_err(StreamError::Io)?
+        } else {
+            DownloadHasher::new(self.opt.checksum)
+        };
+
+        //Nothing left to download, the previous run was interrupted after the last byte
+        if fw.filesize > 0 && existing == fw.filesize {
+            return Ok(hasher.finalize());
+        }
+
+        //Get the stream to download
+        let (mut dl_stream, start, dl_size) = self
+            .client
+            .download_ipsw(fw, existing)
+            .await
+            .map_err(StreamError::Network)?;
+
+        if start > 0 {
+            self.print(format!("Resuming {} {} from {}", device_name, fw.version, HumanBytes(start)).dimmed());
+            info!("resuming {} {} from byte {}", device_name, fw.version, start);
+        } else if existing > 0 {
+            //The server ignored the range, so the file starts over
+            hasher = DownloadHasher::new(self.opt.checksum);
+        }
+
+        //Drop anything past where the stream starts
+        part_file.set_len(start).map_err(StreamError::Io)?;
+        part_file.seek(SeekFrom::Start(start)).map_err(StreamError::Io)?;
+
+        //Write through tokio, so slow disks don't stall the network reads sharing this thread
+        let async_part_file = tokio::fs::File::from_std(part_file.try_clone().map_err(StreamError::Io)?);
+        let mut part_file_stream = BufWriter::with_capacity(WRITE_BUFFER_SIZE, async_part_file);
+
+        //Set up progress bar
+        let download_progress_bar = pass.progress.add(ProgressBar::new(dl_size));
+        download_progress_bar.set_style(ProgressStyle::default_bar()
+            .template(&self.opt.progress_template)
+            .progress_chars("#>-"));
+        download_progress_bar.set_prefix(device_name.to_string());
+        download_progress_bar.set_position(start);
+
+        //When printing progress lines, the time and percent of the last line
+        let mut last_log = (std::time::Instant::now(), 0);
+        //When emitting progress events, the percent of the last event
+        let mut last_event_percent = 0;
+
+        //Actually download file. Resumed bytes count as streamed, as retries resume them again
+        pass.bytes_bar.inc(start);
+        let mut streamed = start;
+        let result = loop {
+            //Stop reading while paused. The server waits for us, so nothing is lost
+            if !self.wait_while_paused().await {
+                break Err(StreamError::Killed);
+            }
+
+            //Select over the download and being interrupted
+            tokio::select! {
+                //Packets for file
+                byte = dl_stream.next() => {
+                    match byte {
+                        Some(Ok(byte)) => {
+                            if let Some(rate_limiter) = &self.rate_limiter {
+                                rate_limiter.acquire(byte.len() as u64).await;
+                            }
+
+                            //Update dl bars
+                            download_progress_bar.inc(byte.len() as u64);
+                            pass.bytes_bar.inc(byte.len() as u64);
+                            self.metrics.add_bytes(byte.len() as u64);
+                            streamed += byte.len() as u64;
+
+                            let percent = (download_progress_bar.position() * 100).checked_div(dl_size).unwrap_or(100);
+                            if self.opt.progress_format == ProgressFormat::Json && percent > last_event_percent {
+                                self.emit(&ProgressEvent::Progress {
+                                    device: device_name,
+                                    version: &fw.version,
+                                    bytes: download_progress_bar.position(),
+                                    total: dl_size,
+                                });
+                                last_event_percent = percent;
+                            }
+                            if let Some(interval) = self.opt.progress_log_interval {
+
+                                if percent >= last_log.1 + PROGRESS_LOG_PERCENT || last_log.0.elapsed() >= interval {
+                                    self.log_progress(device_name, &fw.version, &download_progress_bar, percent);
+                                    last_log = (std::time::Instant::now(), percent - percent % PROGRESS_LOG_PERCENT);
+                                }
+                            }
+
+                            hasher.update(&byte);
+                            if let Err(why) = part_file_stream.write_all(byte.as_ref()).await {
+                                break Err(StreamError::Io(why));
+                            }
+                        }
+                        Some(Err(why)) => break Err(StreamError::Network(why)),
+                        //Stream done
+                        None => break Ok(()),
+                    }
+                }
+
+                //break if cancelled
+                _ = self.cancel.cancelled() => {
+                    break Err(StreamError::Killed);
+                }
+            }
+        };
+
+        //Write out what was buffered, so an interrupted download keeps everything received
+        if let Err(StreamError::Killed) = result {
+            let _ = part_file_stream.flush().await;
+        }
+
+        //Retries start over, so this attempt no longer counts towards the total
+        if result.is_err() {
+            pass.bytes_bar.set_position(pass.bytes_bar.position().saturating_sub(streamed));
+        }
+        result?;
+
+        part_file_stream.flush().await.map_err(StreamError::Io)?;
+        download_progress_bar.finish_and_clear();
+
+        Ok(hasher.finalize())
+    }
+
+    /// Downloads the ipsw referenced by `fw` into the part file over `segments` connections at once, each fetching its
+    /// own byte range. Continues from whatever previous attempts or runs left.
+    ///
+    /// The first segment writes into the part file itself and the others into their own files next to it, which are
+    /// appended once done, so the part file only ever holds bytes downloaded without gaps. Segments are retried on
+    /// their own. If one fails for good, the segments are still appended up to the first gap, so the next attempt
+    /// resumes from there.
+    async fn segments_to_part(
+        &self,
+        pass: &Pass,
+        fw: &Firmware,
+        device_name: &str,
+        part_file: &mut File,
+        part_path: &Path,
+    ) -> std::result::Result<Digests, StreamError> {
+        //A part file larger than the firmware can't be a prefix of it
+        let mut existing = part_file.metadata().map_err(StreamError::Io)?.len();
+        if existing > fw.filesize {
+            debug!("part file for {} {} is larger than the firmware, starting over", device_name, fw.version);
+            existing = 0;
+            part_file.set_len(0).map_err(StreamError::Io)?;
+        }
+        remove_segment_files(part_path).map_err(StreamError::Io)?;
+
+        if existing < fw.filesize {
+            let ranges = split_range(existing..fw.filesize, self.opt.segments);
+            let done: Vec<AtomicU64> = ranges.iter().map(|_| AtomicU64::new(0)).collect();
+
+            if existing > 0 {
+                self.print(format!("Resuming {} {} from {}", device_name, fw.version, HumanBytes(existing)).dimmed());
+                info!("resuming {} {} from byte {}", device_name, fw.version, existing);
+            }
+            debug!("downloading {} {} in {} segments", device_name, fw.version, ranges.len());
+
+            //Set up progress bar
+            let download_progress_bar = pass.progress.add(ProgressBar::new(fw.filesize));
+            download_progress_bar.set_style(ProgressStyle::default_bar()
+                .template(&self.opt.progress_template)
+                .progress_chars("#>-"));
+            download_progress_bar.set_prefix(device_name.to_string());
+            download_progress_bar.set_position(existing);
+            pass.bytes_bar.inc(existing);
+
+            let marks = Mutex::new(ProgressMarks {
+                last_log: (std::time::Instant::now(), 0),
+                last_event_percent: 0,
+            });
+            let segments = ranges.iter().zip(&done).enumerate().map(|(i, (range, done))| {
+                let (bar, marks) = (&download_progress_bar, &marks);
+                let (path, position) = if i == 0 {
+                    (part_path.to_path_buf(), range.start)
+                } else {
+                    (segment_path(part_path, i), 0)
+                };
+                async move {
+                    let segment = Segment { path: &path, position, range: range.clone(), done };
+                    self.download_segment(pass, fw, device_name, segment, bar, marks).await
+                }
+            });
+            let result = futures::future::try_join_all(segments).await;
+            download_progress_bar.finish_and_clear();
+
+            let done_bytes: Vec<u64> = done.iter().map(|done| done.load(Ordering::SeqCst)).collect();
+            let joined = join_segments(part_file, part_path, &ranges, &done_bytes).await.map_err(StreamError::Io);
+
+            if let Err(why) = result {
+                joined?;
+
+                //Retries start over, so this attempt no longer counts towards the total
+                let streamed = existing + done_bytes.iter().sum::<u64>();
+                pass.bytes_bar.set_position(pass.bytes_bar.position().saturating_sub(streamed));
+                return Err(why);
+            }
+            joined?;
+        }
+
+        //Segments arrive out of order, so the file is only hashed once complete
+        let hasher = hash_prefix(part_file, fw.filesize, self.opt.checksum).await.map_err(StreamError::Io)?;
+        Ok(hasher.finalize())
+    }
+
+    /// Downloads the bytes of `segment` of the ipsw referenced by `fw` into its file, retrying transient errors from
+    /// where the last attempt stopped.
+    async fn download_segment(
+        &self,
+        pass: &Pass,
+        fw: &Firmware,
+        device_name: &str,
+        segment: Segment<'_>,
+        bar: &ProgressBar,
+        marks: &Mutex<ProgressMarks>,
+    ) -> std::result::Result<(), StreamError> {
+        let Segment { path, position, range, done } = segment;
+        //The first segment continues the part file, so it must not be cut
+        let file = OpenOptions::new().write(true).create(true).truncate(false).open(path).map_err(StreamError::Io)?;
+        let mut file = tokio::fs::File::from_std(file);
+        let segment = format!("{} bytes {}-{}", device_name, range.start, range.end);
+
+        let mut attempt = 0;
+        loop {
+            let written = done.load(Ordering::SeqCst);
+            let start = range.start + written;
+            if start >= range.end {
+                return Ok(());
+            }
+
+            file.seek(SeekFrom::Start(position + written)).await.map_err(StreamError::Io)?;
+            match self.stream_segment(pass, fw, device_name, &mut file, start..range.end, done, bar, marks).await {
+                //The server closed the connection early without an error
+                Ok(()) if range.start + done.load(Ordering::SeqCst) < range.end => {
+                    if attempt >= self.opt.retries {
+                        let why = format!("{} ended early", segment);
+                        let why = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, why);
+                        return Err(StreamError::Io(why));
+                    }
+                    attempt += 1;
+                    self.report_retry(&"segment ended early", &segment, attempt);
+                }
+                Err(StreamError::Network(why)) if why.is_transient() && attempt < self.opt.retries => {
+                    attempt += 1;
+                    self.report_retry(&why, &segment, attempt);
+
+                    if !self.backoff(attempt, why.retry_after()).await {
+                        return Err(StreamError::Killed);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Streams the bytes in `range` of the ipsw referenced by `fw` into `file` at its current position, counting those
+    /// written in `done`.
+    #[allow(clippy::too_many_arguments)]
+    async fn stream_segment(
+        &self,
+        pass: &Pass,
+        fw: &Firmware,
+        device_name: &str,
+        file: &mut tokio::fs::File,
+        range: Range<u64>,
+        done: &AtomicU64,
+        bar: &ProgressBar,
+        marks: &Mutex<ProgressMarks>,
+    ) -> std::result::Result<(), StreamError> {
+        let mut dl_stream = self.client.download_ipsw_range(fw, range.clone()).await.map_err(StreamError::Network)?;
+
+        //Buffered by hand rather than with a BufWriter, so `done` only counts bytes that reached the file
+        let mut buffer = Vec::with_capacity(WRITE_BUFFER_SIZE);
+        let result = loop {
+            //Stop reading while paused. The server waits for us, so nothing is lost
+            if !self.wait_while_paused().await {
+                break Err(StreamError::Killed);
+            }
+
+            tokio::select! {
+                byte = dl_stream.next() => {
+                    match byte {
+                        Some(Ok(byte)) => {
+                            if let Some(rate_limiter) = &self.rate_limiter {
+                                rate_limiter.acquire(byte.len() as u64).await;
+                            }
+
+                            //Update dl bars
+                            bar.inc(byte.len() as u64);
+                            pass.bytes_bar.inc(byte.len() as u64);
+                            self.metrics.add_bytes(byte.len() as u64);
+                            self.segment_progress(fw, device_name, bar, marks);
+
+                            buffer.extend_from_slice(&byte);
+                            if buffer.len() >= WRITE_BUFFER_SIZE {
+                                if let Err(why) = file.write_all(&buffer).await {
+                                    break Err(StreamError::Io(why));
+                                }
+                                done.fetch_add(buffer.len() as u64, Ordering::SeqCst);
+                                buffer.clear();
+                            }
+                        }
+                        Some(Err(why)) => break Err(StreamError::Network(why)),
+                        //Stream done
+                        None => break Ok(()),
+                    }
+                }
+
+                //break if cancelled
+                _ = self.cancel.cancelled() => {
+                    break Err(StreamError::Killed);
+                }
+            }
+        };
+
+        //Write out what was buffered, so retries and interrupted downloads keep everything received
+        if !buffer.is_empty() {
+            file.write_all(&buffer).await.map_err(StreamError::Io)?;
+            done.fetch_add(buffer.len() as u64, Ordering::SeqCst);
+        }
+        file.flush().await.map_err(StreamError::Io)?;
+
+        result
+    }
+
+    /// Emits progress events and prints progress lines for a segmented download, as configured.
+    fn segment_progress(&self, fw: &Firmware, device_name: &str, bar: &ProgressBar, marks: &Mutex<ProgressMarks>) {
+        let percent = (bar.position() * 100).checked_div(fw.filesize).unwrap_or(100);
+        let mut marks = marks.lock().expect("progress lock poisoned");
+
+        if self.opt.progress_format == ProgressFormat::Json && percent > marks.last_event_percent {
+            self.emit(&ProgressEvent::Progress {
+                device: device_name,
+                version: &fw.version,
+                bytes: bar.position(),
+                total: fw.filesize,
+            });
+            marks.last_event_percent = percent;
+        }
+        if let Some(interval) = self.opt.progress_log_interval {
+            if percent >= marks.last_log.1 + PROGRESS_LOG_PERCENT || marks.last_log.0.elapsed() >= interval {
+                self.log_progress(device_name, &fw.version, bar, percent);
+                marks.last_log = (std::time::Instant::now(), percent - percent % PROGRESS_LOG_PERCENT);
+            }
+        }
+    }
+
+    /// Prints a line describing how far along a download is, for when progress bars aren't drawn.
+    fn log_progress(&self, device_name: &str, version: &str, bar: &ProgressBar, percent: u64) {
+        self.print(format!(
+            "{} {}: {}/{} ({}%) at {}/s",
+            device_name,
+            version,
+            HumanBytes(bar.position()),
+            HumanBytes(bar.length()),
+            percent,
+            HumanBytes(bar.per_sec())
+        ));
+    }
+
+    /// Waits before the next attempt at a failed request, doubling the delay with each attempt unless a fixed
+    /// `retry_delay` is set. If the server said how long to wait with `retry_after`, that is waited instead, up to
+    /// [`MAX_RETRY_AFTER`].
+    ///
+    /// # Returns
+    /// `false` if cancelled while waiting.
+    async fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> bool {
+        let delay = match (retry_after, self.opt.retry_delay) {
+            (Some(retry_after), _) => retry_after.min(MAX_RETRY_AFTER),
+            (None, Some(retry_delay)) => retry_delay,
+            (None, None) => Duration::from_secs_f64(self.opt.backoff_base * 2f64.powi(attempt as i32 - 1)),
+        };
+        debug!("waiting {:?} before attempt {}", delay, attempt + 1);
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => true,
+            _ = self.cancel.cancelled() => {
+                false
+            }
+        }
+    }
+
+    /// Reports a device firmware download error.
+    async fn report_err(&self, pass: &Pass, err: Error, device: &Device) {
+        error!(device = device.name.as_str(), error_kind = err.kind(), "Getting device firmware errored: {}", err);
+        pass.record_failure(&device.identifier, &device.name, &err);
+
+        self.report(pass, DownloadResult::Failed {
+            device: device.name.clone(),
+            version: None,
+            error: err.to_string(),
+        }).await;
+    }
+
+    /// Reports that the API is rate limiting a device's requests, and waits until it may be asked again.
+    ///
+    /// # Returns
+    /// `false` if cancelled while waiting.
+    async fn wait_out_rate_limit(&self, err: &Error, device: &str, waits: u32) -> bool {
+        let delay = err
+            .retry_after()
+            .unwrap_or_else(|| Duration::from_secs_f64(self.opt.backoff_base * 2f64.powi(waits as i32 - 1)))
+            .min(MAX_RETRY_AFTER);
+        info!("{} is rate limited, waiting {:?} ({})", device, delay, err);
+
+        self.print(format!("Rate limited on {}, waiting {} seconds before trying again", device, delay.as_secs()).yellow());
+
+        self.backoff(waits, Some(delay)).await
+    }
+
+    /// Reports that a request failed, and will be retried.
+    fn report_retry(&self, err: &impl Display, device: &str, attempt: u32) {
+        let retries = self.opt.retries;
+        warn!(attempt, retries, "Request for {} errored, retrying (attempt {} of {}): {}", device, attempt, retries, err);
+    }
+
+    /// Reports that a specific firmware failed to download.
+    async fn report_failed_firmware(&self, pass: &Pass, device_name: &str, firmware: &Firmware, err: Error) {
+        error!(
+            device = device_name,
+            version = firmware.version.as_str(),
+            buildid = firmware.buildid.as_str(),
+            error_kind = err.kind(),
+            "{} {} failed: {}", device_name, firmware.version, err
+        );
+
+        pass.record_failure(&firmware.identifier, device_name, &err);
+
+        //It won't be downloaded this pass, so stop waiting on it
+        let total_bytes = pass.bytes_bar.length().saturating_sub(firmware.filesize);
+        pass.bytes_bar.set_length(total_bytes);
+
+        self.report(pass, DownloadResult::Failed {
+            device: device_name.to_string(),
+            version: Some(firmware.version.clone()),
+            error: err.to_string(),
+        }).await;
+    }
+
+    /// Counts the result of a download towards the run summary, printing it if using JSON output and sending notifications.
+    async fn report(&self, pass: &Pass, result: DownloadResult) {
+        match result {
+            DownloadResult::Downloaded { .. } => pass.total_downloaded.fetch_add(1, Ordering::SeqCst),
+            DownloadResult::Skipped { .. } => pass.total_skipped.fetch_add(1, Ordering::SeqCst),
+            DownloadResult::Failed { .. } => pass.total_failed.fetch_add(1, Ordering::SeqCst),
+        };
+        //Only download spans have an outcome, so this does nothing for devices that failed before picking firmware
+        Span::current().record("outcome", match result {
+            DownloadResult::Downloaded { .. } => "downloaded",
+            DownloadResult::Skipped { .. } => "skipped",
+            DownloadResult::Failed { .. } => "failed",
+        });
+        match &result {
+            DownloadResult::Downloaded { device, .. } => self.metrics.downloaded(device),
+            DownloadResult::Skipped { .. } => self.metrics.skipped(),
+            DownloadResult::Failed { device, .. } => self.metrics.failed(device),
+        }
+
+        if self.opt.progress_format == ProgressFormat::Json {
+            print_json(&ProgressEvent::Finished(&result));
+        } else if self.opt.output == OutputFormat::Json {
+            print_json(&result);
+        }
+
+        //Skips are routine, so only notify of results worth knowing about
+        if let Some(webhook) = &self.webhook {
+            if !matches!(result, DownloadResult::Skipped { .. }) {
+                webhook.notify(&WebhookEvent::Download(&result)).await;
+            }
+        }
+        if self.opt.notify {
+            notify_desktop(&WebhookEvent::Download(&result)).await;
+        }
+        if self.email.is_some() {
+            pass.results.lock().expect("results lock poisoned").push(result);
+        }
+    }
+
+    /// Prints a human readable message. Does nothing if quiet, or using JSON output as stdout must stay parsable.
+    fn print(&self, msg: impl Display) {
+        if self.prints_text() && !self.opt.quiet {
+            println!("{}", msg);
+        }
+    }
+
+    /// True if stdout is for human readable text, rather than JSON.
+    fn prints_text(&self) -> bool {
+        self.opt.output == OutputFormat::Text && self.opt.progress_format != ProgressFormat::Json
+    }
+
+    /// Prints a progress event, if emitting them.
+    fn emit(&self, event: &ProgressEvent) {
+        if self.opt.progress_format == ProgressFormat::Json {
+            print_json(event);
+        }
+    }
+
+    /// Performs tasks after a failed or successful download. total done increment, progress bar ect.
+    fn after_fw_download(&self, pass: &Pass, device: &Device) {
+        let total_done = pass.total_done.fetch_add(1, Ordering::SeqCst) + 1;
+        pass.devices_bar.inc(1);
+
+        let done_str = format!(
+            "{}{}/{}{}",
+            "(".bold().italic(),
+            total_done.to_string().cyan().italic(),
+            pass.total_todo.load(Ordering::SeqCst).to_string().cyan().italic(),
+            ")".bold().italic(),
+        );
+
+        self.print(format!("Ended work on: {} {}", device.name, done_str));
+    }
+}
+
+/// Hashes the first `len` bytes of a file, without blocking the async runtime.
+async fn hash_prefix(file: &File, len: u64, checksum: Checksum) -> std::io::Result<DownloadHasher> {
+    let mut file = file.try_clone()?;
+
+    tokio::task::spawn_blocking(move || {
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut hasher = DownloadHasher::new(checksum);
+        std::io::copy(&mut std::io::BufReader::new(file).take(len), &mut hasher)?;
+
+        Ok(hasher)
+    })
+    .await
+    .expect("hashing task panicked")
+}
+
+/// Path of the file the `index`th segment of a download into `part_path` is written to, ex. `16.7.8.ipsw.part.1`.
+fn segment_path(part_path: &Path, index: usize) -> PathBuf {
+    let mut path = part_path.as_os_str().to_owned();
+    path.push(format!(".{}", index));
+    PathBuf::from(path)
+}
+
+/// Removes the segment files left next to `part_path` by an earlier run, which can't be resumed without their ranges.
+fn remove_segment_files(part_path: &Path) -> std::io::Result<()> {
+    let (dir, name) = match (part_path.parent(), part_path.file_name().and_then(|name| name.to_str())) {
+        (Some(dir), Some(name)) => (dir, format!("{}.", name)),
+        _ => return Ok(()),
+    };
+
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let is_segment = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.strip_prefix(name.as_str()))
+            .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()));
+        if is_segment {
+            debug!("removing stale segment {}", path.display());
+            remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends the segment files of a download to its part file, up to the first segment that didn't finish, so the part
+/// file ends up holding every byte downloaded without gaps. `done` holds the bytes written to each of `ranges`.
+/// Removes the segment files either way.
+async fn join_segments(file: &File, part_path: &Path, ranges: &[Range<u64>], done: &[u64]) -> std::io::Result<()> {
+    let mut file = file.try_clone()?;
+    let part_path = part_path.to_path_buf();
+    let ranges = ranges.to_vec();
+    let done = done.to_vec();
+
+    tokio::task::spawn_blocking(move || {
+        let mut len = ranges[0].start + done[0];
+        let mut gap_free = done[0] == ranges[0].end - ranges[0].start;
+        file.set_len(len)?;
+        file.seek(SeekFrom::Start(len))?;
+
+        for (i, (range, &done)) in ranges.iter().zip(&done).enumerate().skip(1) {
+            let path = segment_path(&part_path, i);
+            if gap_free && done > 0 {
+                let segment = File::open(&path)?;
+                len += std::io::copy(&mut segment.take(done), &mut file)?;
+                gap_free = done == range.end - range.start;
+            } else {
+                gap_free = false;
+            }
+
+            match remove_file(&path) {
+                Err(why) if why.kind() != std::io::ErrorKind::NotFound => return Err(why),
+                _ => {}
+            }
+        }
+
+        debug!("joined segments of {} into {} bytes", part_path.display(), len);
+        Ok(())
+    })
+    .await
+    .expect("joining task panicked")
+}
+
+/// True if a downloaded file is smaller or larger than the firmware, ex. it was truncated by a crash.
+/// Files of firmware without a known size are never truncated.
+fn is_truncated(firmware: &Firmware, file_path: &Path) -> bool {
+    let size = match metadata(file_path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return false,
+    };
+
+    if firmware.filesize > 0 && size != firmware.filesize {
+        warn!(
+            "{} is {} bytes, but should be {}, re-downloading",
+            file_path.display(),
+            size,
+            firmware.filesize
+        );
+        return true;
+    }
+    false
+}
+
+/// A range of an ipsw downloaded over its own connection, written to `path` starting at `position`. `done` counts the
+/// bytes of the range written so far.
+struct Segment<'a> {
+    path: &'a Path,
+    position: u64,
+    range: Range<u64>,
+    done: &'a AtomicU64,
+}
+
+/// Splits `range` into up to `segments` ranges of about the same size, none smaller than [`MIN_SEGMENT_SIZE`] unless
+/// `range` itself is.
+fn split_range(range: Range<u64>, segments: usize) -> Vec<Range<u64>> {
+    let len = range.end - range.start;
+    let segments = (segments as u64).min(len / MIN_SEGMENT_SIZE).max(1);
+    let segment_len = len / segments;
+
+    (0..segments)
+        .map(|i| {
+            let start = range.start + i * segment_len;
+            //The last segment takes the remainder
+            let end = if i + 1 == segments { range.end } else { start + segment_len };
+            start..end
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::ClientOptions;
+
+    #[tokio::test]
+    async fn force_keeps_files_of_other_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        //Nothing listens here, so downloads fail straight away
+        let client = Client::with_options(ClientOptions {
+            api_url: "http://127.0.0.1:9".to_string(),
+            cache: None,
+            ..ClientOptions::default()
+        })
+        .unwrap();
+        let opt = DownloadOptions {
+            download_path: dir.path().to_path_buf(),
+            force: true,
+            retries: 0,
+            quiet: true,
+            ..DownloadOptions::default()
+        };
+        let downloader = Downloader::new(client, Vec::new(), opt).unwrap();
+
+        //Shared IPSWs have the same SHA1 for every device they support
+        let firmware = |identifier: &str| Firmware {
+            identifier: identifier.to_string(),
+            version: "16.7.8".to_string(),
+            buildid: "20H343".to_string(),
+            sha1sum: "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d".to_string(),
+            md5sum: String::new(),
+            filesize: 5,
+            url: String::new(),
+            uploaddate: Utc::now(),
+            signed: true,
+            ota: false,
+            beta: false,
+        };
+        let other = firmware("iPhone10,1");
+        let other_path = dir.path().join("iPhone 8").join("16.7.8.ipsw");
+        std::fs::create_dir_all(other_path.parent().unwrap()).unwrap();
+        std::fs::write(&other_path, "hello").unwrap();
+        downloader.record("iPhone 8", &other, &other.sha1sum, 5, &other_path);
+
+        let firmware = firmware("iPhone10,4");
+        let file_path = dir.path().join("iPhone 8 Plus").join("16.7.8.ipsw");
+        assert_eq!(downloader.downloaded_path(&firmware, &file_path), Some(other_path.clone()));
+        assert_eq!(downloader.existing_path(&firmware, &file_path), None);
+
+        let pass = Pass::new(1, true, Journal::start(dir.path(), Vec::new()));
+        downloader.download_firmware(&pass, "iPhone 8 Plus", &firmware, file_path).await;
+        assert_eq!(std::fs::read_to_string(&other_path).unwrap(), "hello");
+        assert_eq!(downloader.existing_path(&other, &other_path), Some(other_path));
+    }
+
+    #[tokio::test]
+    async fn join_segments_stops_at_gaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let part_path = dir.path().join("16.7.8.ipsw.part");
+        //Resumed from byte 2, with the second segment cut short and a third one done
+        std::fs::write(&part_path, "heLL").unwrap();
+        std::fs::write(segment_path(&part_path, 1), "ow").unwrap();
+        std::fs::write(segment_path(&part_path, 2), "rld").unwrap();
+        std::fs::write(dir.path().join("16.7.8.ipsw.part.old"), "").unwrap();
+
+        let file = OpenOptions::new().read(true).write(true).open(&part_path).unwrap();
+        let ranges = [2..4, 4..7, 7..10];
+        join_segments(&file, &part_path, &ranges, &[2, 2, 3]).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&part_path).unwrap(), "heLLow");
+        assert!(!segment_path(&part_path, 1).exists());
+        assert!(!segment_path(&part_path, 2).exists());
+
+        std::fs::write(segment_path(&part_path, 3), "stale").unwrap();
+        remove_segment_files(&part_path).unwrap();
+        assert!(!segment_path(&part_path, 3).exists());
+        assert!(dir.path().join("16.7.8.ipsw.part.old").exists());
+    }
+
+    #[test]
+    fn failed_downloads_give_planned_bytes_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let pass = Pass::new(3, true, Journal::start(dir.path(), Vec::new()));
+
+        let failed = pass.plan_bytes(60, 100).unwrap();
+        assert!(pass.plan_bytes(60, 100).is_none());
+        drop(failed);
+
+        pass.plan_bytes(60, 100).unwrap().keep();
+        assert!(pass.plan_bytes(60, 100).is_none());
+        assert!(pass.plan_bytes(40, 100).is_some());
     }
 }
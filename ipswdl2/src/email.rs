@@ -0,0 +1,213 @@
+//! Emails a summary of each run over SMTP, for headless archive servers nobody watches.
+use std::fmt;
+
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tracing::{debug, error, info};
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::output::{DownloadResult, Summary};
+use crate::webhook::WebhookEvent;
+
+/// Environment variable the SMTP password is read from if the config file has none, so it can stay out of the file.
+pub const PASSWORD_VAR: &str = "IPSWDL2_SMTP_PASSWORD";
+
+/// How the connection to the SMTP server is secured.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// TLS from the start, usually on port 465.
+    Tls,
+    /// Upgrade a plain connection with STARTTLS, usually on port 587.
+    #[default]
+    StartTls,
+    /// No encryption at all, only for servers on the same machine or network.
+    None,
+}
+
+/// The `[email]` section of the config file.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    /// SMTP server to send through, ex. "smtp.example.com".
+    pub smtp_host: String,
+    /// Port of the SMTP server. Defaults to the usual port of `security`.
+    pub smtp_port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    /// User to log in as. Sends without logging in if not set.
+    pub username: Option<String>,
+    /// Password to log in with. Read from `IPSWDL2_SMTP_PASSWORD` if not set.
+    pub password: Option<String>,
+    /// Sender of the email, ex. "ipswdl2 <archive@example.com>".
+    pub from: String,
+    /// Recipients of the email.
+    pub to: Vec<String>,
+}
+
+impl fmt::Debug for EmailConfig {
+    //Keep secrets out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmailConfig")
+            .field("smtp_host", &self.smtp_host)
+            .field("smtp_port", &self.smtp_port)
+            .field("security", &self.security)
+            .field("username", &self.username)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .finish()
+    }
+}
+
+/// Sends run summaries to the configured recipients.
+pub struct Email {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Email {
+    /// Connects lazily to the SMTP server in `config`. Nothing is sent until a run completes.
+    ///
+    /// # Returns
+    /// * Err(Email) - An address isn't valid, there are no recipients, or the TLS backend failed to initialize.
+    pub fn new(config: &EmailConfig) -> Result<Self> {
+        let invalid_address = |address: &str| Error::Email(format!("invalid address {}", address));
+
+        let from = config.from.parse().map_err(|_| invalid_address(&config.from))?;
+        let to = config
+            .to
+            .iter()
+            .map(|address| address.parse().map_err(|_| invalid_address(address)))
+            .collect::<Result<Vec<Mailbox>>>()?;
+        if to.is_empty() {
+            return Err(Error::Email("no recipients configured".to_string()));
+        }
+
+        let mut builder = match config.security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host),
+            SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_host)),
+        }
+        .map_err(|why| Error::Email(why.to_string()))?;
+
+        if let Some(port) = config.smtp_port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &config.username {
+            let password = config
+                .password
+                .clone()
+                .or_else(|| std::env::var(PASSWORD_VAR).ok())
+                .unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        Ok(Email {
+            transport: builder.build(),
+            from,
+            to,
+        })
+    }
+
+    /// Emails the summary of a run. Failures are only logged, as a broken mail server shouldn't stop downloads.
+    pub async fn send_summary(&self, summary: &Summary, results: &[DownloadResult]) {
+        let mut message = Message::builder().from(self.from.clone());
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+
+        let message = message
+            .subject(subject(summary))
+            .header(ContentType::TEXT_PLAIN)
+            .body(summary_body(summary, results));
+        let message = match message {
+            Ok(message) => message,
+            Err(why) => {
+                error!("Failed to build summary email: {}", why);
+                return;
+            }
+        };
+
+        debug!("sending summary email to {} recipients", self.to.len());
+        match self.transport.send(message).await {
+            Ok(_) => info!("sent summary email"),
+            Err(why) => error!("Failed to send summary email: {}", why),
+        }
+    }
+}
+
+/// Gets the subject line of a summary email, leading with what needs attention.
+fn subject(summary: &Summary) -> String {
+    if summary.failed > 0 {
+        format!("ipswdl2: {} failed, {} downloaded", summary.failed, summary.downloaded)
+    } else {
+        format!("ipswdl2: {} downloaded", summary.downloaded)
+    }
+}
+
+/// A heading of the summary email, and which results are listed under it.
+type Section = (&'static str, fn(&DownloadResult) -> bool);
+
+/// Writes the body of a summary email, listing downloads, failures and skips under their own headings.
+fn summary_body(summary: &Summary, results: &[DownloadResult]) -> String {
+    let mut body = format!("{}\n", WebhookEvent::RunCompleted(summary).message());
+
+    let sections: [Section; 3] = [
+        ("Downloaded", |result| matches!(result, DownloadResult::Downloaded { .. })),
+        ("Failed", |result| matches!(result, DownloadResult::Failed { .. })),
+        ("Skipped", |result| matches!(result, DownloadResult::Skipped { .. })),
+    ];
+    for (heading, in_section) in sections.iter() {
+        let lines: Vec<String> = results
+            .iter()
+            .filter(|result| in_section(result))
+            .map(|result| format!("  {}\n", WebhookEvent::Download(result).message()))
+            .collect();
+
+        if !lines.is_empty() {
+            body.push_str(&format!("\n{}:\n", heading));
+            lines.iter().for_each(|line| body.push_str(line));
+        }
+    }
+
+    body
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn body_lists_results_by_kind() {
+        let summary = Summary {
+            downloaded: 0,
+            skipped: 1,
+            failed: 1,
+            minutes: 2,
+            deferred: Vec::new(),
+        };
+        let results = vec![
+            DownloadResult::Skipped {
+                device: "iPhone 8".to_string(),
+                version: None,
+                path: None,
+                reason: "already downloaded".to_string(),
+            },
+            DownloadResult::Failed {
+                device: "iPhone X".to_string(),
+                version: None,
+                error: "oops".to_string(),
+            },
+        ];
+
+        let body = summary_body(&summary, &results);
+        assert!(body.starts_with("Finished in 2 minutes."));
+        assert!(body.find("Failed:\n  Failed to download iPhone X: oops").unwrap() < body.find("Skipped:").unwrap());
+        assert!(!body.contains("Downloaded:"));
+        assert_eq!(subject(&summary), "ipswdl2: 1 failed, 0 downloaded");
+    }
+}
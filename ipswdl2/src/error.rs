@@ -0,0 +1,200 @@
+//! Error type shared by the whole crate.
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// Result type used throughout the crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Every way the crate can fail.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// A request to the API or Apple's servers failed.
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The server doesn't have the requested file.
+    #[error("{0} was not found (404)")]
+    NotFound(String),
+    /// The server refused to serve the requested file, ex. Apple pulled an old build.
+    #[error("access to {0} was denied (403)")]
+    Forbidden(String),
+    /// The server responded with an unexpected non-success status.
+    #[error("request for {what} failed with status {status}")]
+    Status {
+        /// What was being requested, ex. "iPhone10,1 20H343".
+        what: String,
+        status: reqwest::StatusCode,
+        /// How long the server asked to wait before trying again, from its Retry-After header.
+        retry_after: Option<std::time::Duration>,
+    },
+    /// The server is rate limiting us (429).
+    #[error("request for {what} was rate limited (429)")]
+    RateLimited {
+        /// What was being requested, ex. "firmware listing of iPhone10,1".
+        what: String,
+        /// How long the server asked to wait before trying again, from its Retry-After header.
+        retry_after: Option<std::time::Duration>,
+    },
+    /// A configured request header isn't valid HTTP.
+    #[error("invalid header {0}")]
+    InvalidHeader(String),
+    /// A download received no data for too long.
+    #[error("download stalled, no data received for {0:?}")]
+    Stalled(std::time::Duration),
+    /// The server didn't say how large a download is.
+    #[error("download of {0} has no content length")]
+    NoContentLength(String),
+    /// Reading or writing a file failed.
+    #[error("{context} {}: {source}", path.display())]
+    Io {
+        /// What was being done to the file, ex. "could not create file".
+        context: &'static str,
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// Some downloaded files didn't match their checksums.
+    #[error("{0} verification checks failed")]
+    VerifyFailed(usize),
+    /// A file no longer matches the checksum recorded when it was downloaded.
+    #[error("{} doesn't match its recorded checksum", .0.display())]
+    ChecksumMismatch(PathBuf),
+    /// Some files could not be mirrored by a sync.
+    #[error("{0} files failed to sync")]
+    SyncFailed(usize),
+    /// Servers checked by `status-api` could not be reached.
+    #[error("could not reach {0}")]
+    Unreachable(String),
+    /// The server sent a whole file or the wrong bytes when asked for part of it, so it can't be downloaded in segments.
+    #[error("server does not support range requests for {0}")]
+    RangeNotSupported(String),
+    /// A downloaded file isn't a readable zip archive, ex. it was truncated or is an error page.
+    #[error("not a valid ipsw: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    /// A download doesn't match the checksums the API has for it.
+    #[error("{0}")]
+    BadChecksum(String),
+    /// A plist inside an ipsw could not be parsed.
+    #[error("invalid plist: {0}")]
+    Plist(#[from] plist::Error),
+    /// SHSH blobs could not be saved.
+    #[error("saving SHSH blobs failed: {0}")]
+    Blobs(String),
+    /// Nothing in an ipsw matched the entries asked for.
+    #[error("no entries of {} matched", .0.display())]
+    NoMatchingEntries(PathBuf),
+    /// Uploading to S3 failed, or it isn't configured right.
+    #[error("S3 error: {0}")]
+    S3(String),
+    /// Uploading to a WebDAV server failed, or it isn't configured right.
+    #[error("WebDAV error: {0}")]
+    WebDav(String),
+    /// Uploading over SFTP failed, or it isn't configured right.
+    #[error("SFTP error: {0}")]
+    Sftp(String),
+    /// Summary emails can't be sent, as the SMTP settings aren't valid.
+    #[error("email error: {0}")]
+    Email(String),
+    /// The config file isn't valid TOML, or has unknown settings.
+    #[error("invalid config file {}: {source}", path.display())]
+    Config {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    /// The HTTP server serving the library failed, ex. its address was in use.
+    #[error("HTTP server failed: {0}")]
+    Serve(#[from] hyper::Error),
+    /// The index of downloaded firmware could not be read or written.
+    #[error("firmware index error: {0}")]
+    Index(#[from] rusqlite::Error),
+    /// Writing an export as CSV failed.
+    #[error("failed to write CSV: {0}")]
+    Csv(#[from] csv::Error),
+    /// Writing an export as JSON failed.
+    #[error("failed to write JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    /// Devices attached over USB could not be read, ex. libimobiledevice isn't installed.
+    #[error("could not read connected devices: {0}")]
+    ConnectedDevices(String),
+    /// No device matched a name or identifier.
+    #[error("no device matches {0}")]
+    DeviceNotFound(String),
+    /// The command can't print its results in the output format picked, ex. CSV for a download.
+    #[error("this command can't print {0} output")]
+    UnsupportedOutput(String),
+    /// Another process is already downloading into the directory.
+    #[error("{} is locked by another ipswdl2 process (pid {pid})", dir.display())]
+    Locked { dir: PathBuf, pid: u32 },
+    /// The ctrl-c handler could not be bound.
+    #[error("failed to bind the ctrl-c handler: {0}")]
+    Ctrlc(#[from] ctrlc::Error),
+}
+
+impl Error {
+    /// True if the error may go away by retrying, ex. a dropped connection or a server error.
+    /// Missing or forbidden files won't appear by asking again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Http(_) | Error::NoContentLength(_) | Error::Stalled(_) | Error::RateLimited { .. } => true,
+            Error::Status { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    /// Names the kind of error in snake case, ex. "not_found", for structured logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Http(_) => "http",
+            Error::NotFound(_) => "not_found",
+            Error::Forbidden(_) => "forbidden",
+            Error::Status { .. } => "status",
+            Error::RateLimited { .. } => "rate_limited",
+            Error::InvalidHeader(_) => "invalid_header",
+            Error::Stalled(_) => "stalled",
+            Error::NoContentLength(_) => "no_content_length",
+            Error::Io { .. } => "io",
+            Error::VerifyFailed(_) => "verify_failed",
+            Error::ChecksumMismatch(_) => "checksum_mismatch",
+            Error::SyncFailed(_) => "sync_failed",
+            Error::Unreachable(_) => "unreachable",
+            Error::RangeNotSupported(_) => "range_not_supported",
+            Error::Zip(_) => "zip",
+            Error::BadChecksum(_) => "bad_checksum",
+            Error::Plist(_) => "plist",
+            Error::Blobs(_) => "blobs",
+            Error::NoMatchingEntries(_) => "no_matching_entries",
+            Error::S3(_) => "s3",
+            Error::WebDav(_) => "webdav",
+            Error::Sftp(_) => "sftp",
+            Error::Email(_) => "email",
+            Error::Config { .. } => "config",
+            Error::Serve(_) => "serve",
+            Error::Index(_) => "index",
+            Error::Csv(_) => "csv",
+            Error::Json(_) => "json",
+            Error::ConnectedDevices(_) => "connected_devices",
+            Error::DeviceNotFound(_) => "device_not_found",
+            Error::UnsupportedOutput(_) => "unsupported_output",
+            Error::Locked { .. } => "locked",
+            Error::Ctrlc(_) => "ctrlc",
+        }
+    }
+
+    /// How long the server asked to wait before retrying, if it did.
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            Error::Status { retry_after, .. } | Error::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Creates an [`Error::Io`] from an io error, and what was being done to which file.
+    pub fn io(context: &'static str, path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        Error::Io {
+            context,
+            path: path.into(),
+            source,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Exports the contents of a download directory for inventory systems.
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::index::{Index, IndexEntry, INDEX_FILE_NAME};
+
+/// Format to export a library manifest in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    /// A single JSON array of entries.
+    Json,
+    /// CSV with a header row.
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("unknown export format: {}", s)),
+        }
+    }
+}
+
+/// Gets every firmware in the index of `download_path`, with paths including `download_path`.
+/// Empty if nothing was ever downloaded there.
+pub fn library_manifest(download_path: &Path) -> Result<Vec<IndexEntry>> {
+    if !download_path.join(INDEX_FILE_NAME).exists() {
+        return Ok(Vec::new());
+    }
+
+    let index = Index::open(download_path)?;
+
+    let entries = index
+        .entries()?
+        .into_iter()
+        .map(|mut entry| {
+            entry.path = download_path.join(&entry.path);
+            entry
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+/// Writes the entries to `writer` in the given format.
+pub fn write_manifest(entries: &[IndexEntry], format: ExportFormat, mut writer: impl Write) -> Result<()> {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer(&mut writer, entries)?;
+            writeln!(writer).map_err(|why| Error::io("could not write", "<output>", why))?;
+        }
+        ExportFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for entry in entries {
+                csv_writer.serialize(entry)?;
+            }
+            csv_writer.flush().map_err(|why| Error::io("could not write", "<output>", why))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use std::path::PathBuf;
+
+    #[test]
+    fn writes_csv_with_header() {
+        let entries = vec![IndexEntry {
+            device: "iPhone 8".to_string(),
+            identifier: "iPhone10,1".to_string(),
+            version: "16.7.8".to_string(),
+            buildid: "20H343".to_string(),
+            sha1: "abc".to_string(),
+            size: 42,
+            path: PathBuf::from("ipsw/iPhone 8/16.7.8.ipsw"),
+            downloaded: Utc.ymd(2024, 5, 13).and_hms(17, 0, 0),
+        }];
+
+        let mut out = Vec::new();
+        write_manifest(&entries, ExportFormat::Csv, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            out,
+            "device,identifier,version,buildid,sha1,size,path,downloaded\n\
+            iPhone 8,\"iPhone10,1\",16.7.8,20H343,abc,42,ipsw/iPhone 8/16.7.8.ipsw,2024-05-13T17:00:00Z\n"
+        );
+    }
+}
@@ -0,0 +1,431 @@
+//! Selection of devices by name, identifier, platform and class.
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use glob::{MatchOptions, Pattern};
+
+use crate::api_json_types::Device;
+
+/// Short platform names, and the normalized API platforms they stand for.
+const PLATFORM_ALIASES: &[(&str, &[&str])] = &[("macos", &["macos", "macosx"])];
+
+/// A product family, selected by identifier prefix.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeviceClass {
+    IPhone,
+    IPad,
+    IPod,
+    AppleTv,
+    /// HomePods, which identify as AudioAccessory.
+    HomePod,
+    Watch,
+}
+
+impl DeviceClass {
+    /// Prefix of the identifiers of devices in the class, ex. "iPhone" for "iPhone15,2".
+    pub fn identifier_prefix(self) -> &'static str {
+        match self {
+            DeviceClass::IPhone => "iPhone",
+            DeviceClass::IPad => "iPad",
+            DeviceClass::IPod => "iPod",
+            DeviceClass::AppleTv => "AppleTV",
+            DeviceClass::HomePod => "AudioAccessory",
+            DeviceClass::Watch => "Watch",
+        }
+    }
+
+    /// True if the device is in the class.
+    pub fn contains(self, device: &Device) -> bool {
+        device.identifier.starts_with(self.identifier_prefix())
+    }
+}
+
+impl FromStr for DeviceClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match normalize(s).replace(' ', "").as_str() {
+            "iphone" => Ok(DeviceClass::IPhone),
+            "ipad" => Ok(DeviceClass::IPad),
+            "ipod" => Ok(DeviceClass::IPod),
+            "appletv" => Ok(DeviceClass::AppleTv),
+            "homepod" | "audioaccessory" => Ok(DeviceClass::HomePod),
+            "watch" => Ok(DeviceClass::Watch),
+            _ => Err(format!(
+                "unknown device class: {}, expected iphone, ipad, ipod, appletv, homepod or watch",
+                s
+            )),
+        }
+    }
+}
+
+/// Filters selecting which devices to work on. The default filter matches every device.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceFilter {
+    /// Only match devices whose name contains any of these terms. Empty matches all devices.
+    pub terms: Vec<String>,
+    /// Only match devices whose identifier is or starts with this.
+    pub identifier: Option<String>,
+    /// Only match devices with any of these exact identifiers, ex. those attached over USB. Empty matches all devices.
+    pub identifiers: Vec<String>,
+    /// Only match devices of this platform, ex. "iPhoneOS". `macos` matches every Mac platform, unless exact.
+    pub platform: Option<String>,
+    /// Only match devices in any of these classes. Empty matches all devices.
+    pub classes: Vec<DeviceClass>,
+    /// Match exactly, instead of ignoring case and extra whitespace.
+    pub exact_match: bool,
+}
+
+impl DeviceFilter {
+    /// True if the device matches every part of the filter.
+    ///
+    /// Unless `exact_match` is set, matching ignores case and extra whitespace.
+    pub fn matches(&self, device: &Device) -> bool {
+        let exact = self.exact_match;
+
+        //Any filter term may match
+        (self.terms.is_empty() || self.terms.iter().any(|f| term_contains(&device.name, f, exact)))
+            //Matches exact identifiers as well, as they are a prefix of themselves
+            && self.identifier.as_ref().is_none_or(|i| term_starts_with(&device.identifier, i, exact))
+            && (self.identifiers.is_empty() || self.identifiers.contains(&device.identifier))
+            && self.platform.as_ref().is_none_or(|p| platform_matches(&device.platform, p, exact))
+            && (self.classes.is_empty() || self.classes.iter().any(|c| c.contains(device)))
+    }
+}
+
+/// True if `platform` is the platform `term` names, either directly or through [`PLATFORM_ALIASES`] unless `exact`.
+fn platform_matches(platform: &str, term: &str, exact: bool) -> bool {
+    let alias = if exact {
+        None
+    } else {
+        let term = normalize(term);
+        PLATFORM_ALIASES.iter().find(|(alias, _)| *alias == term)
+    };
+
+    match alias {
+        Some((_, platforms)) => platforms.contains(&normalize(platform).as_str()),
+        None => term_eq(platform, term, exact),
+    }
+}
+
+/// Firmware a device list entry pins its devices to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pin {
+    /// The newest firmware matching the other options, as if not pinned.
+    Latest,
+    /// Firmware of a version, ex. `17.5.1`.
+    Version(String),
+    /// Firmware with a build id, ex. `21F90`.
+    Build(String),
+}
+
+impl FromStr for Pin {
+    type Err = String;
+
+    /// Parses `latest`, a version or a build id. Build ids are told apart by containing a letter.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s.is_empty() || s.eq_ignore_ascii_case("latest") {
+            Ok(Pin::Latest)
+        } else if s.chars().any(|c| c.is_ascii_alphabetic()) {
+            Ok(Pin::Build(s.to_string()))
+        } else {
+            Ok(Pin::Version(s.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pin::Latest => f.write_str("latest"),
+            Pin::Version(version) => write!(f, "version {}", version),
+            Pin::Build(buildid) => write!(f, "build {}", buildid),
+        }
+    }
+}
+
+/// An entry of a device list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListEntry {
+    /// Identifier or name pattern of the devices, ex. `iPhone15,2` or `iPhone 15*`.
+    pub pattern: String,
+    /// Firmware to download for the devices.
+    pub pin: Pin,
+}
+
+/// Devices a device list names.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolvedList {
+    /// Identifiers of the listed devices in list order, without duplicates.
+    pub identifiers: Vec<String>,
+    /// Firmware pinned by identifier. Devices tracking the latest firmware aren't included.
+    pub pins: HashMap<String, Pin>,
+    /// Patterns that matched no device.
+    pub unmatched: Vec<String>,
+}
+
+/// Gets the entries of a device list, one identifier or name pattern per line, optionally pinned to a version or
+/// build with `= <version>`, ex. `iPhone15,2 = 17.5.1` or `iPad13,4 = latest`. Blank lines and `#` comments are
+/// skipped.
+pub fn parse_device_list(list: &str) -> Vec<ListEntry> {
+    list.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut parts = line.splitn(2, '=');
+            let pattern = parts.next().unwrap_or_default().trim().to_string();
+            //Parsing pins never fails
+            let pin = parts.next().map_or(Pin::Latest, |pin| pin.parse().unwrap_or(Pin::Latest));
+
+            ListEntry { pattern, pin }
+        })
+        .collect()
+}
+
+/// Resolves device list entries to the identifiers of the devices they name.
+///
+/// An entry matches a device if it is its identifier or name, ignoring case and extra whitespace, ex. `iPhone12,1` or
+/// `iPhone 11`. Entries with `*`, `?` or `[` are glob patterns matched against both, ex. `iPhone 15*`. If several
+/// entries match a device, the first one pins it.
+pub fn resolve_device_list(devices: &[Device], entries: &[ListEntry]) -> ResolvedList {
+    let options = MatchOptions {
+        case_sensitive: false,
+        ..MatchOptions::default()
+    };
+
+    let mut resolved = ResolvedList::default();
+    for entry in entries {
+        let pattern = if entry.pattern.contains(['*', '?', '[']) {
+            Pattern::new(&entry.pattern).ok()
+        } else {
+            None
+        };
+
+        let mut matched = false;
+        for device in devices {
+            let matches = match &pattern {
+                Some(pattern) => {
+                    pattern.matches_with(&device.identifier, options) || pattern.matches_with(&device.name, options)
+                }
+                None => {
+                    term_eq(&device.identifier, &entry.pattern, false) || term_eq(&device.name, &entry.pattern, false)
+                }
+            };
+            if !matches {
+                continue;
+            }
+
+            matched = true;
+            if !resolved.identifiers.contains(&device.identifier) {
+                resolved.identifiers.push(device.identifier.clone());
+                if entry.pin != Pin::Latest {
+                    resolved.pins.insert(device.identifier.clone(), entry.pin.clone());
+                }
+            }
+        }
+
+        if !matched {
+            resolved.unmatched.push(entry.pattern.clone());
+        }
+    }
+
+    resolved
+}
+
+/// Finds the devices whose name or identifier matches `term`, best match first.
+///
+/// Exact matches rank highest, then prefixes, then substrings, then fuzzy matches where the characters of the term
+/// appear in order, ex. "ip14pm" for "iPhone 14 Pro Max". Ties go to the shorter name.
+pub fn search<'a>(devices: &'a [Device], term: &str) -> Vec<&'a Device> {
+    let mut ranked: Vec<(u32, &Device)> = devices
+        .iter()
+        .filter_map(|device| {
+            let score = search_score(&device.name, term).max(search_score(&device.identifier, term))?;
+            Some((score, device))
+        })
+        .collect();
+
+    ranked.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .cmp(a_score)
+            .then_with(|| a.name.len().cmp(&b.name.len()))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    ranked.into_iter().map(|(_, device)| device).collect()
+}
+
+/// Scores how well `term` matches `haystack`. Higher is better, and `None` is no match at all.
+fn search_score(haystack: &str, term: &str) -> Option<u32> {
+    let haystack = normalize(haystack);
+    let term = normalize(term);
+
+    if term.is_empty() {
+        None
+    } else if haystack == term {
+        Some(1000)
+    } else if haystack.starts_with(&term) {
+        Some(800)
+    } else if haystack.contains(&term) {
+        Some(600)
+    } else {
+        fuzzy_score(&haystack, &term)
+    }
+}
+
+/// Scores `term` by how closely together its characters appear in order in `haystack`, ignoring whitespace.
+fn fuzzy_score(haystack: &str, term: &str) -> Option<u32> {
+    let mut haystack = haystack.chars();
+    let mut gaps = 0;
+
+    for (i, c) in term.chars().filter(|c| !c.is_whitespace()).enumerate() {
+        let skipped = haystack.by_ref().position(|h| h == c)?;
+
+        //Where the match starts doesn't matter, only how spread out it is
+        if i > 0 {
+            gaps += skipped as u32;
+        }
+    }
+
+    Some(500u32.saturating_sub(gaps * 10))
+}
+
+/// Normalizes a name or filter term for matching, by case folding, trimming and collapsing inner whitespace.
+fn normalize(term: &str) -> String {
+    term.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// True if `haystack` contains `term`. Both are normalized first unless `exact`.
+fn term_contains(haystack: &str, term: &str, exact: bool) -> bool {
+    if exact {
+        haystack.contains(term)
+    } else {
+        normalize(haystack).contains(&normalize(term))
+    }
+}
+
+/// True if `haystack` starts with `term`. Both are normalized first unless `exact`.
+fn term_starts_with(haystack: &str, term: &str, exact: bool) -> bool {
+    if exact {
+        haystack.starts_with(term)
+    } else {
+        normalize(haystack).starts_with(&normalize(term))
+    }
+}
+
+/// True if `haystack` equals `term`. Both are normalized first unless `exact`.
+fn term_eq(haystack: &str, term: &str, exact: bool) -> bool {
+    if exact {
+        haystack == term
+    } else {
+        normalize(haystack) == normalize(term)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolves_device_lists() {
+        let device = |name: &str, identifier: &str| Device {
+            name: name.to_string(),
+            identifier: identifier.to_string(),
+            platform: String::new(),
+            boardconfig: String::new(),
+            cpid: 0,
+            bdid: 0,
+        };
+        let devices = vec![
+            device("iPhone 11", "iPhone12,1"),
+            device("iPhone 15", "iPhone15,4"),
+            device("iPhone 15 Pro", "iPhone16,1"),
+            device("iPad Air 2 (WiFi)", "iPad5,3"),
+        ];
+
+        let entries = parse_device_list(
+            "# lab fleet\niphone12,1 = 17.5.1\n\niPhone 15*  # every 15\nipad air 2 (wifi) = 20H343\niPhone 99\n",
+        );
+        let patterns: Vec<&str> = entries.iter().map(|entry| entry.pattern.as_str()).collect();
+        assert_eq!(patterns, vec!["iphone12,1", "iPhone 15*", "ipad air 2 (wifi)", "iPhone 99"]);
+
+        let resolved = resolve_device_list(&devices, &entries);
+        assert_eq!(resolved.identifiers, vec!["iPhone12,1", "iPhone15,4", "iPhone16,1", "iPad5,3"]);
+        assert_eq!(resolved.pins.get("iPhone12,1"), Some(&Pin::Version("17.5.1".to_string())));
+        assert_eq!(resolved.pins.get("iPad5,3"), Some(&Pin::Build("20H343".to_string())));
+        assert_eq!(resolved.pins.get("iPhone15,4"), None);
+        assert_eq!(resolved.unmatched, vec!["iPhone 99"]);
+        assert_eq!("Latest".parse(), Ok(Pin::Latest));
+    }
+
+    #[test]
+    fn normalize_folds_case_and_whitespace() {
+        assert_eq!(normalize("  iPhone   14\tPro "), "iphone 14 pro");
+    }
+
+    #[test]
+    fn filter_terms_match_loosely_unless_exact() {
+        assert!(term_contains("iPhone 14 Pro", " iphone  14 ", false));
+        assert!(!term_contains("iPhone 14 Pro", "iphone 14", true));
+        assert!(term_starts_with("iPhone15,2", "IPHONE15", false));
+        assert!(term_eq("iPhoneOS", "iphoneos", false));
+        assert!(!term_eq("iPhoneOS", "iphoneos", true));
+    }
+
+    #[test]
+    fn classes_match_identifier_prefixes() {
+        let device = |identifier: &str| Device {
+            name: String::new(),
+            identifier: identifier.to_string(),
+            platform: String::new(),
+            boardconfig: String::new(),
+            cpid: 0,
+            bdid: 0,
+        };
+        let filter = DeviceFilter {
+            classes: vec!["iPad".parse().unwrap(), "HomePod".parse().unwrap()],
+            ..DeviceFilter::default()
+        };
+
+        assert!(filter.matches(&device("iPad13,4")));
+        assert!(filter.matches(&device("AudioAccessory5,1")));
+        assert!(!filter.matches(&device("iPod9,1")));
+        assert!("toaster".parse::<DeviceClass>().is_err());
+    }
+
+    #[test]
+    fn platform_aliases_match_every_mac() {
+        assert!(platform_matches("MacOS", "macos", false));
+        assert!(platform_matches("MacOSX", "macOS", false));
+        assert!(!platform_matches("iPhoneOS", "macos", false));
+        assert!(!platform_matches("MacOSX", "macos", true));
+        assert!(platform_matches("iPhoneOS", "iphoneos", false));
+    }
+
+    #[test]
+    fn search_ranks_closest_first() {
+        let device = |name: &str, identifier: &str| Device {
+            name: name.to_string(),
+            identifier: identifier.to_string(),
+            platform: "iPhoneOS".to_string(),
+            boardconfig: String::new(),
+            cpid: 0,
+            bdid: 0,
+        };
+        let devices = vec![
+            device("iPhone 14 Pro Max", "iPhone15,3"),
+            device("iPhone 14 Pro", "iPhone15,2"),
+            device("iPad Pro (12.9-inch)", "iPad6,7"),
+        ];
+
+        let names = |term| search(&devices, term).iter().map(|d| d.name.as_str()).collect::<Vec<_>>();
+
+        assert_eq!(names("iphone 14 pro"), vec!["iPhone 14 Pro", "iPhone 14 Pro Max"]);
+        assert_eq!(names("ip14pm"), vec!["iPhone 14 Pro Max"]);
+        assert_eq!(names("iPhone15,3"), vec!["iPhone 14 Pro Max"]);
+        assert!(names("watch").is_empty());
+    }
+}
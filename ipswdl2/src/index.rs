@@ -0,0 +1,185 @@
+//! SQLite index of downloaded firmware, kept in the download directory.
+//!
+//! The index records which build every file holds, so skip decisions don't depend on file names.
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+
+/// Name of the index file in the download directory.
+pub const INDEX_FILE_NAME: &str = ".ipswdl2.db";
+
+/// A downloaded firmware file.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub device: String,
+    pub identifier: String,
+    pub version: String,
+    pub buildid: String,
+    /// Hex encoded SHA1 of the file, as computed when it was downloaded.
+    pub sha1: String,
+    pub size: u64,
+    /// Path of the file, relative to the download directory.
+    pub path: PathBuf,
+    pub downloaded: DateTime<Utc>,
+}
+
+impl IndexEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(IndexEntry {
+            device: row.get("device")?,
+            identifier: row.get("identifier")?,
+            version: row.get("version")?,
+            buildid: row.get("buildid")?,
+            sha1: row.get("sha1")?,
+            size: row.get::<_, i64>("size")? as u64,
+            path: PathBuf::from(row.get::<_, String>("path")?),
+            downloaded: row.get("downloaded")?,
+        })
+    }
+}
+
+/// Index of the firmware downloaded to a directory.
+pub struct Index {
+    /// Connections aren't Sync, so every download task takes turns.
+    conn: Mutex<Connection>,
+}
+
+impl Index {
+    /// Opens the index in `download_path`, creating it and the directory if needed.
+    ///
+    /// # Returns
+    /// * Err(Io) - The download directory could not be created.
+    /// * Err(Index) - The database could not be opened or set up.
+    pub fn open(download_path: &Path) -> Result<Self> {
+        std::fs::create_dir_all(download_path)
+            .map_err(|why| Error::io("could not create download directory", download_path, why))?;
+
+        let conn = Connection::open(download_path.join(INDEX_FILE_NAME))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS firmware (
+                identifier TEXT NOT NULL,
+                buildid TEXT NOT NULL,
+                device TEXT NOT NULL,
+                version TEXT NOT NULL,
+                sha1 TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                path TEXT NOT NULL,
+                downloaded TEXT NOT NULL,
+                PRIMARY KEY (identifier, buildid)
+            );
+            CREATE INDEX IF NOT EXISTS firmware_sha1 ON firmware (sha1);",
+        )?;
+
+        Ok(Index { conn: Mutex::new(conn) })
+    }
+
+    /// Records a downloaded file, replacing any earlier entry for the same build.
+    pub fn record(&self, entry: &IndexEntry) -> Result<()> {
+        self.conn.lock().expect("index lock poisoned").execute(
+            "INSERT OR REPLACE INTO firmware (identifier, buildid, device, version, sha1, size, path, downloaded)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                entry.identifier,
+                entry.buildid,
+                entry.device,
+                entry.version,
+                entry.sha1,
+                entry.size as i64,
+                entry.path.to_string_lossy(),
+                entry.downloaded,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Gets the entry for a build of a device, if it was downloaded.
+    pub fn find(&self, identifier: &str, buildid: &str) -> Result<Option<IndexEntry>> {
+        let entry = self
+            .conn
+            .lock()
+            .expect("index lock poisoned")
+            .query_row(
+                "SELECT * FROM firmware WHERE identifier = ?1 AND buildid = ?2",
+                params![identifier, buildid],
+                IndexEntry::from_row,
+            )
+            .optional()?;
+
+        Ok(entry)
+    }
+
+    /// Gets the entries of files with a SHA1, whatever build or path they were recorded under. Case is ignored.
+    pub fn find_sha1(&self, sha1: &str) -> Result<Vec<IndexEntry>> {
+        let conn = self.conn.lock().expect("index lock poisoned");
+        let mut statement = conn.prepare("SELECT * FROM firmware WHERE sha1 = lower(?1) ORDER BY downloaded")?;
+        let entries = statement
+            .query_map(params![sha1], IndexEntry::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    /// Gets every entry, ordered by device and download date.
+    pub fn entries(&self) -> Result<Vec<IndexEntry>> {
+        let conn = self.conn.lock().expect("index lock poisoned");
+        let mut statement = conn.prepare("SELECT * FROM firmware ORDER BY device, downloaded")?;
+        let entries = statement
+            .query_map(params![], IndexEntry::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    /// Forgets the entry for a file, ex. after it was deleted. `path` is relative to the download directory.
+    pub fn remove_path(&self, path: &Path) -> Result<()> {
+        self.conn
+            .lock()
+            .expect("index lock poisoned")
+            .execute("DELETE FROM firmware WHERE path = ?1", params![path.to_string_lossy()])?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn record_and_find() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = Index::open(dir.path()).unwrap();
+
+        let mut entry = IndexEntry {
+            device: "iPhone 8".to_string(),
+            identifier: "iPhone10,1".to_string(),
+            version: "16.7.8".to_string(),
+            buildid: "20H343".to_string(),
+            sha1: "abc".to_string(),
+            size: 42,
+            path: PathBuf::from("iPhone 8/16.7.8.ipsw"),
+            downloaded: Utc.ymd(2024, 5, 13).and_hms(17, 0, 0),
+        };
+        index.record(&entry).unwrap();
+
+        //Recording the same build again replaces it
+        entry.sha1 = "def".to_string();
+        index.record(&entry).unwrap();
+
+        assert_eq!(index.find("iPhone10,1", "20H343").unwrap(), Some(entry.clone()));
+        assert_eq!(index.find("iPhone10,1", "20H999").unwrap(), None);
+        assert_eq!(index.find_sha1("DEF").unwrap(), vec![entry.clone()]);
+        assert!(index.find_sha1("abc").unwrap().is_empty());
+        assert_eq!(index.entries().unwrap().len(), 1);
+
+        index.remove_path(&entry.path).unwrap();
+        assert!(index.entries().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+//! Reading files out of downloaded ipsw archives.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Files describing a firmware's restore, extracted next to downloads when asked to.
+pub const MANIFEST_FILES: &[&str] = &["BuildManifest.plist", "Restore.plist"];
+
+/// A file inside an ipsw.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    /// Uncompressed size in bytes.
+    pub size: u64,
+    pub compressed_size: u64,
+}
+
+/// Restore information from the `Restore.plist` of an ipsw.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct RestoreInfo {
+    pub product_version: Option<String>,
+    pub product_build_version: Option<String>,
+    /// Identifiers of the devices the ipsw restores, ex. "iPhone10,1".
+    #[serde(default)]
+    pub supported_product_types: Vec<String>,
+    /// Boards the ipsw restores.
+    #[serde(default)]
+    pub device_map: Vec<Board>,
+    /// Ramdisk images by restore type, ex. "User" or "Update".
+    #[serde(default)]
+    pub restore_ram_disks: BTreeMap<String, String>,
+    /// Kernel caches by build type, ex. "Release".
+    #[serde(default)]
+    pub restore_kernel_caches: BTreeMap<String, String>,
+}
+
+/// A board an ipsw restores.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Board {
+    #[serde(rename = "BoardConfig")]
+    pub board_config: String,
+    #[serde(rename = "Platform")]
+    pub platform: Option<String>,
+    #[serde(rename = "CPID")]
+    pub cpid: Option<u32>,
+    #[serde(rename = "BDID")]
+    pub bdid: Option<u32>,
+}
+
+/// The contents of an ipsw.
+#[derive(Serialize, Debug)]
+pub struct Inspection {
+    pub entries: Vec<Entry>,
+    /// `None` if the ipsw has no `Restore.plist`.
+    pub restore: Option<RestoreInfo>,
+}
+
+/// Lists the entries of an ipsw and reads its `Restore.plist`, without blocking the async runtime.
+///
+/// # Returns
+/// * Err(Zip) - The ipsw isn't a readable zip archive.
+/// * Err(Plist) - The `Restore.plist` could not be parsed.
+pub async fn inspect(ipsw_path: PathBuf) -> Result<Inspection> {
+    tokio::task::spawn_blocking(move || -> Result<Inspection> {
+        let file = File::open(&ipsw_path).map_err(|why| Error::io("could not open", &ipsw_path, why))?;
+        let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+
+        let mut entries = Vec::new();
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i)?;
+            if entry.is_dir() {
+                continue;
+            }
+
+            entries.push(Entry {
+                name: entry.name().to_string(),
+                size: entry.size(),
+                compressed_size: entry.compressed_size(),
+            });
+        }
+
+        //Plists are read by seeking, which compressed entries can't do
+        let restore = match archive.by_name("Restore.plist") {
+            Ok(mut plist) => {
+                let mut contents = Vec::new();
+                plist
+                    .read_to_end(&mut contents)
+                    .map_err(|why| Error::io("could not read Restore.plist in", &ipsw_path, why))?;
+                Some(plist::from_reader(Cursor::new(contents))?)
+            }
+            Err(zip::result::ZipError::FileNotFound) => None,
+            Err(why) => return Err(why.into()),
+        };
+
+        Ok(Inspection { entries, restore })
+    })
+    .await
+    .expect("inspect task panicked")
+}
+
+/// Gets the directory the manifests of an ipsw are extracted to, ex. `16.7.8.plists` for `16.7.8.ipsw`.
+pub fn manifest_dir(ipsw_path: &Path) -> PathBuf {
+    ipsw_path.with_extension("plists")
+}
+
+/// Extracts [`MANIFEST_FILES`] from an ipsw into its [`manifest_dir`]. Files the ipsw lacks are skipped.
+///
+/// # Returns
+/// The paths of the extracted files.
+pub async fn extract_manifests(ipsw_path: &Path) -> Result<Vec<PathBuf>> {
+    extract(
+        ipsw_path.to_path_buf(),
+        manifest_dir(ipsw_path),
+        |name| MANIFEST_FILES.contains(&name),
+    )
+    .await
+}
+
+/// Extracts every entry of an ipsw whose name `select` accepts into `to`, keeping their directories,
+/// without blocking the async runtime.
+///
+/// # Returns
+/// * Ok(Vec) - The paths of the extracted files.
+/// * Err(Zip) - The ipsw isn't a readable zip archive.
+/// * Err(Io) - A file could not be read or written.
+pub async fn extract<F>(ipsw_path: PathBuf, to: PathBuf, select: F) -> Result<Vec<PathBuf>>
+where
+    F: Fn(&str) -> bool + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || -> Result<Vec<PathBuf>> {
+        let file = File::open(&ipsw_path).map_err(|why| Error::io("could not open", &ipsw_path, why))?;
+        let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+
+        let mut extracted = Vec::new();
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            if entry.is_dir() || !select(entry.name()) {
+                continue;
+            }
+
+            //Names escaping the output directory are never extracted
+            let path = match entry.enclosed_name() {
+                Some(name) => to.join(name),
+                None => continue,
+            };
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|why| Error::io("could not create directory for", &path, why))?;
+            }
+            let mut out = File::create(&path).map_err(|why| Error::io("could not create", &path, why))?;
+            std::io::copy(&mut entry, &mut out).map_err(|why| Error::io("could not extract", &path, why))?;
+
+            extracted.push(path);
+        }
+
+        Ok(extracted)
+    })
+    .await
+    .expect("extract task panicked")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn extracts_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let ipsw_path = dir.path().join("16.7.8.ipsw");
+
+        let mut writer = zip::ZipWriter::new(File::create(&ipsw_path).unwrap());
+        for name in &["BuildManifest.plist", "kernelcache.release.iphone10", "Firmware/Restore.plist"] {
+            writer.start_file(*name, Default::default()).unwrap();
+            writer.write_all(name.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        //Only the manifests at the root are wanted
+        let extracted = extract_manifests(&ipsw_path).await.unwrap();
+        assert_eq!(extracted, vec![dir.path().join("16.7.8.plists/BuildManifest.plist")]);
+        assert_eq!(fs::read_to_string(&extracted[0]).unwrap(), "BuildManifest.plist");
+    }
+
+    #[tokio::test]
+    async fn inspects_restore_plist() {
+        let dir = tempfile::tempdir().unwrap();
+        let ipsw_path = dir.path().join("16.7.8.ipsw");
+
+        let mut writer = zip::ZipWriter::new(File::create(&ipsw_path).unwrap());
+        writer.start_file("Restore.plist", Default::default()).unwrap();
+        writer
+            .write_all(
+                br#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict>
+    <key>ProductVersion</key><string>16.7.8</string>
+    <key>SupportedProductTypes</key><array><string>iPhone10,1</string></array>
+    <key>DeviceMap</key><array><dict>
+        <key>BoardConfig</key><string>d20ap</string>
+        <key>CPID</key><integer>32789</integer>
+    </dict></array>
+    <key>RestoreRamDisks</key><dict><key>User</key><string>096-12345-678.dmg</string></dict>
+</dict></plist>"#,
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let inspection = inspect(ipsw_path).await.unwrap();
+        assert_eq!(inspection.entries.len(), 1);
+        assert_eq!(inspection.entries[0].name, "Restore.plist");
+
+        let restore = inspection.restore.unwrap();
+        assert_eq!(restore.product_version.as_deref(), Some("16.7.8"));
+        assert_eq!(restore.device_map[0].board_config, "d20ap");
+        assert_eq!(restore.device_map[0].cpid, Some(32789));
+        assert_eq!(restore.restore_ram_disks["User"], "096-12345-678.dmg");
+    }
+}
@@ -0,0 +1,143 @@
+//! Mirroring iTunes installers, which Windows needs to restore devices.
+use std::path::{Path, PathBuf};
+
+use futures::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing::info;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+use crate::api_json_types::ITunesRelease;
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::output::DownloadResult;
+use crate::part::PartFile;
+use crate::paths::sanitize_file_name;
+use crate::version::compare_versions;
+
+/// Directory under the download directory iTunes installers are saved to.
+pub const ITUNES_DIR: &str = "iTunes";
+
+/// Gets the installer of a release, preferring the 64 bit one.
+pub fn installer_url(release: &ITunesRelease) -> Option<&str> {
+    release.url_64bit.as_deref().or(release.url.as_deref())
+}
+
+/// Gets the newest release that has an installer.
+pub fn newest_release(releases: &[ITunesRelease]) -> Option<&ITunesRelease> {
+    releases
+        .iter()
+        .filter(|release| installer_url(release).is_some())
+        .max_by(|a, b| compare_versions(&a.version, &b.version))
+}
+
+/// Gets the path the installer at `url` is saved to, ex. `iTunes/12.13.1.3/iTunes64Setup.exe`.
+pub fn installer_path(download_path: &Path, release: &ITunesRelease, url: &str) -> PathBuf {
+    let file_name = url
+        .split('?')
+        .next()
+        .and_then(|url| url.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("iTunesSetup.exe");
+
+    download_path
+        .join(ITUNES_DIR)
+        .join(sanitize_file_name(&release.version))
+        .join(sanitize_file_name(file_name))
+}
+
+/// Downloads the installer of the newest iTunes release for `platform`, ex. "windows", unless already downloaded.
+///
+/// # Returns
+/// * Ok(Downloaded | Skipped) - The installer was downloaded, or already was.
+/// * Err(NotFound) - No release for the platform has an installer.
+/// * Err - The API or download failed.
+pub async fn download_newest(
+    client: &Client,
+    download_path: &Path,
+    platform: &str,
+    show_progress: bool,
+) -> Result<DownloadResult> {
+    let device = format!("iTunes ({})", platform);
+    let releases = client.get_itunes_releases(platform).await?;
+    let release =
+        newest_release(&releases).ok_or_else(|| Error::NotFound(format!("iTunes installer for {}", platform)))?;
+    let url = installer_url(release).expect("newest release has an installer");
+    let path = installer_path(download_path, release, url);
+
+    if path.exists() {
+        return Ok(DownloadResult::Skipped {
+            device,
+            version: Some(release.version.clone()),
+            path: Some(path),
+            reason: "already downloaded".to_string(),
+        });
+    }
+
+    info!("downloading iTunes {} from {}", release.version, url);
+    let mut part_file = PartFile::open(&path)?;
+    let part_path = part_file.path().to_path_buf();
+    let file = part_file.as_file_mut();
+    file.set_len(0).map_err(|why| Error::io("could not truncate", &part_path, why))?;
+
+    let (mut stream, _, size) = client.download_url(url, &format!("iTunes {}", release.version), 0).await?;
+
+    let bar = if show_progress { ProgressBar::new(size) } else { ProgressBar::hidden() };
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{prefix} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec}")
+            .progress_chars("#>-"),
+    );
+    bar.set_prefix(format!("iTunes {}", release.version));
+
+    let file = file.try_clone().map_err(|why| Error::io("could not open", &part_path, why))?;
+    let mut writer = BufWriter::new(tokio::fs::File::from_std(file));
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        bar.inc(chunk.len() as u64);
+        writer.write_all(&chunk).await.map_err(|why| Error::io("could not write", &part_path, why))?;
+    }
+    writer.flush().await.map_err(|why| Error::io("could not write", &part_path, why))?;
+    bar.finish_and_clear();
+
+    let size = part_file.persist(&path)?;
+
+    Ok(DownloadResult::Downloaded {
+        device,
+        version: release.version.clone(),
+        buildid: release.version.clone(),
+        size,
+        path,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn release(version: &str, url: Option<&str>, url_64bit: Option<&str>) -> ITunesRelease {
+        ITunesRelease {
+            platform: "WINDOWS".to_string(),
+            version: version.to_string(),
+            url: url.map(str::to_string),
+            url_64bit: url_64bit.map(str::to_string),
+            releasedate: None,
+            uploaddate: None,
+        }
+    }
+
+    #[test]
+    fn picks_newest_installer() {
+        let releases = vec![
+            release("12.9.0.1", Some("https://example.com/iTunesSetup.exe"), None),
+            release("12.10.0.1", None, Some("https://example.com/a/iTunes64Setup.exe?x=1")),
+            release("12.11.0.1", None, None),
+        ];
+
+        let newest = newest_release(&releases).unwrap();
+        assert_eq!(newest.version, "12.10.0.1");
+        assert_eq!(
+            installer_path(Path::new("ipsw"), newest, installer_url(newest).unwrap()),
+            Path::new("ipsw/iTunes/12.10.0.1/iTunes64Setup.exe")
+        );
+    }
+}
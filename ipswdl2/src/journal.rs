@@ -0,0 +1,208 @@
+//! State of runs kept in the download directory: the journal of the devices a run has processed, so a crashed run can
+//! be resumed, and the devices the last run failed on, so only they can be retried.
+//!
+//! The journal is rewritten as each device starts and finishes, and deleted once the run completes. A journal left
+//! behind means the run was interrupted.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::error::{Error, Result};
+
+/// Name of the journal file in the download directory.
+pub const JOURNAL_FILE_NAME: &str = ".ipswdl2.journal.json";
+
+/// Name of the file in the download directory listing the devices the last run failed on.
+pub const FAILED_FILE_NAME: &str = ".ipswdl2.failed.json";
+
+/// A device a run failed on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FailedDevice {
+    pub identifier: String,
+    pub device: String,
+    /// Kind of the error, as in [`Error::kind`], ex. `stalled`.
+    pub error_kind: String,
+    pub error: String,
+}
+
+/// Records the devices a run failed on in `download_path`, replacing those of the last run.
+pub fn save_failures(download_path: &Path, failures: &[FailedDevice]) -> Result<()> {
+    let path = download_path.join(FAILED_FILE_NAME);
+    if failures.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(why) if why.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::io("could not delete failed devices", &path, why))
+            }
+            _ => Ok(()),
+        };
+    }
+
+    let json = serde_json::to_string_pretty(failures)?;
+    std::fs::write(&path, json).map_err(|why| Error::io("could not write failed devices to", &path, why))
+}
+
+/// Gets the devices the last run in `download_path` failed on, empty if it failed on none.
+pub fn load_failures(download_path: &Path) -> Result<Vec<FailedDevice>> {
+    let path = download_path.join(FAILED_FILE_NAME);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let json = std::fs::read_to_string(&path).map_err(|why| Error::io("could not read failed devices", &path, why))?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// How far a run got with a device.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceState {
+    /// Not started yet.
+    Pending,
+    /// Started, but not finished. Downloads of it may have been cut off.
+    InProgress,
+    /// Finished, whether or not its downloads succeeded.
+    Done,
+}
+
+/// Contents of the journal file.
+#[derive(Serialize, Deserialize, Debug)]
+struct Entries {
+    /// When the run started.
+    started: DateTime<Utc>,
+    /// State of each device of the run by identifier.
+    devices: BTreeMap<String, DeviceState>,
+}
+
+/// Journal of a run.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    entries: Mutex<Entries>,
+}
+
+impl Journal {
+    /// Starts the journal of a new run over the devices with `identifiers` in `download_path`, replacing any left by
+    /// an earlier run.
+    pub fn start<'a>(download_path: &Path, identifiers: impl IntoIterator<Item = &'a str>) -> Self {
+        let entries = Entries {
+            started: Utc::now(),
+            devices: identifiers.into_iter().map(|id| (id.to_string(), DeviceState::Pending)).collect(),
+        };
+        let journal = Journal {
+            path: download_path.join(JOURNAL_FILE_NAME),
+            entries: Mutex::new(entries),
+        };
+
+        journal.save();
+        journal
+    }
+
+    /// Opens the journal an interrupted run left in `download_path`, if any.
+    ///
+    /// # Returns
+    /// * Ok(None) - No run was interrupted.
+    /// * Err(Io | Json) - The journal could not be read.
+    pub fn resume(download_path: &Path) -> Result<Option<Self>> {
+        let path = download_path.join(JOURNAL_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let json = std::fs::read_to_string(&path).map_err(|why| Error::io("could not read journal", &path, why))?;
+        let entries = serde_json::from_str(&json)?;
+
+        Ok(Some(Journal {
+            path,
+            entries: Mutex::new(entries),
+        }))
+    }
+
+    /// When the run started.
+    pub fn started(&self) -> DateTime<Utc> {
+        self.entries.lock().expect("journal lock poisoned").started
+    }
+
+    /// True if the run already finished the device with `identifier`.
+    pub fn is_done(&self, identifier: &str) -> bool {
+        let entries = self.entries.lock().expect("journal lock poisoned");
+        entries.devices.get(identifier) == Some(&DeviceState::Done)
+    }
+
+    /// Records how far the run got with the device with `identifier`.
+    pub fn set(&self, identifier: &str, state: DeviceState) {
+        self.entries
+            .lock()
+            .expect("journal lock poisoned")
+            .devices
+            .insert(identifier.to_string(), state);
+        self.save();
+    }
+
+    /// Deletes the journal once the run completed, so it isn't resumed.
+    pub fn finish(&self) {
+        if let Err(why) = std::fs::remove_file(&self.path) {
+            warn!("could not delete journal {}: {}", self.path.display(), why);
+        }
+    }
+
+    /// Writes the journal. Failures are only logged, as the run itself is fine.
+    fn save(&self) {
+        //Held while writing, so concurrent devices don't write over each other
+        let entries = self.entries.lock().expect("journal lock poisoned");
+        let json = serde_json::to_string_pretty(&*entries).expect("journal serializes");
+
+        //Written aside and renamed, so a crash mid write never leaves a broken journal
+        let temp = PathBuf::from(format!("{}.tmp", self.path.display()));
+        let written = std::fs::write(&temp, json).and_then(|_| std::fs::rename(&temp, &self.path));
+        if let Err(why) = written {
+            error!("could not write journal {}: {}", self.path.display(), why);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resumes_interrupted_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Journal::resume(dir.path()).unwrap().is_none());
+
+        let journal = Journal::start(dir.path(), vec!["iPhone10,1", "iPhone10,4", "iPad5,3"]);
+        journal.set("iPhone10,1", DeviceState::Done);
+        journal.set("iPhone10,4", DeviceState::InProgress);
+        drop(journal);
+
+        let resumed = Journal::resume(dir.path()).unwrap().unwrap();
+        assert!(resumed.is_done("iPhone10,1"));
+        assert!(!resumed.is_done("iPhone10,4"));
+        assert!(!resumed.is_done("iPad5,3"));
+
+        resumed.finish();
+        assert!(Journal::resume(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn saves_failures_of_the_last_run() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_failures(dir.path()).unwrap().is_empty());
+
+        let failures = vec![FailedDevice {
+            identifier: "iPhone10,1".to_string(),
+            device: "iPhone 8".to_string(),
+            error_kind: "stalled".to_string(),
+            error: "download stalled".to_string(),
+        }];
+        save_failures(dir.path(), &failures).unwrap();
+        assert_eq!(load_failures(dir.path()).unwrap(), failures);
+
+        //A run without failures clears them
+        save_failures(dir.path(), &[]).unwrap();
+        assert!(load_failures(dir.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+//! Library for downloading Apple IPhone SoftWare (IPSW) files using the [ipsw.me](https://ipsw.me) API.
+//!
+//! [`Client`] provides raw access to the API, while [`Downloader`] downloads firmware for many devices at once.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use ipswdl2::{Client, DeviceFilter, DownloadOptions, Downloader};
+//!
+//! # async fn run() -> ipswdl2::Result<()> {
+//! let client = Client::new()?;
+//! let devices = client.get_all_devices().await?;
+//!
+//! let options = DownloadOptions {
+//!     filter: DeviceFilter {
+//!         terms: vec!["iPhone 15".to_string()],
+//!         ..DeviceFilter::default()
+//!     },
+//!     ..DownloadOptions::default()
+//! };
+//!
+//! Downloader::new(client, devices, options)?.begin().await;
+//! # Ok(())
+//! # }
+//! ```
+pub mod api_json_types;
+pub mod beta;
+pub mod cache;
+pub mod cancel;
+pub mod client;
+pub mod config;
+pub mod connected;
+pub mod desktop;
+pub mod downloader;
+pub mod email;
+pub mod error;
+pub mod export;
+pub mod filter;
+pub mod index;
+pub mod ipsw;
+pub mod itunes;
+pub mod journal;
+pub mod lock;
+pub mod logging;
+pub mod metadata;
+pub mod metrics;
+pub mod output;
+mod part;
+pub mod paths;
+pub mod rate_limit;
+pub mod remote;
+pub mod s3;
+pub mod serve;
+pub mod sftp;
+pub mod shsh;
+pub mod sums;
+pub mod sync;
+pub mod units;
+pub mod verify;
+pub mod version;
+pub mod webdav;
+pub mod webhook;
+
+pub use cancel::CancellationToken;
+pub use client::{Client, ClientOptions};
+pub use downloader::{DownloadOptions, Downloader};
+pub use error::{Error, Result};
+pub use filter::DeviceFilter;
@@ -0,0 +1,135 @@
+//! Lockfile keeping two runs from downloading into the same directory at once.
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, error, info};
+
+use crate::error::{Error, Result};
+
+/// Name of the lockfile in the download directory.
+pub const LOCK_FILE_NAME: &str = ".ipswdl2.lock";
+
+/// Holds the lock on a download directory until dropped. The lockfile holds the pid of the process owning it.
+#[derive(Debug)]
+pub struct DirLock {
+    path: PathBuf,
+}
+
+impl DirLock {
+    /// Locks `dir`, creating it if needed.
+    ///
+    /// Locks left behind by processes that are no longer running, ex. after a crash, are taken over.
+    ///
+    /// # Returns
+    /// * Err(Locked) - A running process holds the lock.
+    /// * Err(Io) - The directory or lockfile could not be created.
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).map_err(|why| Error::io("could not create download directory", dir, why))?;
+        let path = dir.join(LOCK_FILE_NAME);
+
+        //A second attempt is only made after removing a stale lock
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id()).map_err(|why| Error::io("could not write lockfile", &path, why))?;
+                    debug!("locked {}", dir.display());
+                    return Ok(DirLock { path });
+                }
+                Err(why) if why.kind() == ErrorKind::AlreadyExists => {
+                    let owner = fs::read_to_string(&path).ok().and_then(|pid| pid.trim().parse::<u32>().ok());
+
+                    match owner {
+                        Some(pid) if is_running(pid) => {
+                            return Err(Error::Locked {
+                                dir: dir.to_path_buf(),
+                                pid,
+                            })
+                        }
+                        _ => {
+                            info!("removing stale lock {} of {:?}", path.display(), owner);
+                            fs::remove_file(&path).map_err(|why| Error::io("could not remove stale lockfile", &path, why))?;
+                        }
+                    }
+                }
+                Err(why) => return Err(Error::io("could not create lockfile", &path, why)),
+            }
+        }
+
+        Err(Error::io(
+            "could not create lockfile",
+            &path,
+            ErrorKind::AlreadyExists.into(),
+        ))
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        if let Err(why) = fs::remove_file(&self.path) {
+            error!("could not remove lockfile {}: {}", self.path.display(), why);
+        }
+    }
+}
+
+/// True if a process with this pid is running.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    use std::convert::TryFrom;
+
+    //0 and negative pids would address process groups, which no lockfile holds
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(pid) if pid > 0 => pid,
+        _ => return false,
+    };
+
+    //Signal 0 only checks that the process exists. EPERM means it does, but belongs to another user
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+
+    std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+/// True if a process with this pid is running.
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .arg("/FI")
+        .arg(format!("PID eq {}", pid))
+        .arg("/NH")
+        .output()
+        .map_or(true, |output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locks_directories() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let lock = DirLock::acquire(dir.path()).unwrap();
+        assert!(matches!(DirLock::acquire(dir.path()), Err(Error::Locked { .. })));
+
+        drop(lock);
+        assert!(!dir.path().join(LOCK_FILE_NAME).exists());
+        DirLock::acquire(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn takes_over_stale_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(LOCK_FILE_NAME), "not a pid").unwrap();
+
+        DirLock::acquire(dir.path()).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn keeps_locks_of_other_users() {
+        //init is always running, and signalling it fails with EPERM unless running as root
+        assert!(is_running(1));
+    }
+}
@@ -0,0 +1,278 @@
+//! Logging to the console and the log file, built on `tracing`.
+//!
+//! Besides free-form text, logs can be written as JSON lines for log aggregation systems. Fields attached to events,
+//! ex. `info!(device = name, bytes = size, "downloaded {}", name)`, become fields of the JSON object so they can be
+//! indexed.
+//!
+//! Log files are appended to across runs, and can be rotated by size or day so long-running watch mode doesn't fill
+//! the disk.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Local, NaiveDate};
+use tracing::level_filters::LevelFilter;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::error::{Error, Result};
+
+/// Format of the log file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogFormat {
+    /// Free-form lines, for people.
+    Text,
+    /// One JSON object per line, with structured fields.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format: {}", s)),
+        }
+    }
+}
+
+/// Gets the console log level for a number of `-v` flags. Errors and warnings are always logged.
+pub fn verbosity_level(verbose: u64) -> LevelFilter {
+    match verbose {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Builds a layer logging events at `level` and above to stderr, colored if `ansi` is set.
+pub fn console_layer<S>(level: LevelFilter, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Box::new(
+        tracing_subscriber::fmt::layer()
+            .with_writer(io::stderr)
+            .with_ansi(ansi)
+            .with_target(false)
+            .with_filter(level),
+    )
+}
+
+/// Builds a layer logging events at `level` and above to `writer` in `format`.
+///
+/// JSON lines hold the fields of each event at the top level, next to the span the event happened in, ex. the
+/// `download` span of a firmware with its version, size, speed and outcome.
+pub fn file_layer<S, W>(format: LogFormat, level: LevelFilter, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(false);
+
+    match format {
+        LogFormat::Text => Box::new(layer.with_filter(level)),
+        LogFormat::Json => Box::new(layer.json().flatten_event(true).with_current_span(true).with_filter(level)),
+    }
+}
+
+/// When a log file is rotated.
+#[derive(Clone, Copy, Debug)]
+pub struct Rotation {
+    /// Rotate once the file grows past this many bytes.
+    pub max_size: Option<u64>,
+    /// Rotate when the first line of a new day is written.
+    pub daily: bool,
+    /// Rotated files to keep, as `<file>.1` (newest) to `<file>.<keep>`. Older ones are deleted.
+    pub keep: usize,
+}
+
+impl Rotation {
+    /// True if nothing ever causes a rotation.
+    fn is_never(&self) -> bool {
+        self.max_size.is_none() && !self.daily
+    }
+}
+
+/// A log file appended to across runs, moved aside and started over according to its [`Rotation`].
+///
+/// Files are only rotated between lines, so no line is ever split across two files.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    /// Bytes in the current file.
+    size: u64,
+    /// Day the current file was last written to.
+    day: NaiveDate,
+    /// True if the last write ended a line.
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    /// Opens the log file at `path` for appending, creating it if needed.
+    ///
+    /// # Returns
+    /// * Err(Io) - The file could not be opened.
+    pub fn open(path: &Path, rotation: Rotation) -> Result<Self> {
+        let file = append(path)?;
+        let metadata = file.metadata().map_err(|why| Error::io("could not read metadata of", path, why))?;
+        //Lines written on an earlier day rotate away with the first line of today
+        let day = metadata
+            .modified()
+            .map(|modified| DateTime::<Local>::from(modified).naive_local().date())
+            .unwrap_or_else(|_| Local::now().naive_local().date());
+
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            rotation,
+            file,
+            size: metadata.len(),
+            day,
+            at_line_start: true,
+        })
+    }
+
+    /// True if the current file is due to be rotated before writing `len` more bytes.
+    fn should_rotate(&self, len: usize, today: NaiveDate) -> bool {
+        if !self.at_line_start || self.size == 0 || self.rotation.is_never() {
+            return false;
+        }
+
+        let too_large = self.rotation.max_size.is_some_and(|max| self.size + len as u64 > max);
+        let new_day = self.rotation.daily && today != self.day;
+        too_large || new_day
+    }
+
+    /// Shifts every rotated file up by one, moves the current file to `<file>.1`, and starts a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            //The oldest is overwritten by the one after it
+            for n in (1..self.rotation.keep).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let today = Local::now().naive_local().date();
+        if self.should_rotate(buf.len(), today) {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        self.day = today;
+        if written > 0 {
+            self.at_line_start = buf[written - 1] == b'\n';
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Opens a file for appending, creating it if needed.
+fn append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|why| Error::io("could not open log file", path, why))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn json_fields_are_top_level() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = {
+            let buf = buf.clone();
+            move || SharedBuf(buf.clone())
+        };
+
+        let subscriber = tracing_subscriber::registry().with(file_layer(LogFormat::Json, LevelFilter::DEBUG, writer));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::trace!("below the file level");
+            let span = tracing::info_span!("download", version = "16.7.8");
+            let _entered = span.enter();
+            tracing::info!(device = "iPhone 8", bytes = 1024u64, "downloaded {}", "iPhone 8");
+        });
+
+        let line: serde_json::Value = serde_json::from_slice(&buf.lock().unwrap()).unwrap();
+        assert_eq!(line["message"], "downloaded iPhone 8");
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["device"], "iPhone 8");
+        assert_eq!(line["bytes"], 1024);
+        assert_eq!(line["span"]["version"], "16.7.8");
+    }
+
+    /// Log output shared with the test.
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rotates_by_size_between_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ipswdl2.log");
+        let rotation = Rotation {
+            max_size: Some(10),
+            daily: false,
+            keep: 2,
+        };
+
+        let mut file = RotatingFile::open(&path, rotation).unwrap();
+        //Lines are never split, even if they cross the limit
+        file.write_all(b"first ").unwrap();
+        file.write_all(b"line\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+        file.write_all(b"third\n").unwrap();
+        file.write_all(b"fourth\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(dir.path().join("ipswdl2.log.1")).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(dir.path().join("ipswdl2.log.2")).unwrap(), "second\n");
+        assert!(!dir.path().join("ipswdl2.log.3").exists());
+
+        //Later runs append to the same file
+        drop(file);
+        RotatingFile::open(&path, rotation).unwrap().write_all(b"x\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\nx\n");
+    }
+}
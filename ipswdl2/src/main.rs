@@ -1,72 +1,1437 @@
 
-use std::fs::File;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
-use log::{info, LevelFilter};
-use simplelog::{Config, WriteLogger};
+use chrono::NaiveDate;
+use colored::Colorize;
+use glob::Pattern;
+use indicatif::HumanBytes;
+use tracing::level_filters::LevelFilter;
+use tracing::{error, info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use structopt::clap::AppSettings;
 use structopt::*;
 
-use crate::client::Client;
-use crate::downloader::Downloader;
-
-mod client;
-mod api_json_types;
-mod downloader;
+use ipswdl2::api_json_types::Device;
+use ipswdl2::beta::{Channel, DEFAULT_BETA_SOURCE};
+use ipswdl2::cache::Cache;
+use ipswdl2::config::Config;
+use ipswdl2::connected::connected_devices;
+use ipswdl2::client::{parse_header, DEFAULT_API_URL, DEFAULT_CDN_URL, DEFAULT_USER_AGENT};
+use ipswdl2::downloader::{DownloadOrder, DEFAULT_PROGRESS_TEMPLATE};
+use ipswdl2::export::{library_manifest, write_manifest, ExportFormat};
+use ipswdl2::filter::{parse_device_list, resolve_device_list, search, DeviceClass, ResolvedList};
+use ipswdl2::ipsw;
+use ipswdl2::itunes;
+use ipswdl2::journal::load_failures;
+use ipswdl2::logging::{self, verbosity_level, LogFormat, RotatingFile, Rotation};
+use ipswdl2::metadata::MetadataFormat;
+use ipswdl2::output::{
+    format_table, print_json, DeviceColumn, DownloadResult, OutputFormat, ProgressFormat, SigningStatus,
+};
+use ipswdl2::paths::{DirBy, Layout, PathTemplate};
+use ipswdl2::remote::{is_remote, Remote};
+use ipswdl2::s3::{S3Bucket, S3Credentials, DEFAULT_REGION};
+use ipswdl2::sftp::Sftp;
+use ipswdl2::sync::{sync, SyncAction, SyncTarget};
+use ipswdl2::serve::DEFAULT_SERVE_ADDR;
+use ipswdl2::shsh::BlobOptions;
+use ipswdl2::units::{parse_bytes, parse_duration};
+use ipswdl2::verify::{verify_listing, Checksum, VerifyStatus};
+use ipswdl2::version::{compare_versions, newest_first};
+use ipswdl2::webdav::WebDav;
+use ipswdl2::webhook::WebhookFormat;
+use ipswdl2::{CancellationToken, Client, ClientOptions, DeviceFilter, DownloadOptions, Downloader, Error, Result};
 
 /// Downloads the newest .ipsw for Apple devices
 #[derive(StructOpt)]
-pub struct CliOpts {
-    /// Directory to download .ipsw files to.
+#[structopt(global_settings = &[AppSettings::VersionlessSubcommands])]
+struct CliOpts {
+    /// Filename to log to. Will not log if not set.
+    #[structopt(short, long, global = true)]
+    log_path: Option<PathBuf>,
+
+    /// Level of the log file.
+    #[structopt(long, global = true, default_value="debug", possible_values(&["off", "error", "warn", "info", "debug", "trace"]))]
+    log_level: LevelFilter,
+
+    /// Log more to stderr, where errors and warnings always go. -v logs info, -vv debug and -vvv everything.
+    /// Independent of the log file's --log-level.
+    #[structopt(short, long, global = true, parse(from_occurrences))]
+    verbose: u64,
+
+    /// Rotate the log file once it grows past this size, ex. "10MB".
+    #[structopt(long, global = true, parse(try_from_str = parse_bytes))]
+    log_max_size: Option<u64>,
+
+    /// Rotate the log file daily.
+    #[structopt(long, global = true)]
+    log_rotate_daily: bool,
+
+    /// Rotated log files to keep, as <log>.1 (newest) to <log>.N.
+    #[structopt(long, global = true, default_value="5")]
+    log_keep: usize,
+
+    /// Format of the log file. `json` writes one JSON object per line, with fields like device, version and bytes.
+    #[structopt(long, global = true, default_value="text", possible_values(&["text", "json"]))]
+    log_format: LogFormat,
+
+    /// Format to print results in. `json` prints one JSON object per line, for use in scripts. `csv` prints listings
+    /// of devices and firmware as CSV, for spreadsheets.
+    #[structopt(short, long, global = true, default_value="text", possible_values(&["text", "json", "csv"]))]
+    output: OutputFormat,
+
+    /// Only print errors.
+    #[structopt(short, long, global = true)]
+    quiet: bool,
+
+    /// Never color output. Color is also disabled when stdout isn't a terminal, or NO_COLOR is set.
+    #[structopt(long, global = true)]
+    no_color: bool,
+
+    /// Config file to read. Defaults to ipswdl2/config.toml in the users config directory, if it exists.
+    #[structopt(long, global = true, env = "IPSWDL2_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Base URL of the ipsw.me API, ex. to use a mirror or self-hosted instance.
+    #[structopt(long, global = true, env = "IPSWDL2_API_URL", default_value = DEFAULT_API_URL)]
+    api_url: String,
+
+    /// Time to wait for a connection before giving up, ex. "30s".
+    #[structopt(long, global = true, default_value="30s", parse(try_from_str = parse_duration))]
+    connect_timeout: Duration,
+
+    /// Time to wait for an API response, or for more data while downloading, before giving up and retrying, ex. "60s".
+    #[structopt(long, global = true, default_value="60s", parse(try_from_str = parse_duration))]
+    read_timeout: Duration,
+
+    /// Idle connections kept open to each server for reuse. Raise it with many parallel downloads.
+    #[structopt(long, global = true, default_value="8")]
+    pool_size: usize,
+
+    /// Directory to cache API responses in. Defaults to the users cache directory.
+    #[structopt(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// Don't cache API responses.
+    #[structopt(long, global = true)]
+    no_cache: bool,
+
+    /// Use cached device lists and firmware listings younger than this without asking the API, ex. "1h".
+    #[structopt(long, global = true, parse(try_from_str = parse_duration))]
+    cache_ttl: Option<Duration>,
+
+    /// Ask the API for everything, even if the cache is younger than --cache-ttl.
+    #[structopt(long, global = true)]
+    refresh: bool,
+
+    /// User-Agent to send with every request.
+    #[structopt(long, global = true, default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+
+    /// Extra header to send with every request, as "name:value". Can be repeated.
+    #[structopt(short="H", long, global = true, number_of_values(1), parse(try_from_str = parse_header))]
+    header: Vec<(String, String)>,
+
+    #[structopt(subcommand)]
+    cmd: Command
+}
+
+#[derive(StructOpt)]
+//Only ever parsed once, so the size of the download args doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    /// Download ipsw files. Downloads the newest firmware for every device unless filtered.
+    Download(DownloadArgs),
+    /// List all devices that could be downloaded, as a table of their name, identifier, platform and board.
+    ListDevices {
+        /// Columns to show, in order, ex. "identifier,boardconfig".
+        #[structopt(
+            long,
+            use_delimiter = true,
+            default_value="name,identifier,platform,boardconfig",
+            possible_values(&["name", "identifier", "platform", "boardconfig"])
+        )]
+        columns: Vec<DeviceColumn>,
+    },
+    /// List every firmware available for a single device.
+    ListFirmwares {
+        /// Name or identifier of the device, ex. "iPhone 14 Pro" or "iPhone15,2".
+        device: String
+    },
+    /// Find devices by name or identifier, best match first. Letters may be skipped, ex. "ip14pm" for "iPhone 14 Pro Max".
+    Search {
+        term: String,
+
+        /// Most results to show.
+        #[structopt(short="n", long, default_value="10")]
+        limit: usize
+    },
+    /// Print details of a single device: identifiers, board, chip ids, and its firmware.
+    DeviceInfo {
+        /// Name or identifier of the device, ex. "iPhone 14 Pro" or "iPhone15,2".
+        device: String
+    },
+    /// Look up the decryption keys of a firmware build.
+    Keys {
+        /// Name or identifier of the device, ex. "iPhone 8" or "iPhone10,1".
+        device: String,
+
+        /// Build id of the firmware, ex. 20H343. Defaults to the newest build.
+        #[structopt(short, long)]
+        buildid: Option<String>
+    },
+    /// Report which firmware Apple still signs, for a device or for everything downloaded.
+    SigningStatus(SigningStatusArgs),
+    /// Check downloaded ipsw files against the checksums reported by the API.
+    Verify(VerifyArgs),
+    /// Print every downloaded firmware (device, version, build, size, checksum and path) as JSON or CSV.
+    Export(ExportArgs),
+    /// Copy new and changed firmware from the download directory to another directory or remote, checking checksums.
+    Sync(SyncArgs),
+    /// Serve the download directory over HTTP, with an index page and a JSON listing at /index.json.
+    Serve(ServeArgs),
+    /// Extract files out of a local ipsw, ex. `extract 16.7.8.ipsw 'kernelcache*' '*.dmg'`.
+    Extract(ExtractArgs),
+    /// List the files inside a local ipsw with their sizes, and restore information from its Restore.plist.
+    Inspect {
+        /// The ipsw file to inspect.
+        ipsw: PathBuf
+    },
+    /// Check whether the API and Apple's CDN can be reached and how fast they answer, ex. to tell network problems
+    /// apart from API outages.
+    StatusApi {
+        /// URL on Apple's CDN to check.
+        #[structopt(long, default_value=DEFAULT_CDN_URL)]
+        cdn_url: String,
+    },
+}
+
+/// Options selecting which devices to work on.
+#[derive(StructOpt)]
+struct FilterArgs {
+    /// Only use devices whose name matches the term. Can be repeated to match any of several terms.
+    #[structopt(short, long, number_of_values(1))]
+    filter_term: Vec<String>,
+
+    /// Only use devices whose identifier is or starts with the term, ex. "iPhone15,2" or "iPhone15".
+    #[structopt(short, long)]
+    identifier: Option<String>,
+
+    /// Only use devices of a platform, ex. "iPhoneOS" or "AppleTVOS". "macos" selects every Mac, including Apple Silicon.
+    #[structopt(short="P", long)]
+    platform: Option<String>,
+
+    /// Only use devices of a product family. Can be repeated to match any of several classes.
+    #[structopt(
+        short="C",
+        long,
+        number_of_values(1),
+        possible_values(&["iphone", "ipad", "ipod", "appletv", "homepod", "watch"]),
+        case_insensitive(true)
+    )]
+    class: Vec<DeviceClass>,
+
+    /// Match device filters exactly, instead of ignoring case and extra whitespace.
+    #[structopt(short="x", long)]
+    exact_match: bool
+}
+
+impl FilterArgs {
+    /// Gets the filter described by these options.
+    fn device_filter(&self) -> DeviceFilter {
+        DeviceFilter {
+            terms: self.filter_term.clone(),
+            identifier: self.identifier.clone(),
+            identifiers: Vec::new(),
+            platform: self.platform.clone(),
+            classes: self.class.clone(),
+            exact_match: self.exact_match,
+        }
+    }
+}
+
+/// Options for the layout of files in the download directory. Download and verify must be given the same ones.
+#[derive(StructOpt)]
+struct PathArgs {
+    /// Layout preset: "name" (iPhone 8/16.7.8.ipsw), "identifier" (iPhone10,1/16.7.8.ipsw), "platform"
+    /// (iPhoneOS/iPhone 8/16.7.8.ipsw) or "flat" (iPhone10,1_16.7.8_20H343.ipsw).
+    #[structopt(long, possible_values(&["name", "identifier", "platform", "flat"]))]
+    layout: Option<Layout>,
+
+    /// Custom layout instead of a preset. Placeholders are {name}, {identifier}, {version}, {buildid} and {platform}.
+    #[structopt(short="t", long, conflicts_with("layout"))]
+    path_template: Option<PathTemplate>,
+
+    /// Name device directories by "name" (iPad Pro (12.9-inch) (3rd generation)) or "identifier" (iPad8,5).
+    /// Identifiers never change, while names sometimes do between API updates.
+    #[structopt(long, possible_values(&["name", "identifier"]), conflicts_with("path-template"))]
+    dir_by: Option<DirBy>,
+
+    /// Transliterate names in paths to ASCII, ex. for storage shared with filesystems that mangle other characters.
+    #[structopt(long)]
+    ascii_paths: bool
+}
+
+impl PathArgs {
+    /// Gets the template these options describe, the name layout if none was picked.
+    fn template(&self) -> PathTemplate {
+        let template = match &self.path_template {
+            Some(template) => template.clone(),
+            None => self.layout.unwrap_or_default().template().with_dir_by(self.dir_by.unwrap_or_default()),
+        };
+
+        template.with_ascii(self.ascii_paths)
+    }
+}
+
+/// Options for uploading to remote destinations.
+#[derive(StructOpt)]
+struct RemoteArgs {
+    /// Region of the S3 bucket.
+    #[structopt(long, env = "AWS_REGION", default_value = DEFAULT_REGION)]
+    s3_region: String,
+
+    /// URL of an S3 compatible server to upload to instead of Amazon, ex. "http://minio.local:9000".
+    #[structopt(long, env = "AWS_ENDPOINT_URL")]
+    s3_endpoint: Option<String>,
+
+    /// Path of the sftp executable, used to upload to sftp:// destinations.
+    #[structopt(long, default_value="sftp")]
+    sftp: PathBuf
+}
+
+impl RemoteArgs {
+    /// Gets the remote destination an s3://, sftp://, dav:// or davs:// URL names.
+    ///
+    /// # Returns
+    /// * Err(S3 | WebDav | Sftp) - The remote isn't configured right.
+    fn remote(&self, url: &str) -> Result<Remote> {
+        if url.starts_with("s3://") {
+            let credentials = S3Credentials::from_env()?;
+            Ok(Remote::S3(S3Bucket::new(url, self.s3_region.clone(), self.s3_endpoint.clone(), credentials)?))
+        } else if url.starts_with("sftp://") {
+            Ok(Remote::Sftp(Sftp::new(url, self.sftp.clone())?))
+        } else {
+            Ok(Remote::WebDav(WebDav::new(url)?))
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct SyncArgs {
+    /// Directory, or s3://, sftp://, dav:// or davs:// URL, to mirror the library to.
+    dest: String,
+
+    /// Directory .ipsw files were downloaded to.
+    #[structopt(short="p", long, default_value="./ipsw")]
+    download_path: PathBuf,
+
+    /// Delete files from the destination that are no longer in the library, ex. after --keep or --delete-old-fw pruned
+    /// them. Only directory destinations can be deleted from.
+    #[structopt(long)]
+    delete: bool,
+
+    #[structopt(flatten)]
+    remote: RemoteArgs
+}
+
+#[derive(StructOpt)]
+struct DownloadArgs {
+    /// Directory to download .ipsw files to, or an s3://, sftp://, dav:// or davs:// URL to upload them to.
     #[structopt(short="p", long, default_value="./ipsw")]
     download_path: PathBuf,
 
-    /// Delete old ipsw files when a newer version is available.
+    /// Directory downloads are kept in until uploaded, when the download path is remote. Defaults to the temp directory.
+    #[structopt(long)]
+    staging_dir: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    remote: RemoteArgs,
+
+    #[structopt(flatten)]
+    paths: PathArgs,
+
+    #[structopt(flatten)]
+    filter: FilterArgs,
+
+    /// Delete old ipsw files next to a newer version, once it has been downloaded and verified.
     #[structopt(short, long)]
     delete_old_fw: bool,
 
-    /// Download the latest ipsw for all devices.
-    #[structopt(short="A", long, conflicts_with("filter-term"), required_unless("filter-term"), required_unless("list-device-names"))]
-    #[allow(dead_code)]
-    download_all: bool, //Never used, but needed to avoid CLI from running without user input
+    /// After downloading, delete all but the N newest downloaded versions of each device.
+    #[structopt(short, long, conflicts_with_all(&["delete-old-fw", "all-versions"]))]
+    keep: Option<usize>,
 
-    /// Filter ipsw files to only device names matching the term.
-    #[structopt(short, long, required_unless("download-all"), required_unless("list-device-names"))]
-    filter_term: Option<String>,
+    /// How to show download progress. `json` prints one JSON event per line for every state change, in place of any
+    /// other output.
+    #[structopt(long, default_value="bars", possible_values(&["bars", "json"]))]
+    progress_format: ProgressFormat,
 
-    /// Filename to log to. Will not log if not set.
+    /// indicatif template for the progress bar of each download. {prefix} is the device name.
+    #[structopt(long, default_value = DEFAULT_PROGRESS_TEMPLATE)]
+    progress_template: String,
+
+    /// When output isn't a terminal, print a progress line this often (and every 10 percent) instead of progress bars.
+    #[structopt(long, default_value="30s", parse(try_from_str = parse_duration))]
+    progress_interval: Duration,
+
+    /// Verify the checksums of files that are already downloaded, deleting and re-downloading any that don't match.
+    /// Without it, only their sizes are checked.
+    #[structopt(long, aliases(&["force-corrupt", "verify-existing"]))]
+    repair: bool,
+
+    /// Delete and re-download files that are already downloaded or uploaded, whether or not they are corrupt.
+    #[structopt(long, conflicts_with("repair"))]
+    force: bool,
+
+    /// API checksums to compare downloads against.
+    #[structopt(long, default_value="sha1", possible_values(&["md5", "sha1", "both"]))]
+    checksum: Checksum,
+
+    /// Save the version, build, release date, URL and checksums of each download to a sidecar file next to it,
+    /// as `<version>.json` or `<version>.md`.
+    #[structopt(long, possible_values(&["json", "markdown"]))]
+    metadata: Option<MetadataFormat>,
+
+    /// Save SHSH2 blobs of each signed firmware for the device with this ECID, using tsschecker, into a
+    /// `<version>.shsh2` directory next to the download.
+    #[structopt(long)]
+    save_blobs: Option<String>,
+
+    /// Path of the tsschecker executable, used by --save-blobs.
+    #[structopt(long, default_value="tsschecker")]
+    tsschecker: PathBuf,
+
+    /// Download releases from ipsw.me, or developer and public betas from --beta-source into the beta directory.
+    #[structopt(long, default_value="release", possible_values(&["release", "beta"]))]
+    channel: Channel,
+
+    /// URL of an AppleDB style JSON list of builds, used with --channel beta.
+    #[structopt(long, default_value = DEFAULT_BETA_SOURCE)]
+    beta_source: String,
+
+    /// Only download firmware for the devices in this file, one identifier or name per line. Names may use * wildcards.
+    /// Append "= <version or build>" to pin a device, ex. "iPhone15,2 = 17.5.1". "-" reads the list from stdin.
+    #[structopt(long)]
+    devices_file: Option<PathBuf>,
+
+    /// Read a device list from stdin, as with --devices-file, ex. identifiers piped in from another tool.
+    #[structopt(long, conflicts_with("devices-file"))]
+    stdin: bool,
+
+    /// Only download firmware for the devices attached over USB. Needs libimobiledevice.
+    #[structopt(long, conflicts_with_all(&["devices-file", "stdin"]))]
+    connected: bool,
+
+    /// With --connected, only download firmware newer than the version installed on the attached devices.
+    #[structopt(long, requires("connected"))]
+    newer_than_installed: bool,
+
+    /// Download the newest OTA update of devices without ipsw files, like the Apple Watch, instead of leaving them out.
+    #[structopt(long)]
+    ota_fallback: bool,
+
+    /// Download the newest iTunes installer for Windows into the iTunes directory, instead of any firmware.
+    #[structopt(long)]
+    itunes: bool,
+
+    /// Save the decryption keys of each download, if any are known, to a `<version>.keys.json` file next to it.
+    #[structopt(long)]
+    save_keys: bool,
+
+    /// Extract BuildManifest.plist and Restore.plist of each download into a `<version>.plists` directory next to it.
+    #[structopt(long)]
+    extract_manifests: bool,
+
+    /// Amount of devices to download concurrently.
+    #[structopt(short, long, default_value="1")]
+    jobs: usize,
+
+    /// Order to download devices in, so runs cut short by --max-duration or --max-total-size get the most important
+    /// first. `api` keeps the order of the API.
+    #[structopt(
+        long,
+        default_value="api",
+        possible_values(&["api", "smallest-first", "alphabetical", "newest-release-first"])
+    )]
+    order: DownloadOrder,
+
+    /// Download each ipsw over this many connections at once, each fetching a part of the file. Falls back to one
+    /// connection if the server doesn't support range requests.
+    #[structopt(long, default_value="1")]
+    segments: usize,
+
+    /// Download this firmware version (ex. 16.7.8) instead of the newest.
+    #[structopt(short="V", long)]
+    version: Option<String>,
+
+    /// Download the firmware with this build id (ex. 20H343) instead of the newest.
     #[structopt(short, long)]
-    log_path: Option<PathBuf>,
+    buildid: Option<String>,
+
+    /// Only consider firmware of this version or newer, ex. "17" or "16.4".
+    #[structopt(long)]
+    min_version: Option<String>,
+
+    /// Only consider firmware of this version or older. "17" allows every 17.x.
+    #[structopt(long)]
+    max_version: Option<String>,
+
+    /// Only consider firmware uploaded on or after this date, ex. 2024-01-01.
+    #[structopt(long)]
+    since: Option<NaiveDate>,
+
+    /// Only download firmware Apple is currently signing, skipping builds that can't be restored.
+    #[structopt(short, long)]
+    signed_only: bool,
+
+    /// Download every firmware version for each device instead of only the newest. Versions already downloaded are skipped.
+    #[structopt(short="a", long, conflicts_with("delete-old-fw"))]
+    all_versions: bool,
+
+    /// Times to retry each step of a device before giving up on it: fetching its firmware listing, starting a
+    /// download, and resuming one cut off mid stream.
+    #[structopt(short, long, default_value="3")]
+    retries: u32,
+
+    /// Seconds to wait before the first retry. Doubles with each following retry.
+    #[structopt(long, default_value="2")]
+    backoff_base: f64,
+
+    /// Wait this long before every retry instead of doubling the wait, ex. "30s" or "5m".
+    #[structopt(long, conflicts_with("backoff-base"), parse(try_from_str = parse_duration))]
+    retry_delay: Option<Duration>,
+
+    /// Maximum download speed per second across all downloads, ex. "500K" or "10MB".
+    #[structopt(long, parse(try_from_str = parse_bytes))]
+    limit_rate: Option<u64>,
+
+    /// Only retry the devices the last run failed on, ex. after a network blip.
+    #[structopt(long, conflicts_with_all(&["devices-file", "stdin", "connected"]))]
+    retry_failed: bool,
+
+    /// Resume the run an earlier process left unfinished, ex. after a crash, without querying the devices it finished.
+    #[structopt(long)]
+    resume_run: bool,
+
+    /// Stop starting downloads after this long, ex. "6h" for a nightly window. Downloads in progress are finished, and
+    /// the devices left are resumed by the next run with --resume-run.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    max_duration: Option<Duration>,
+
+    /// Download at most this much per run, ex. "50GB". Devices whose firmware would go over it are left for the next
+    /// run with --resume-run.
+    #[structopt(long, parse(try_from_str = parse_bytes))]
+    max_total_size: Option<u64>,
+
+    /// Keep running, downloading new firmware every interval until ctrl-c is pressed.
+    #[structopt(short, long)]
+    watch: bool,
+
+    /// Time to wait between passes in watch mode, ex. "90s", "30m", "6h" or "1d".
+    #[structopt(long, default_value="6h", parse(try_from_str = parse_duration))]
+    interval: Duration,
+
+    /// URL to POST to when firmware is downloaded, fails to download, or a run completes.
+    #[structopt(long)]
+    webhook: Option<String>,
+
+    /// Payload format for the webhook. Guessed from the URL if not set.
+    #[structopt(long, possible_values(&["json", "discord", "slack"]))]
+    webhook_format: Option<WebhookFormat>,
+
+    /// Show a desktop notification when a download fails or a run completes.
+    #[structopt(long)]
+    notify: bool,
+
+    /// Serve Prometheus metrics at /metrics on this address while downloading, ex. "127.0.0.1:9184".
+    #[structopt(long)]
+    metrics_addr: Option<SocketAddr>,
 
-    /// List all device names that could be downloaded. Should only be used by itself.
-    #[structopt(short="L", long, conflicts_with("filter-term"), conflicts_with("download-all"))]
-    list_device_names: bool
+    /// Write Prometheus metrics to this file while downloading, for node_exporter's textfile collector.
+    #[structopt(long)]
+    metrics_file: Option<PathBuf>,
+}
+
+impl DownloadArgs {
+    /// Gets the options to pass to the downloader.
+    ///
+    /// # Returns
+    /// * Err(S3 | WebDav | Sftp) - The download path is a remote URL, but the remote isn't configured right.
+    /// * Err(Io | Config) - The config file could not be read.
+    fn download_options(&self, cli: &CliOpts) -> Result<DownloadOptions> {
+        let config = match &cli.config {
+            Some(path) => Config::load(path)?,
+            None => Config::load_default()?,
+        };
+
+        //Remote downloads are staged locally until uploaded
+        let (download_path, remote) = match self.download_path.to_str().filter(|path| is_remote(path)) {
+            Some(url) => {
+                let staging_dir = self.staging_dir.clone().unwrap_or_else(|| std::env::temp_dir().join("ipswdl2"));
+                (staging_dir, Some(self.remote.remote(url)?))
+            }
+            None => (self.download_path.clone(), None),
+        };
+
+        Ok(DownloadOptions {
+            download_path,
+            remote,
+            path_template: self.paths.template(),
+            delete_old_fw: self.delete_old_fw,
+            keep: self.keep,
+            repair: self.repair,
+            force: self.force,
+            checksum: self.checksum,
+            extract_manifests: self.extract_manifests,
+            save_keys: self.save_keys,
+            metadata: self.metadata,
+            ota_fallback: self.ota_fallback,
+            blobs: self.save_blobs.clone().map(|ecid| BlobOptions {
+                ecid,
+                tsschecker: self.tsschecker.clone(),
+            }),
+            channel: self.channel,
+            beta_source: self.beta_source.clone(),
+            filter: self.filter.device_filter(),
+            jobs: self.jobs,
+            order: self.order,
+            segments: self.segments,
+            version: self.version.clone(),
+            buildid: self.buildid.clone(),
+            min_version: self.min_version.clone(),
+            max_version: self.max_version.clone(),
+            since: self.since,
+            signed_only: self.signed_only,
+            installed_versions: HashMap::new(),
+            pins: HashMap::new(),
+            all_versions: self.all_versions,
+            retries: self.retries,
+            backoff_base: self.backoff_base,
+            retry_delay: self.retry_delay,
+            limit_rate: self.limit_rate,
+            output: cli.output,
+            quiet: cli.quiet,
+            progress_format: self.progress_format,
+            progress_template: self.progress_template.clone(),
+            //Bars are drawn to stderr, and turn to garbage in logs
+            progress_log_interval: if atty::is(atty::Stream::Stderr) && atty::is(atty::Stream::Stdout) {
+                None
+            } else {
+                Some(self.progress_interval)
+            },
+            webhook: self.webhook.clone(),
+            webhook_format: self.webhook_format,
+            notify: self.notify,
+            email: config.email,
+            metrics_addr: self.metrics_addr,
+            metrics_file: self.metrics_file.clone(),
+            resume_run: self.resume_run,
+            max_duration: self.max_duration,
+            max_total_size: self.max_total_size,
+        })
+    }
+}
+
+#[derive(StructOpt)]
+struct VerifyArgs {
+    /// Directory .ipsw files were downloaded to.
+    #[structopt(short="p", long, default_value="./ipsw")]
+    download_path: PathBuf,
+
+    #[structopt(flatten)]
+    paths: PathArgs,
+
+    #[structopt(flatten)]
+    filter: FilterArgs
+}
+
+#[derive(StructOpt)]
+struct SigningStatusArgs {
+    /// Name or identifier of a device, ex. "iPhone 8". Checks every downloaded firmware if not set.
+    device: Option<String>,
+
+    /// Only report this version of the device, ex. 16.7.8.
+    #[structopt(short="V", long, requires("device"))]
+    version: Option<String>,
+
+    /// Directory .ipsw files were downloaded to, checked if no device is given.
+    #[structopt(short="p", long, default_value="./ipsw")]
+    download_path: PathBuf
+}
+
+#[derive(StructOpt)]
+struct ExportArgs {
+    /// Directory .ipsw files were downloaded to.
+    #[structopt(short="p", long, default_value="./ipsw")]
+    download_path: PathBuf,
+
+    /// Format to print the manifest in.
+    #[structopt(short="F", long, default_value="json", possible_values(&["json", "csv"]))]
+    format: ExportFormat
+}
+
+#[derive(StructOpt)]
+struct ServeArgs {
+    /// Directory .ipsw files were downloaded to.
+    #[structopt(short="p", long, default_value="./ipsw")]
+    download_path: PathBuf,
+
+    /// Address and port to listen on.
+    #[structopt(short, long, default_value = DEFAULT_SERVE_ADDR)]
+    addr: SocketAddr
+}
+
+#[derive(StructOpt)]
+struct ExtractArgs {
+    /// The ipsw file to extract from.
+    ipsw: PathBuf,
+
+    /// Glob patterns selecting the entries to extract, ex. "kernelcache*" or "Firmware/**/iBoot*".
+    #[structopt(required = true, parse(try_from_str = Pattern::new))]
+    patterns: Vec<Pattern>,
+
+    /// Directory to extract to. Directories inside the ipsw are kept.
+    #[structopt(short="d", long, default_value=".")]
+    dest: PathBuf
 }
 
 #[tokio::main]
 async fn main() {
     let cli: CliOpts = CliOpts::from_args();
 
-    //Init logging if option is passed
-    if let Some(path) = &cli.log_path {
-        WriteLogger::init(LevelFilter::Debug, Config::default(), File::create(path).expect("log-path is in invalid file!")).unwrap();
+    if cli.no_color || std::env::var_os("NO_COLOR").is_some() || !atty::is(atty::Stream::Stdout) {
+        colored::control::set_override(false);
     }
 
-    let client = Client::new();
+    //Nothing is logged yet, so this is the only failure printed directly
+    if let Err(why) = init_logging(&cli) {
+        eprintln!("{}", format!("Error: {}", why).red());
+        std::process::exit(1);
+    }
 
-    println!("Getting Devices...");
+    if let Err(why) = run(cli).await {
+        error!("{}", why);
+        std::process::exit(1);
+    }
+}
 
-    let devices = client.get_all_devices().await.expect("Cannot hit API!");
+/// Logs errors and warnings to stderr, more if verbose, and everything at the log level to the log file if passed.
+///
+/// # Returns
+/// * Err(Io) - The log file could not be opened.
+fn init_logging(cli: &CliOpts) -> Result<()> {
+    let file_layer = match &cli.log_path {
+        Some(path) => {
+            let log_file = RotatingFile::open(path, Rotation {
+                max_size: cli.log_max_size,
+                daily: cli.log_rotate_daily,
+                keep: cli.log_keep,
+            })?;
+            Some(logging::file_layer(cli.log_format, cli.log_level, Mutex::new(log_file)))
+        }
+        None => None,
+    };
+    let ansi = !cli.no_color && std::env::var_os("NO_COLOR").is_none() && atty::is(atty::Stream::Stderr);
+    tracing_subscriber::registry()
+        .with(logging::console_layer(verbosity_level(cli.verbose), ansi))
+        .with(file_layer)
+        .try_init()
+        .expect("logger was already initialized");
 
-    //List devices if flag is set
-    if cli.list_device_names {
-        for device in &devices {
-            println!("{}", device.name);
+    Ok(())
+}
+
+/// Runs the operation selected by the CLI options.
+async fn run(cli: CliOpts) -> Result<()> {
+    //Only listings are tables, so nothing else has a CSV form
+    let listing = matches!(cli.cmd, Command::ListDevices { .. } | Command::ListFirmwares { .. });
+    if cli.output == OutputFormat::Csv && !listing {
+        return Err(Error::UnsupportedOutput("csv".to_string()));
+    }
+
+    //These only read local files, so don't need the API
+    match &cli.cmd {
+        Command::Export(args) => {
+            let entries = library_manifest(&args.download_path)?;
+            return write_manifest(&entries, args.format, std::io::stdout().lock());
+        }
+        Command::Sync(args) => return sync_library(args, cli.output).await,
+        Command::Serve(args) => {
+            println!("Serving {} on http://{}, press ctrl-c to stop", args.download_path.display(), args.addr);
+            return ipswdl2::serve::serve(args.download_path.clone(), args.addr).await;
+        }
+        Command::Extract(args) => return extract(args, &cli).await,
+        Command::Inspect { ipsw } => return inspect(ipsw, cli.output).await,
+        _ => {}
+    }
+
+    let client = Client::with_options(ClientOptions {
+        api_url: cli.api_url.clone(),
+        connect_timeout: Some(cli.connect_timeout),
+        read_timeout: Some(cli.read_timeout),
+        user_agent: cli.user_agent.clone(),
+        headers: cli.header.clone(),
+        cache: if cli.no_cache {
+            None
+        } else {
+            cli.cache_dir.clone().or_else(Cache::default_dir).map(Cache::new)
+        },
+        cache_ttl: cli.cache_ttl,
+        refresh: cli.refresh,
+        pool_size: cli.pool_size,
+        ..ClientOptions::default()
+    })?;
+
+    //Checked before getting devices, as that fails if the API is down
+    if let Command::StatusApi { cdn_url } = &cli.cmd {
+        return status_api(&client, cdn_url, cli.output).await;
+    }
+
+    //iTunes installers aren't tied to devices
+    if let Command::Download(args) = &cli.cmd {
+        if args.itunes {
+            return download_itunes(&client, args, &cli).await;
+        }
+    }
+
+    //Progress events replace all other output
+    let progress_events = matches!(&cli.cmd, Command::Download(args) if args.progress_format == ProgressFormat::Json);
+    let chatty = cli.output == OutputFormat::Text && !cli.quiet && !progress_events;
+
+    if chatty {
+        println!("Getting Devices...");
+    }
+
+    let devices = client.get_all_devices().await?;
+
+    match &cli.cmd {
+        Command::ListDevices { columns } => list_devices(&devices, columns, cli.output),
+        Command::ListFirmwares { device } => list_firmwares(&client, &devices, device, cli.output).await,
+        Command::Search { term, limit } => {
+            search_devices(&devices, term, *limit, cli.output);
+            Ok(())
+        }
+        Command::DeviceInfo { device } => device_info(&client, &devices, device, cli.output).await,
+        Command::Keys { device, buildid } => keys(&client, &devices, device, buildid.as_deref(), cli.output).await,
+        Command::SigningStatus(args) => signing_status(&client, &devices, args, &cli).await,
+        Command::Verify(args) => verify(&client, &devices, args, &cli).await,
+        Command::Export(_) | Command::Sync(_) | Command::Serve(_) | Command::Extract(_) | Command::Inspect { .. } => unreachable!("local commands are handled before fetching devices"),
+        Command::StatusApi { .. } => unreachable!("status-api is handled before fetching devices"),
+        Command::Download(args) => {
+            if chatty {
+                println!("Got {} devices!", devices.len());
+            }
+            info!("Got {} devices", devices.len());
+
+            //Interrupted downloads are kept, so ctrl-c only needs to stop the downloader
+            let cancel = CancellationToken::new();
+            let handler_token = cancel.clone();
+            ctrlc::set_handler(move || {
+                warn!("ctrl-c received, exiting...");
+                handler_token.cancel();
+            })?;
+
+            let mut options = args.download_options(&cli)?;
+            //Lets other tools pipe in identifiers, ex. `mdmquery | ipswdl2 download --stdin`
+            let list = if args.stdin || args.devices_file.as_deref() == Some(Path::new("-")) {
+                Some((read_stdin_list()?, "stdin".to_string()))
+            } else if let Some(path) = &args.devices_file {
+                let list =
+                    std::fs::read_to_string(path).map_err(|why| Error::io("could not read device list", path, why))?;
+                Some((list, path.display().to_string()))
+            } else {
+                None
+            };
+            if let Some((list, source)) = list {
+                let resolved = resolve_list(&devices, &list, &source)?;
+                options.filter.identifiers = resolved.identifiers;
+                options.pins = resolved.pins;
+            }
+            if args.retry_failed {
+                let failures = load_failures(&options.download_path)?;
+                if failures.is_empty() {
+                    if chatty {
+                        println!("The last run had no failures to retry.");
+                    }
+                    return Ok(());
+                }
+
+                for failure in &failures {
+                    info!("retrying {}, which failed with {}: {}", failure.device, failure.error_kind, failure.error);
+                }
+                options.filter.identifiers = failures.into_iter().map(|failure| failure.identifier).collect();
+            }
+            if args.connected {
+                let installed = installed_versions().await?;
+                options.filter.identifiers = installed.keys().cloned().collect();
+                if args.newer_than_installed {
+                    options.installed_versions = installed;
+                }
+            }
+
+            let downloader = Downloader::with_cancellation(client, devices, options, cancel)?;
+            if args.watch {
+                downloader.watch(args.interval).await;
+            } else {
+                downloader.begin().await;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Resolves a device list from `source`, warning about entries that match no device.
+///
+/// # Returns
+/// * Err(DeviceNotFound) - No entry matched a device.
+fn resolve_list(devices: &[Device], list: &str, source: &str) -> Result<ResolvedList> {
+    let resolved = resolve_device_list(devices, &parse_device_list(list));
+    for entry in &resolved.unmatched {
+        warn!("{} in {} matches no device", entry, source);
+    }
+
+    if resolved.identifiers.is_empty() {
+        return Err(Error::DeviceNotFound(format!("any entry of {}", source)));
+    }
+    Ok(resolved)
+}
+
+/// Reads a device list from stdin.
+fn read_stdin_list() -> Result<String> {
+    let mut list = String::new();
+    std::io::stdin()
+        .read_to_string(&mut list)
+        .map_err(|why| Error::io("could not read device list from", Path::new("stdin"), why))?;
+    Ok(list)
+}
+
+/// Gets the installed versions of the devices attached over USB by identifier. If several devices share an
+/// identifier, the oldest version is kept, so firmware is downloaded if any of them can update.
+///
+/// # Returns
+/// * Err(ConnectedDevices) - No device is attached, or they could not be read.
+async fn installed_versions() -> Result<HashMap<String, String>> {
+    let connected = connected_devices().await?;
+    if connected.is_empty() {
+        return Err(Error::ConnectedDevices("no devices are attached".to_string()));
+    }
+
+    let mut installed: HashMap<String, String> = HashMap::new();
+    for device in connected {
+        info!("downloading for connected {} on {} ({})", device.identifier, device.version, device.udid);
+
+        let oldest = installed.entry(device.identifier.clone()).or_insert_with(|| device.version.clone());
+        if compare_versions(&device.version, oldest) == std::cmp::Ordering::Less {
+            *oldest = device.version;
+        }
+    }
+    Ok(installed)
+}
+
+/// Downloads the newest iTunes installer for Windows.
+async fn download_itunes(client: &Client, args: &DownloadArgs, cli: &CliOpts) -> Result<()> {
+    let show_progress = cli.output == OutputFormat::Text && !cli.quiet && atty::is(atty::Stream::Stderr);
+    let result = itunes::download_newest(client, &args.download_path, "windows", show_progress).await?;
+
+    if cli.output == OutputFormat::Json {
+        print_json(&result);
+        return Ok(())
+    }
+    if cli.quiet {
+        return Ok(())
+    }
+
+    match result {
+        DownloadResult::Downloaded { version, size, path, .. } => {
+            println!("{}", format!("Downloaded iTunes {}, {} to {}", version, HumanBytes(size), path.display()).green())
+        }
+        DownloadResult::Skipped { version, .. } => {
+            println!("{}", format!("iTunes {} is already downloaded", version.unwrap_or_default()).dimmed())
+        }
+        DownloadResult::Failed { .. } => unreachable!("failures are returned as errors"),
+    }
+
+    Ok(())
+}
+
+/// Checks how fast the API and Apple's CDN answer, printing the status and latency of each.
+///
+/// # Returns
+/// * Err(Unreachable) - Either could not be reached, ex. DNS failed or the connection timed out.
+async fn status_api(client: &Client, cdn_url: &str, output: OutputFormat) -> Result<()> {
+    let (api, cdn) = futures::join!(client.probe_api(), client.probe(cdn_url));
+    let probes = [("API", api), ("Apple CDN", cdn)];
+
+    let mut unreachable = Vec::new();
+    for (name, probe) in &probes {
+        if output == OutputFormat::Json {
+            print_json(&match &probe.answer {
+                Ok((status, latency)) => serde_json::json!({
+                    "name": name,
+                    "url": probe.url,
+                    "reachable": true,
+                    "status": status.as_u16(),
+                    "latency_ms": latency.as_millis() as u64,
+                }),
+                Err(why) => serde_json::json!({
+                    "name": name,
+                    "url": probe.url,
+                    "reachable": false,
+                    "error_kind": why.kind(),
+                    "error": why.to_string(),
+                }),
+            });
+        } else {
+            match &probe.answer {
+                Ok((status, latency)) => {
+                    //Any answer means the server is reachable, but errors hint at an outage
+                    let status = if status.is_success() || status.is_redirection() {
+                        status.to_string().green()
+                    } else {
+                        status.to_string().yellow()
+                    };
+                    println!("{:<12}{} in {} ms ({})", name, status, latency.as_millis(), probe.url);
+                }
+                Err(why) => println!("{:<12}{} ({})", name, format!("unreachable: {}", why).red(), probe.url),
+            }
+        }
+
+        if probe.answer.is_err() {
+            unreachable.push(*name);
+        }
+    }
+
+    if unreachable.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Unreachable(unreachable.join(" and ")))
+    }
+}
+
+/// Prints every device as a table of `columns`.
+fn list_devices(devices: &[Device], columns: &[DeviceColumn], output: OutputFormat) -> Result<()> {
+    if output == OutputFormat::Json {
+        print_json(&devices);
+        return Ok(());
+    }
+    if output == OutputFormat::Csv {
+        let mut csv_writer = csv::Writer::from_writer(std::io::stdout());
+        csv_writer.write_record(columns.iter().map(|column| column.name()))?;
+        for device in devices {
+            csv_writer.write_record(columns.iter().map(|column| column.value(device)))?;
+        }
+        return csv_writer.flush().map_err(|why| Error::io("could not write", "<output>", why));
+    }
+
+    let headers: Vec<&str> = columns.iter().map(|column| column.header()).collect();
+    let rows: Vec<Vec<&str>> = devices
+        .iter()
+        .map(|device| columns.iter().map(|column| column.value(device)).collect())
+        .collect();
+
+    let mut lines = format_table(&headers, &rows).into_iter();
+    if let Some(header) = lines.next() {
+        println!("{}", header.bold());
+    }
+    for line in lines {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+/// Prints the devices best matching `term`.
+fn search_devices(devices: &[Device], term: &str, limit: usize, output: OutputFormat) {
+    let found: Vec<&Device> = search(devices, term).into_iter().take(limit).collect();
+
+    if output == OutputFormat::Json {
+        print_json(&found);
+    } else if found.is_empty() {
+        println!("{}", format!("No devices match {}", term).dimmed());
+    } else {
+        for device in found {
+            println!("{:<40} {}", device.name, device.identifier);
+        }
+    }
+}
+
+/// Finds the device matching a name or identifier. Exact matches are preferred over partial name matches.
+fn find_device<'a>(devices: &'a [Device], term: &str) -> Option<&'a Device> {
+    devices
+        .iter()
+        .find(|d| d.identifier.eq_ignore_ascii_case(term) || d.name.eq_ignore_ascii_case(term))
+        .or_else(|| devices.iter().find(|d| d.name.contains(term)))
+}
+
+/// Prints every firmware entry for the device matching `term`.
+async fn list_firmwares(client: &Client, devices: &[Device], term: &str, output: OutputFormat) -> Result<()> {
+    let device = find_device(devices, term).ok_or_else(|| Error::DeviceNotFound(term.to_string()))?;
+    let listing = client.get_device_firmware(device).await?;
+
+    if output == OutputFormat::Json {
+        print_json(&listing);
+        return Ok(())
+    }
+    if output == OutputFormat::Csv {
+        let mut csv_writer = csv::Writer::from_writer(std::io::stdout());
+        csv_writer.write_record([
+            "name", "identifier", "version", "buildid", "filesize", "uploaddate", "signed", "sha1sum", "md5sum", "url",
+        ])?;
+        for fw in &listing.firmwares {
+            csv_writer.write_record([
+                &listing.name,
+                &listing.identifier,
+                &fw.version,
+                &fw.buildid,
+                &fw.filesize.to_string(),
+                &fw.uploaddate.to_rfc3339(),
+                &fw.signed.to_string(),
+                &fw.sha1sum,
+                &fw.md5sum,
+                &fw.url,
+            ])?;
         }
-        return
+        return csv_writer.flush().map_err(|why| Error::io("could not write", "<output>", why));
     }
 
-    println!("Got {} devices!", devices.len());
-    info!("Got {} devices", devices.len());
+    println!("{} ({})", listing.name.bold(), listing.identifier);
+    println!("{:<12} {:<12} {:>10}  {:<10}  Signed", "Version", "Build", "Size", "Uploaded");
 
-    Downloader::new(client, devices, cli).begin().await
-}
\ No newline at end of file
+    for fw in &listing.firmwares {
+        let signed = if fw.signed { "yes".green() } else { "no".red() };
+
+        println!(
+            "{:<12} {:<12} {:>10}  {:<10}  {}",
+            fw.version,
+            fw.buildid,
+            HumanBytes(fw.filesize).to_string(),
+            fw.uploaddate.format("%Y-%m-%d").to_string(),
+            signed
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints details of the device matching `term`.
+async fn device_info(client: &Client, devices: &[Device], term: &str, output: OutputFormat) -> Result<()> {
+    let device = find_device(devices, term).ok_or_else(|| Error::DeviceNotFound(term.to_string()))?;
+    let listing = client.get_device_firmware(device).await?;
+    let newest = listing.firmwares.iter().min_by(|a, b| newest_first(a, b));
+
+    if output == OutputFormat::Json {
+        print_json(&serde_json::json!({
+            "name": listing.name,
+            "identifier": listing.identifier,
+            "platform": listing.platform,
+            "boardconfig": listing.boardconfig,
+            "cpid": listing.cpid,
+            "bdid": listing.bdid,
+            "firmware_count": listing.firmwares.len(),
+            "newest_version": newest.map(|fw| &fw.version),
+            "newest_buildid": newest.map(|fw| &fw.buildid),
+        }));
+        return Ok(())
+    }
+
+    println!("{}", listing.name.bold());
+    println!("{:<14}{}", "Identifier", listing.identifier);
+    println!("{:<14}{}", "Platform", listing.platform);
+    println!("{:<14}{}", "Board", listing.boardconfig);
+    println!("{:<14}{:#x}", "CPID", listing.cpid);
+    println!("{:<14}{:#x}", "BDID", listing.bdid);
+    println!("{:<14}{}", "Firmwares", listing.firmwares.len());
+    match newest {
+        Some(fw) => println!("{:<14}{} ({})", "Newest", fw.version, fw.buildid),
+        None => println!("{:<14}none", "Newest"),
+    }
+
+    Ok(())
+}
+
+/// Prints the decryption keys of a build of the device matching `term`, or of its newest build.
+///
+/// # Returns
+/// * Err(NotFound) - No keys are known for the build.
+async fn keys(client: &Client, devices: &[Device], term: &str, buildid: Option<&str>, output: OutputFormat) -> Result<()> {
+    let device = find_device(devices, term).ok_or_else(|| Error::DeviceNotFound(term.to_string()))?;
+
+    let buildid = match buildid {
+        Some(buildid) => buildid.to_string(),
+        None => {
+            let listing = client.get_device_firmware(device).await?;
+            listing
+                .firmwares
+                .iter()
+                .min_by(|a, b| newest_first(a, b))
+                .map(|fw| fw.buildid.clone())
+                .ok_or_else(|| Error::NotFound(format!("firmware of {}", device.identifier)))?
+        }
+    };
+
+    let keys = client.get_firmware_keys(&device.identifier, &buildid).await?;
+
+    if output == OutputFormat::Json {
+        print_json(&keys);
+        return Ok(())
+    }
+
+    println!("{} {} ({})", device.name.bold(), keys.buildid, keys.codename.as_deref().unwrap_or("unknown codename"));
+    for key in &keys.keys {
+        println!("{}", format!("{} ({})", key.image, key.filename).bold());
+        println!("  IV   {}", key.iv.as_deref().unwrap_or("-"));
+        println!("  Key  {}", key.key.as_deref().unwrap_or("-"));
+    }
+
+    Ok(())
+}
+
+/// Reports whether firmware of a device, or every downloaded firmware, is still signed.
+async fn signing_status(client: &Client, devices: &[Device], args: &SigningStatusArgs, cli: &CliOpts) -> Result<()> {
+    let mut statuses = Vec::new();
+
+    match &args.device {
+        Some(term) => {
+            let device = find_device(devices, term).ok_or_else(|| Error::DeviceNotFound(term.to_string()))?;
+            let listing = client.get_device_firmware(device).await?;
+
+            for fw in &listing.firmwares {
+                if args.version.as_ref().is_none_or(|v| *v == fw.version) {
+                    statuses.push(SigningStatus {
+                        device: listing.name.clone(),
+                        identifier: fw.identifier.clone(),
+                        version: fw.version.clone(),
+                        buildid: fw.buildid.clone(),
+                        signed: fw.signed,
+                        path: None,
+                    });
+                }
+            }
+        }
+        None => {
+            let entries = library_manifest(&args.download_path)?;
+
+            //Each device's listing only needs fetching once
+            let mut identifiers: Vec<&str> = entries.iter().map(|e| e.identifier.as_str()).collect();
+            identifiers.sort_unstable();
+            identifiers.dedup();
+
+            for identifier in identifiers {
+                let listing = match devices.iter().find(|d| d.identifier == identifier) {
+                    Some(device) => client.get_device_firmware(device).await,
+                    None => Err(Error::DeviceNotFound(identifier.to_string())),
+                };
+                let listing = match listing {
+                    Ok(listing) => listing,
+                    Err(why) => {
+                        error!("Failed to get firmware for {}: {}", identifier, why);
+                        continue
+                    }
+                };
+
+                for entry in entries.iter().filter(|e| e.identifier == identifier) {
+                    statuses.push(SigningStatus {
+                        device: entry.device.clone(),
+                        identifier: entry.identifier.clone(),
+                        version: entry.version.clone(),
+                        buildid: entry.buildid.clone(),
+                        signed: listing.firmwares.iter().any(|fw| fw.buildid == entry.buildid && fw.signed),
+                        path: Some(entry.path.clone()),
+                    });
+                }
+            }
+        }
+    }
+
+    for status in &statuses {
+        if cli.output == OutputFormat::Json {
+            print_json(status);
+            continue
+        }
+
+        let name = format!("{} {} ({})", status.device, status.version, status.buildid);
+        if status.signed {
+            println!("{} {}", "SIGNED  ".green(), name);
+        } else if !cli.quiet {
+            println!("{} {}", "UNSIGNED".red(), name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Verifies the downloaded files of every device matching the filter.
+///
+/// # Returns
+/// * Err(VerifyFailed) - Some files didn't match their checksum, or couldn't be checked.
+async fn verify(client: &Client, devices: &[Device], args: &VerifyArgs, cli: &CliOpts) -> Result<()> {
+    let filter = args.filter.device_filter();
+    let template = args.paths.template();
+    let mut failed = 0;
+
+    for device in devices.iter().filter(|d| filter.matches(d)) {
+        let listing = match client.get_device_firmware(device).await {
+            Ok(listing) => listing,
+            Err(why) => {
+                error!("Failed to get firmware for {}: {}", device.name, why);
+                failed += 1;
+                continue
+            }
+        };
+
+        for result in verify_listing(&listing, &args.download_path, &template).await {
+            let ok = matches!(result.status, VerifyStatus::Ok | VerifyStatus::NoChecksum);
+            if !ok {
+                failed += 1;
+                error!("{} failed verification: {:?}", result.path.display(), result.status);
+            }
+
+            if cli.output == OutputFormat::Json {
+                print_json(&result);
+                continue
+            }
+            if ok && cli.quiet {
+                continue
+            }
+
+            let name = format!("{} {} ({})", result.device, result.version, result.buildid);
+            match &result.status {
+                VerifyStatus::Ok => println!("{} {}", "OK      ".green(), name),
+                VerifyStatus::NoChecksum => println!("{} {}", "NO SUM  ".dimmed(), name),
+                VerifyStatus::Mismatch { expected, actual } => println!(
+                    "{} {} expected {} but got {}",
+                    "MISMATCH".red(), name, expected, actual
+                ),
+                VerifyStatus::Error { error } => println!("{} {} {}", "ERROR   ".red(), name, error),
+            }
+        }
+    }
+
+    if failed > 0 {
+        Err(Error::VerifyFailed(failed))
+    } else {
+        Ok(())
+    }
+}
+
+/// Mirrors the library to another directory or a remote.
+///
+/// # Returns
+/// * Err(SyncFailed) - Some files could not be mirrored.
+async fn sync_library(args: &SyncArgs, output: OutputFormat) -> Result<()> {
+    let target = if is_remote(&args.dest) {
+        SyncTarget::Remote(args.remote.remote(&args.dest)?)
+    } else {
+        SyncTarget::Dir(PathBuf::from(&args.dest))
+    };
+    if args.delete && matches!(target, SyncTarget::Remote(_)) {
+        warn!("Files can't be deleted from remote destinations, so --delete is ignored");
+    }
+
+    let results = sync(&args.download_path, &target, args.delete).await?;
+
+    let mut failed = 0;
+    for result in &results {
+        if result.action == SyncAction::Failed {
+            failed += 1;
+        }
+
+        if output == OutputFormat::Json {
+            print_json(result);
+            continue
+        }
+
+        let path = result.path.display();
+        match result.action {
+            SyncAction::Copied => println!("{} {}", "COPIED   ".green(), path),
+            SyncAction::Unchanged => println!("{} {}", "UNCHANGED".dimmed(), path),
+            SyncAction::Deleted => println!("{} {}", "DELETED  ".purple(), path),
+            SyncAction::Failed => println!("{} {} {}", "FAILED   ".red(), path, result.error.as_deref().unwrap_or_default()),
+        }
+    }
+
+    if failed > 0 {
+        Err(Error::SyncFailed(failed))
+    } else {
+        Ok(())
+    }
+}
+
+/// Extracts the entries of an ipsw matching any of the patterns.
+///
+/// # Returns
+/// * Err(NoMatchingEntries) - Nothing in the ipsw matched.
+async fn extract(args: &ExtractArgs, cli: &CliOpts) -> Result<()> {
+    let patterns = args.patterns.clone();
+    let extracted = ipsw::extract(args.ipsw.clone(), args.dest.clone(), move |name| {
+        patterns.iter().any(|pattern| pattern.matches(name))
+    })
+    .await?;
+
+    if extracted.is_empty() {
+        return Err(Error::NoMatchingEntries(args.ipsw.clone()));
+    }
+
+    for path in &extracted {
+        info!("extracted {}", path.display());
+
+        if cli.output == OutputFormat::Json {
+            print_json(path);
+        } else if !cli.quiet {
+            println!("{}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the entries of an ipsw, and what its Restore.plist says about it.
+async fn inspect(path: &Path, output: OutputFormat) -> Result<()> {
+    let inspection = ipsw::inspect(path.to_path_buf()).await?;
+
+    if output == OutputFormat::Json {
+        print_json(&inspection);
+        return Ok(())
+    }
+
+    println!("{:>10}  Name", "Size");
+    for entry in &inspection.entries {
+        println!("{:>10}  {}", HumanBytes(entry.size).to_string(), entry.name);
+    }
+
+    let restore = match &inspection.restore {
+        Some(restore) => restore,
+        None => {
+            println!("{}", "No Restore.plist found".dimmed());
+            return Ok(())
+        }
+    };
+    let join_map = |map: &BTreeMap<String, String>| {
+        map.iter().map(|(kind, name)| format!("{}: {}", kind, name)).collect::<Vec<_>>().join(", ")
+    };
+
+    println!();
+    println!(
+        "{:<14}{} ({})",
+        "Version",
+        restore.product_version.as_deref().unwrap_or("unknown"),
+        restore.product_build_version.as_deref().unwrap_or("unknown")
+    );
+    println!("{:<14}{}", "Devices", restore.supported_product_types.join(", "));
+    println!(
+        "{:<14}{}",
+        "Boards",
+        restore.device_map.iter().map(|board| board.board_config.as_str()).collect::<Vec<_>>().join(", ")
+    );
+    println!("{:<14}{}", "Ramdisks", join_map(&restore.restore_ram_disks));
+    println!("{:<14}{}", "Kernelcaches", join_map(&restore.restore_kernel_caches));
+
+    Ok(())
+}
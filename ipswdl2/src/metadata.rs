@@ -0,0 +1,119 @@
+//! Sidecar files describing each download, for archival context.
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use indicatif::HumanBytes;
+use serde::Serialize;
+
+use crate::api_json_types::Firmware;
+use crate::error::Result;
+
+/// Format of metadata sidecars.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MetadataFormat {
+    Json,
+    Markdown,
+}
+
+impl FromStr for MetadataFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(MetadataFormat::Json),
+            "markdown" | "md" => Ok(MetadataFormat::Markdown),
+            _ => Err(format!("unknown metadata format: {}", s)),
+        }
+    }
+}
+
+/// What is known about a downloaded firmware.
+#[derive(Serialize, Debug)]
+pub struct FirmwareMetadata<'a> {
+    pub device: &'a str,
+    pub identifier: &'a str,
+    pub version: &'a str,
+    pub buildid: &'a str,
+    pub released: DateTime<Utc>,
+    pub url: &'a str,
+    pub sha1sum: &'a str,
+    pub md5sum: &'a str,
+    pub filesize: u64,
+    pub signed: bool,
+}
+
+impl<'a> FirmwareMetadata<'a> {
+    pub fn new(device: &'a str, firmware: &'a Firmware) -> Self {
+        FirmwareMetadata {
+            device,
+            identifier: &firmware.identifier,
+            version: &firmware.version,
+            buildid: &firmware.buildid,
+            released: firmware.uploaddate,
+            url: &firmware.url,
+            sha1sum: &firmware.sha1sum,
+            md5sum: &firmware.md5sum,
+            filesize: firmware.filesize,
+            signed: firmware.signed,
+        }
+    }
+
+    /// Renders the metadata as a sidecar file.
+    pub fn render(&self, format: MetadataFormat) -> Result<String> {
+        match format {
+            MetadataFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            MetadataFormat::Markdown => Ok(self.markdown()),
+        }
+    }
+
+    fn markdown(&self) -> String {
+        let mut md = format!("# {} {} ({})\n\n| | |\n|---|---|\n", self.device, self.version, self.buildid);
+
+        let rows = [
+            ("Identifier", self.identifier.to_string()),
+            ("Released", self.released.format("%Y-%m-%d").to_string()),
+            ("Size", HumanBytes(self.filesize).to_string()),
+            ("SHA1", self.sha1sum.to_string()),
+            ("MD5", self.md5sum.to_string()),
+            ("Signed when downloaded", if self.signed { "yes" } else { "no" }.to_string()),
+            ("URL", format!("<{}>", self.url)),
+        ];
+        for (name, value) in rows.iter() {
+            //Writing to a String never fails
+            let _ = writeln!(md, "| {} | {} |", name, value);
+        }
+
+        md
+    }
+}
+
+/// Gets the path of the metadata sidecar of an ipsw, ex. `16.7.8.md` for `16.7.8.ipsw`.
+pub fn metadata_path(ipsw_path: &Path, format: MetadataFormat) -> PathBuf {
+    match format {
+        MetadataFormat::Json => ipsw_path.with_extension("json"),
+        MetadataFormat::Markdown => ipsw_path.with_extension("md"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_markdown() {
+        let firmware: Firmware = serde_json::from_value(serde_json::json!({
+            "identifier": "iPhone10,1", "version": "16.7.8", "buildid": "20H343", "sha1sum": "abc", "md5sum": "def",
+            "filesize": 1024, "url": "https://updates.cdn-apple.com/x.ipsw", "uploaddate": "2024-05-13T17:00:00Z",
+            "signed": true
+        }))
+        .unwrap();
+
+        let md = FirmwareMetadata::new("iPhone 8", &firmware).render(MetadataFormat::Markdown).unwrap();
+        assert!(md.starts_with("# iPhone 8 16.7.8 (20H343)\n"));
+        assert!(md.contains("| Released | 2024-05-13 |"));
+        assert!(md.contains("| URL | <https://updates.cdn-apple.com/x.ipsw> |"));
+        assert_eq!(metadata_path(Path::new("ipsw/16.7.8.ipsw"), MetadataFormat::Markdown), Path::new("ipsw/16.7.8.md"));
+    }
+}
@@ -0,0 +1,254 @@
+//! Prometheus metrics of a running downloader, so a mirror can be monitored like any other service.
+//!
+//! Metrics are served at `/metrics`, or written to a file for node_exporter's textfile collector.
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use tracing::info;
+
+use crate::cancel::CancellationToken;
+use crate::error::{Error, Result};
+
+/// How often the metrics file is rewritten while downloading.
+pub const METRICS_FILE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Content type of the Prometheus text format.
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// Counters of a single device.
+#[derive(Default, Debug)]
+struct DeviceMetrics {
+    completed: u64,
+    failed: u64,
+    /// Unix time of the last successful download.
+    last_success: Option<i64>,
+}
+
+/// Bytes downloaded when the speed was last measured.
+#[derive(Debug)]
+struct SpeedSample {
+    at: Instant,
+    bytes: u64,
+    /// Bytes per second between the last two samples.
+    speed: f64,
+}
+
+/// Metrics of a downloader, shared between its tasks.
+#[derive(Debug)]
+pub struct Metrics {
+    bytes: AtomicU64,
+    skipped: AtomicU64,
+    passes: AtomicU64,
+    devices: Mutex<BTreeMap<String, DeviceMetrics>>,
+    speed: Mutex<SpeedSample>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            bytes: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            passes: AtomicU64::new(0),
+            devices: Mutex::new(BTreeMap::new()),
+            speed: Mutex::new(SpeedSample {
+                at: Instant::now(),
+                bytes: 0,
+                speed: 0.0,
+            }),
+        }
+    }
+
+    /// Counts a finished download of a device.
+    pub fn downloaded(&self, device: &str) {
+        let mut devices = self.devices.lock().expect("metrics lock poisoned");
+        let metrics = devices.entry(device.to_string()).or_default();
+        metrics.completed += 1;
+        metrics.last_success = Some(Utc::now().timestamp());
+    }
+
+    /// Counts a failed download of a device.
+    pub fn failed(&self, device: &str) {
+        self.devices.lock().expect("metrics lock poisoned").entry(device.to_string()).or_default().failed += 1;
+    }
+
+    /// Counts a skipped firmware or device.
+    pub fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts bytes received from Apple's servers, including those of attempts that were retried.
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Counts a finished pass over every device.
+    pub fn pass_completed(&self) {
+        self.passes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Gets the download speed in bytes per second, averaged since it was last measured at least a second ago.
+    fn speed(&self) -> f64 {
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let mut sample = self.speed.lock().expect("metrics lock poisoned");
+
+        let elapsed = sample.at.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            sample.speed = (bytes - sample.bytes) as f64 / elapsed.as_secs_f64();
+            sample.at = Instant::now();
+            sample.bytes = bytes;
+        }
+
+        sample.speed
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+
+        let mut metric = |name: &str, kind: &str, help: &str, values: Vec<(String, String)>| {
+            writeln!(text, "# HELP {} {}", name, help).expect("writing to a String can't fail");
+            writeln!(text, "# TYPE {} {}", name, kind).expect("writing to a String can't fail");
+            for (labels, value) in values {
+                writeln!(text, "{}{} {}", name, labels, value).expect("writing to a String can't fail");
+            }
+        };
+
+        let devices = self.devices.lock().expect("metrics lock poisoned");
+        let per_device = |value: &dyn Fn(&DeviceMetrics) -> Option<String>| -> Vec<(String, String)> {
+            devices
+                .iter()
+                .filter_map(|(device, metrics)| Some((format!("{{device=\"{}\"}}", escape_label(device)), value(metrics)?)))
+                .collect()
+        };
+
+        metric(
+            "ipswdl2_downloads_completed_total",
+            "counter",
+            "Firmware downloaded, by device.",
+            per_device(&|metrics| Some(metrics.completed.to_string())),
+        );
+        metric(
+            "ipswdl2_downloads_failed_total",
+            "counter",
+            "Firmware or devices that failed to download, by device.",
+            per_device(&|metrics| Some(metrics.failed.to_string())),
+        );
+        metric(
+            "ipswdl2_last_success_timestamp_seconds",
+            "gauge",
+            "Unix time of the last successful download, by device.",
+            per_device(&|metrics| metrics.last_success.map(|time| time.to_string())),
+        );
+        metric(
+            "ipswdl2_downloads_skipped_total",
+            "counter",
+            "Firmware or devices skipped, ex. as they were already downloaded.",
+            vec![(String::new(), self.skipped.load(Ordering::Relaxed).to_string())],
+        );
+        metric(
+            "ipswdl2_downloaded_bytes_total",
+            "counter",
+            "Bytes downloaded, including retried attempts.",
+            vec![(String::new(), self.bytes.load(Ordering::Relaxed).to_string())],
+        );
+        metric(
+            "ipswdl2_download_speed_bytes",
+            "gauge",
+            "Current download speed in bytes per second.",
+            vec![(String::new(), format!("{:.0}", self.speed()))],
+        );
+        metric(
+            "ipswdl2_passes_completed_total",
+            "counter",
+            "Passes over every device completed.",
+            vec![(String::new(), self.passes.load(Ordering::Relaxed).to_string())],
+        );
+
+        text
+    }
+
+    /// Writes the metrics to `path`, replacing it at once so the textfile collector never reads half a file.
+    ///
+    /// # Returns
+    /// * Err(Io) - The file could not be written.
+    pub fn write_file(&self, path: &Path) -> Result<()> {
+        let temp = PathBuf::from(format!("{}.tmp", path.display()));
+
+        std::fs::write(&temp, self.render()).map_err(|why| Error::io("could not write metrics to", &temp, why))?;
+        std::fs::rename(&temp, path).map_err(|why| Error::io("could not write metrics to", path, why))
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serves `metrics` at `/metrics` on `addr` until `cancel` is cancelled.
+///
+/// # Returns
+/// * Err(Serve) - The address could not be bound, or the server failed.
+pub async fn serve_metrics(metrics: Arc<Metrics>, addr: SocketAddr, cancel: CancellationToken) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(metrics.clone(), request))) }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!("serving metrics on http://{}/metrics", server.local_addr());
+
+    server.with_graceful_shutdown(async move { cancel.cancelled().await }).await?;
+
+    Ok(())
+}
+
+/// Answers a single request.
+async fn handle(metrics: Arc<Metrics>, request: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    let response = match request.uri().path() {
+        "/metrics" => Response::builder()
+            .header(CONTENT_TYPE, TEXT_FORMAT)
+            .body(Body::from(metrics.render())),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from(StatusCode::NOT_FOUND.to_string())),
+    };
+
+    Ok(response.expect("response headers are always valid"))
+}
+
+/// Escapes a label value, ex. a device name with quotes in it.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_per_device_metrics() {
+        let metrics = Metrics::new();
+        metrics.downloaded("iPhone 8");
+        metrics.failed("iPad \"Pro\"");
+        metrics.add_bytes(1024);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE ipswdl2_downloads_completed_total counter\n"));
+        assert!(text.contains("ipswdl2_downloads_completed_total{device=\"iPhone 8\"} 1\n"));
+        assert!(text.contains("ipswdl2_downloads_failed_total{device=\"iPad \\\"Pro\\\"\"} 1\n"));
+        assert!(text.contains("ipswdl2_downloaded_bytes_total 1024\n"));
+        //Devices that never succeeded have no timestamp
+        assert!(!text.contains("ipswdl2_last_success_timestamp_seconds{device=\"iPad"));
+    }
+}
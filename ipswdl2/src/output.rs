@@ -0,0 +1,221 @@
+//! Types for printing results in machine readable formats.
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use tracing::error;
+use serde::Serialize;
+
+use crate::api_json_types::Device;
+
+/// Format results are printed to stdout in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    /// Human readable, colored text.
+    Text,
+    /// One JSON value per line.
+    Json,
+    /// Comma separated values under a header row. Only listings of devices and firmware can be printed as CSV.
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+/// How download progress is shown.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProgressFormat {
+    /// Progress bars, or progress lines if output isn't a terminal.
+    Bars,
+    /// One [`ProgressEvent`] per line of stdout, in place of any other output.
+    Json,
+}
+
+impl FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bars" => Ok(ProgressFormat::Bars),
+            "json" => Ok(ProgressFormat::Json),
+            _ => Err(format!("unknown progress format: {}", s)),
+        }
+    }
+}
+
+/// A change in the state of a download run, for tools tracking it programmatically.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent<'a> {
+    /// Work on a device started.
+    DeviceStarted { device: &'a str },
+    /// A firmware started downloading.
+    DownloadStarted {
+        device: &'a str,
+        version: &'a str,
+        buildid: &'a str,
+        size: u64,
+    },
+    /// More of a firmware was downloaded.
+    Progress {
+        device: &'a str,
+        version: &'a str,
+        bytes: u64,
+        total: u64,
+    },
+    /// A downloaded firmware matched the API checksum.
+    Verified {
+        device: &'a str,
+        version: &'a str,
+        path: &'a Path,
+    },
+    /// A firmware was downloaded, skipped or failed.
+    Finished(&'a DownloadResult),
+    /// The run finished.
+    RunCompleted(&'a Summary),
+}
+
+/// Outcome of downloading a single firmware, or of a device that failed before any firmware was picked.
+#[derive(Serialize, Debug)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum DownloadResult {
+    /// Firmware was downloaded to `path`.
+    Downloaded {
+        device: String,
+        version: String,
+        buildid: String,
+        size: u64,
+        path: PathBuf,
+    },
+    /// Nothing was downloaded, for a reason that isn't an error. Ex. the file already exists.
+    Skipped {
+        device: String,
+        version: Option<String>,
+        path: Option<PathBuf>,
+        reason: String,
+    },
+    /// Downloading errored.
+    Failed {
+        device: String,
+        version: Option<String>,
+        error: String,
+    },
+}
+
+/// Totals for a whole download run.
+#[derive(Serialize, Debug)]
+pub struct Summary {
+    pub downloaded: u32,
+    pub skipped: u32,
+    pub failed: u32,
+    pub minutes: i64,
+    /// Names of the devices left for the next run, as the run reached its deadline or size limit.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deferred: Vec<String>,
+}
+
+/// Whether Apple still signs a firmware, meaning it can be restored.
+#[derive(Serialize, Debug)]
+pub struct SigningStatus {
+    pub device: String,
+    pub identifier: String,
+    pub version: String,
+    pub buildid: String,
+    pub signed: bool,
+    /// Where the firmware was downloaded to, when checking the library.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+/// A column of the device table.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeviceColumn {
+    Name,
+    Identifier,
+    Platform,
+    Board,
+}
+
+impl DeviceColumn {
+    /// Gets the name the column is picked by, also its heading in CSV output.
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceColumn::Name => "name",
+            DeviceColumn::Identifier => "identifier",
+            DeviceColumn::Platform => "platform",
+            DeviceColumn::Board => "boardconfig",
+        }
+    }
+
+    /// Gets the heading of the column.
+    pub fn header(self) -> &'static str {
+        match self {
+            DeviceColumn::Name => "Name",
+            DeviceColumn::Identifier => "Identifier",
+            DeviceColumn::Platform => "Platform",
+            DeviceColumn::Board => "Board",
+        }
+    }
+
+    /// Gets the value of the column for a device.
+    pub fn value(self, device: &Device) -> &str {
+        match self {
+            DeviceColumn::Name => &device.name,
+            DeviceColumn::Identifier => &device.identifier,
+            DeviceColumn::Platform => &device.platform,
+            DeviceColumn::Board => &device.boardconfig,
+        }
+    }
+}
+
+impl FromStr for DeviceColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(DeviceColumn::Name),
+            "identifier" => Ok(DeviceColumn::Identifier),
+            "platform" => Ok(DeviceColumn::Platform),
+            "boardconfig" => Ok(DeviceColumn::Board),
+            _ => Err(format!("unknown column: {}", s)),
+        }
+    }
+}
+
+/// Lays out rows under `headers` in columns as wide as their widest cell, separated by two spaces.
+pub fn format_table(headers: &[&str], rows: &[Vec<&str>]) -> Vec<String> {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: &[&str]| {
+        let padded: Vec<String> =
+            cells.iter().zip(&widths).map(|(cell, width)| format!("{:<1$}", cell, width)).collect();
+        //The last column isn't padded, so lines don't end in spaces
+        padded.join("  ").trim_end().to_string()
+    };
+
+    std::iter::once(format_row(headers))
+        .chain(rows.iter().map(|row| format_row(row)))
+        .collect()
+}
+
+/// Prints a value to stdout as a single line of JSON.
+pub fn print_json(value: &impl Serialize) {
+    match serde_json::to_string(value) {
+        Ok(json) => println!("{}", json),
+        Err(why) => error!("Failed to serialize output: {}", why),
+    }
+}
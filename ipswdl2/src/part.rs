@@ -0,0 +1,103 @@
+//! Partially downloaded files, kept next to their destination until finished.
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::error::{Error, Result};
+
+/// A download in progress at `<destination>.part`. Deleted when dropped, unless persisted to its destination or kept.
+///
+/// Being in the destination directory means finishing is a rename, rather than a copy of the whole file.
+pub struct PartFile {
+    path: PathBuf,
+    /// `None` once persisted.
+    file: Option<File>,
+}
+
+impl PartFile {
+    /// Opens the part file for `destination`, creating it along with any missing directories.
+    /// Anything left at the part path by an earlier download is kept, so it can be resumed.
+    pub fn open(destination: &Path) -> Result<Self> {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).map_err(|why| Error::io("could not create directory for", destination, why))?;
+        }
+
+        let path = part_path(destination);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            //Leftover part files are resumed, not started over
+            .truncate(false)
+            .open(&path)
+            .map_err(|why| Error::io("could not open part file", &path, why))?;
+
+        Ok(PartFile { path, file: Some(file) })
+    }
+
+    /// Path of the part file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The open part file.
+    pub fn as_file_mut(&mut self) -> &mut File {
+        self.file.as_mut().expect("part file used after persisting")
+    }
+
+    /// Moves the finished download to `destination`.
+    ///
+    /// This is an atomic rename, falling back to a copy if the destination is on another filesystem.
+    ///
+    /// # Returns
+    /// * Ok(u64) - The size of the file in bytes.
+    /// * Err(Io) - The file could not be moved.
+    pub fn persist(mut self, destination: &Path) -> Result<u64> {
+        let file = self.file.take().expect("part file persisted twice");
+        let bytes = file
+            .metadata()
+            .map_err(|why| Error::io("could not read", &self.path, why))?
+            .len();
+        drop(file);
+
+        if let Err(why) = fs::rename(&self.path, destination) {
+            debug!("could not rename {} ({}), copying instead", self.path.display(), why);
+
+            let copied = fs::copy(&self.path, destination);
+            let _ = fs::remove_file(&self.path);
+            copied.map_err(|why| Error::io("could not copy part file to", destination, why))?;
+        }
+
+        Ok(bytes)
+    }
+
+    /// Leaves an unfinished download at the part path, ex. when interrupted.
+    ///
+    /// # Returns
+    /// * Ok(u64) - The bytes kept.
+    /// * Err(Io) - The size of the file could not be read. The file is still kept.
+    pub fn keep(mut self) -> Result<u64> {
+        let file = self.file.take().expect("part file kept after persisting");
+
+        file.metadata()
+            .map(|metadata| metadata.len())
+            .map_err(|why| Error::io("could not read", &self.path, why))
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        //Don't leave a bad file behind if the download didn't finish
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Gets the path a download to `destination` is written to until it finishes, ex. `16.7.8.ipsw.part`.
+pub fn part_path(destination: &Path) -> PathBuf {
+    let mut path = destination.as_os_str().to_owned();
+    path.push(".part");
+    PathBuf::from(path)
+}
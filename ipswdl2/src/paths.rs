@@ -0,0 +1,422 @@
+//! Building the paths firmware is stored at.
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::api_json_types::{Firmware, FirmwareListing};
+
+/// Placeholders a [`PathTemplate`] may use.
+const PLACEHOLDERS: &[&str] = &["name", "identifier", "version", "buildid", "platform"];
+
+/// Names Windows reserves for devices. They can't be used as file names, even with an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+    "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes a string from the API safe to use as a single file or directory name on every platform.
+///
+/// Names are normalized to NFC, so the same name gives the same bytes whether it came from macOS (NFD) or elsewhere.
+/// `/` and `\` become `z`, as they always have so existing downloads keep their paths. Other characters Windows
+/// forbids (`<>:"|?*` and control characters) become `_`. Trailing dots and spaces, which Windows drops, are removed,
+/// and reserved device names like `CON` or `com1.txt` get a `_` added to their stem.
+pub fn sanitize_file_name(name: &str) -> String {
+    let replaced: String = name
+        .nfc()
+        .map(|c| match c {
+            '/' | '\\' => 'z',
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let mut sanitized = replaced.trim_end_matches(['.', ' ']).to_string();
+
+    let stem_len = sanitized.find('.').unwrap_or(sanitized.len());
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(sanitized[..stem_len].trim_end())) {
+        sanitized.insert(stem_len, '_');
+    }
+
+    if sanitized.is_empty() {
+        sanitized.push('_');
+    }
+    sanitized
+}
+
+/// Preset layouts of the download directory, each standing for a [`PathTemplate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// `{name}/{version}.ipsw`, the default.
+    #[default]
+    Name,
+    /// `{identifier}/{version}.ipsw`.
+    Identifier,
+    /// `{platform}/{name}/{version}.ipsw`.
+    Platform,
+    /// `{identifier}_{version}_{buildid}.ipsw`, every file in the download directory itself.
+    Flat,
+}
+
+impl Layout {
+    /// Gets the template this layout stands for.
+    pub fn template(self) -> PathTemplate {
+        let template = match self {
+            Layout::Name => return PathTemplate::default(),
+            Layout::Identifier => "{identifier}/{version}.ipsw",
+            Layout::Platform => "{platform}/{name}/{version}.ipsw",
+            Layout::Flat => "{identifier}_{version}_{buildid}.ipsw",
+        };
+
+        PathTemplate {
+            template: template.to_string(),
+            ascii: false,
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(Layout::Name),
+            "identifier" => Ok(Layout::Identifier),
+            "platform" => Ok(Layout::Platform),
+            "flat" => Ok(Layout::Flat),
+            _ => Err(format!("unknown layout: {}", s)),
+        }
+    }
+}
+
+/// What names the directory of each device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum DirBy {
+    /// The marketing name, ex. `iPad Pro (12.9-inch) (3rd generation)`. The default.
+    #[default]
+    Name,
+    /// The identifier, ex. `iPad8,5`. Unlike names, these never change between API updates.
+    Identifier,
+}
+
+impl FromStr for DirBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(DirBy::Name),
+            "identifier" => Ok(DirBy::Identifier),
+            _ => Err(format!("unknown directory key: {}", s)),
+        }
+    }
+}
+
+/// Layout of downloaded files under the download directory, ex. `{identifier}/{buildid}.ipsw`.
+///
+/// Placeholders are `{name}`, `{identifier}`, `{version}`, `{buildid}` and `{platform}`. `/` separates directories.
+/// The default is `{name}/{version}.ipsw`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathTemplate {
+    template: String,
+    /// Transliterate placeholder values to ASCII.
+    ascii: bool,
+}
+
+impl PathTemplate {
+    /// Transliterates placeholder values to ASCII if `ascii` is set, ex. `iPhone XS Max – Global` becomes
+    /// `iPhone XS Max - Global`. Useful on storage shared with filesystems that mangle other characters.
+    pub fn with_ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
+    }
+
+    /// Names directories by `dir_by`, ex. `{name}/{version}.ipsw` becomes `{identifier}/{version}.ipsw` when keyed by
+    /// identifier. File names are left alone.
+    pub fn with_dir_by(mut self, dir_by: DirBy) -> Self {
+        if let (DirBy::Identifier, Some(end)) = (dir_by, self.template.rfind('/')) {
+            let dirs = self.template[..end].replace("{name}", "{identifier}");
+            self.template = format!("{}{}", dirs, &self.template[end..]);
+        }
+        self
+    }
+
+    /// True if every device gets its own directory, rather than sharing one with other devices.
+    pub fn has_device_dirs(&self) -> bool {
+        match self.template.rfind('/') {
+            Some(end) => {
+                let dirs = &self.template[..end];
+                dirs.contains("{name}") || dirs.contains("{identifier}")
+            }
+            None => false,
+        }
+    }
+
+    /// True if the template names files by build id, so builds sharing a version never collide.
+    pub fn has_buildid(&self) -> bool {
+        self.template.contains("{buildid}")
+    }
+
+    /// Fills the template in for a firmware of the device in `listing`.
+    ///
+    /// Placeholder values are sanitized with [`sanitize_file_name`], so they can't add directories or break on Windows.
+    fn render(&self, listing: &FirmwareListing, firmware: &Firmware) -> String {
+        let mut rendered = self.template.clone();
+
+        for placeholder in PLACEHOLDERS {
+            let value = match *placeholder {
+                "name" => &listing.name,
+                "identifier" => &firmware.identifier,
+                "version" => &firmware.version,
+                "buildid" => &firmware.buildid,
+                _ => &listing.platform,
+            };
+            let value = if self.ascii {
+                sanitize_file_name(&deunicode::deunicode(value))
+            } else {
+                sanitize_file_name(value)
+            };
+
+            rendered = rendered.replace(&format!("{{{}}}", placeholder), &value);
+        }
+
+        rendered
+    }
+}
+
+impl Default for PathTemplate {
+    fn default() -> Self {
+        PathTemplate {
+            template: "{name}/{version}.ipsw".to_string(),
+            ascii: false,
+        }
+    }
+}
+
+impl fmt::Display for PathTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err("path template is empty".to_string());
+        }
+        if Path::new(s).is_absolute() || s.split('/').any(|part| part == "..") {
+            return Err(format!("path template must stay inside the download directory: {}", s));
+        }
+
+        //Every brace must open or close a known placeholder
+        let mut rest = s;
+        while let Some(start) = rest.find(['{', '}']) {
+            if &rest[start..start + 1] == "}" {
+                return Err(format!("unmatched }} in path template: {}", s));
+            }
+
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed {{ in path template: {}", s))?;
+            let placeholder = &rest[start + 1..start + end];
+
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!(
+                    "unknown placeholder {{{}}} in path template, expected one of: {}",
+                    placeholder,
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+
+            rest = &rest[start + end + 1..];
+        }
+
+        Ok(PathTemplate {
+            template: s.to_string(),
+            ascii: false,
+        })
+    }
+}
+
+/// Gets the path a firmware of the device in `listing` is stored at, following `template`.
+///
+/// If `with_buildid` is set and the template doesn't already use the build id, it is appended to the file name as
+/// `<stem>_<buildid>.<extension>`, to keep builds sharing a version apart.
+pub fn firmware_path(
+    download_path: &Path,
+    template: &PathTemplate,
+    listing: &FirmwareListing,
+    firmware: &Firmware,
+    with_buildid: bool,
+) -> PathBuf {
+    let mut file_path = download_path.join(template.render(listing, firmware));
+
+    if with_buildid && !template.has_buildid() {
+        let stem = file_path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let buildid = sanitize_file_name(&firmware.buildid);
+        let file_name = match file_path.extension() {
+            Some(extension) => format!("{}_{}.{}", stem, buildid, extension.to_string_lossy()),
+            None => format!("{}_{}", stem, buildid),
+        };
+        file_path.set_file_name(file_name);
+    }
+
+    file_path
+}
+
+/// Gets `path` as an extended-length path (`\\?\C:\...`), so files can be created past `MAX_PATH` on Windows.
+///
+/// Relative paths are made absolute, as extended-length paths are never normalized. Other platforms have no such
+/// limit, so `path` is returned as is.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+    use std::path::{Component, Prefix};
+
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        match std::env::current_dir() {
+            Ok(dir) => dir.join(path),
+            Err(_) => return path.to_path_buf(),
+        }
+    };
+
+    let mut components = absolute.components();
+    let mut long = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(letter) => PathBuf::from(format!(r"\\?\{}:\", letter as char)),
+            Prefix::UNC(server, share) => PathBuf::from(format!(
+                r"\\?\UNC\{}\{}\",
+                server.to_string_lossy(),
+                share.to_string_lossy()
+            )),
+            //Already extended-length, or a device path
+            _ => return absolute,
+        },
+        _ => return absolute,
+    };
+
+    for component in components {
+        match component {
+            Component::Normal(part) => long.push(part),
+            Component::ParentDir => {
+                long.pop();
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+
+    long
+}
+
+/// Gets `path` as an extended-length path, so files can be created past `MAX_PATH` on Windows.
+///
+/// Other platforms have no such limit, so `path` is returned as is.
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn listing() -> FirmwareListing {
+        serde_json::from_str(
+            r#"{"name":"iPhone 8","identifier":"iPhone10,1","platform":"iPhoneOS","boardconfig":"d20ap",
+            "cpid":32789,"bdid":2,"firmwares":[{"identifier":"iPhone10,1","version":"16.7.8","buildid":"20H343",
+            "sha1sum":"","md5sum":"","filesize":1,"url":"","uploaddate":"2024-05-13T17:00:00Z","signed":true}]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn renders_templates() {
+        let listing = listing();
+        let firmware = &listing.firmwares[0];
+        let dir = Path::new("ipsw");
+
+        let default = PathTemplate::default();
+        assert_eq!(firmware_path(dir, &default, &listing, firmware, false), dir.join("iPhone 8/16.7.8.ipsw"));
+        assert_eq!(firmware_path(dir, &default, &listing, firmware, true), dir.join("iPhone 8/16.7.8_20H343.ipsw"));
+
+        let by_build: PathTemplate = "{platform}/{identifier}/{buildid}.ipsw".parse().unwrap();
+        assert_eq!(
+            firmware_path(dir, &by_build, &listing, firmware, true),
+            dir.join("iPhoneOS/iPhone10,1/20H343.ipsw")
+        );
+    }
+
+    #[test]
+    fn sanitizes_file_names() {
+        assert_eq!(sanitize_file_name("iPad Pro (Wi-Fi/Cellular)"), "iPad Pro (Wi-FizCellular)");
+        assert_eq!(sanitize_file_name("Build: \"beta\" 1?"), "Build_ _beta_ 1_");
+        assert_eq!(sanitize_file_name("trailing. "), "trailing");
+        assert_eq!(sanitize_file_name("CON"), "CON_");
+        assert_eq!(sanitize_file_name("com1.ipsw"), "com1_.ipsw");
+        assert_eq!(sanitize_file_name("CONSOLE"), "CONSOLE");
+        assert_eq!(sanitize_file_name(".."), "_");
+        //NFD input gives the NFC name
+        assert_eq!(sanitize_file_name("Cafe\u{301}"), "Caf\u{e9}");
+    }
+
+    #[test]
+    fn transliterates_to_ascii() {
+        let mut listing = listing();
+        listing.name = "iPhone XS Max \u{2013} Caf\u{e9}".to_string();
+        let firmware = &listing.firmwares[0];
+
+        let template = PathTemplate::default();
+        assert_eq!(template.render(&listing, firmware), "iPhone XS Max \u{2013} Caf\u{e9}/16.7.8.ipsw");
+        let template = template.with_ascii(true);
+        assert_eq!(template.render(&listing, firmware), "iPhone XS Max - Cafe/16.7.8.ipsw");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn makes_long_paths() {
+        assert_eq!(
+            long_path(Path::new(r"C:\ipsw\.\iPhone 8\..\iPhone X\16.7.8.ipsw")),
+            PathBuf::from(r"\\?\C:\ipsw\iPhone X\16.7.8.ipsw")
+        );
+        assert_eq!(
+            long_path(Path::new(r"\\nas\share\16.7.8.ipsw")),
+            PathBuf::from(r"\\?\UNC\nas\share\16.7.8.ipsw")
+        );
+    }
+
+    #[test]
+    fn layouts_group_files() {
+        let listing = listing();
+        let firmware = &listing.firmwares[0];
+
+        assert_eq!(Layout::Name.template().render(&listing, firmware), "iPhone 8/16.7.8.ipsw");
+        assert_eq!(Layout::Identifier.template().render(&listing, firmware), "iPhone10,1/16.7.8.ipsw");
+        assert_eq!(Layout::Platform.template().render(&listing, firmware), "iPhoneOS/iPhone 8/16.7.8.ipsw");
+        assert_eq!(Layout::Flat.template().render(&listing, firmware), "iPhone10,1_16.7.8_20H343.ipsw");
+
+        assert!(Layout::Platform.template().has_device_dirs());
+        assert!(!Layout::Flat.template().has_device_dirs());
+        assert!(!"{platform}/{name}_{version}.ipsw".parse::<PathTemplate>().unwrap().has_device_dirs());
+    }
+
+    #[test]
+    fn keys_dirs_by_identifier() {
+        let template = Layout::Platform.template().with_dir_by(DirBy::Identifier);
+        assert_eq!(template.to_string(), "{platform}/{identifier}/{version}.ipsw");
+
+        let template: PathTemplate = "{name}/{name} {version}.ipsw".parse().unwrap();
+        assert_eq!(template.with_dir_by(DirBy::Identifier).to_string(), "{identifier}/{name} {version}.ipsw");
+        assert_eq!(Layout::Flat.template().with_dir_by(DirBy::Identifier), Layout::Flat.template());
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!("{name}/{model}.ipsw".parse::<PathTemplate>().is_err());
+        assert!("{name/{version}.ipsw".parse::<PathTemplate>().is_err());
+        assert!("../{version}.ipsw".parse::<PathTemplate>().is_err());
+        assert!("".parse::<PathTemplate>().is_err());
+    }
+}
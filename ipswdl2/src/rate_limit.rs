@@ -0,0 +1,59 @@
+//! Token bucket for capping download throughput.
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits the rate bytes are consumed at, shared between every download using it.
+///
+/// Up to one second worth of bytes may burst at once. Consuming more than is available puts the bucket in debt,
+/// which later callers wait to pay off.
+pub struct RateLimiter {
+    /// Bytes allowed per second.
+    bytes_per_sec: u64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Bytes that can be consumed without waiting. Negative when in debt.
+    tokens: f64,
+    /// Last time tokens were added to the bucket.
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `bytes_per_sec` bytes per second.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec: bytes_per_sec.max(1),
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Consumes `bytes` from the bucket, waiting until the rate allows it.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let rate = self.bytes_per_sec as f64;
+
+            //Refill for the time passed, up to one second worth
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+            bucket.last_refill = now;
+
+            bucket.tokens -= bytes as f64;
+
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / rate)
+            } else {
+                Duration::from_secs(0)
+            }
+        };
+
+        if wait > Duration::from_secs(0) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Remote destinations finished downloads are uploaded to, instead of being kept in the download directory.
+//!
+//! Firmware is still downloaded and verified locally first, and only removed once uploaded. Each backend makes sure
+//! a failed upload never looks like a finished file.
+use std::path::Path;
+
+use crate::error::Result;
+use crate::s3::S3Bucket;
+use crate::sftp::Sftp;
+use crate::webdav::WebDav;
+
+/// Where finished downloads are uploaded to.
+#[derive(Clone, Debug)]
+pub enum Remote {
+    /// A bucket on S3 or an S3 compatible server.
+    S3(S3Bucket),
+    /// A directory on a WebDAV server.
+    WebDav(WebDav),
+    /// A directory on an SFTP server.
+    Sftp(Sftp),
+}
+
+impl Remote {
+    /// True if a file exists at `path`, relative to the root of the remote.
+    pub async fn exists(&self, path: &str) -> Result<bool> {
+        match self {
+            Remote::S3(bucket) => bucket.exists(path).await,
+            Remote::WebDav(dav) => dav.exists(path).await,
+            Remote::Sftp(sftp) => sftp.exists(path).await,
+        }
+    }
+
+    /// Uploads a local file to `path`, relative to the root of the remote.
+    pub async fn upload(&self, file: &Path, path: &str) -> Result<()> {
+        match self {
+            Remote::S3(bucket) => bucket.upload(file, path).await,
+            Remote::WebDav(dav) => dav.upload(file, path).await,
+            Remote::Sftp(sftp) => sftp.upload(file, path).await,
+        }
+    }
+
+    /// Gets the URL of `path` on the remote, for messages.
+    pub fn url(&self, path: &str) -> String {
+        match self {
+            Remote::S3(bucket) => bucket.url(path),
+            Remote::WebDav(dav) => dav.url(path),
+            Remote::Sftp(sftp) => sftp.url(path),
+        }
+    }
+}
+
+/// URL schemes of remote destinations.
+const REMOTE_SCHEMES: &[&str] = &["s3://", "dav://", "davs://", "sftp://"];
+
+/// True if a download path names a remote destination, ex. `s3://bucket/prefix`, instead of a directory.
+pub fn is_remote(download_path: &str) -> bool {
+    REMOTE_SCHEMES.iter().any(|scheme| download_path.starts_with(scheme))
+}
+
+/// Gets the path of a file on a remote from its path relative to the download directory, always separated by `/`.
+pub fn remote_path(relative_path: &Path) -> String {
+    relative_path
+        .iter()
+        .map(|component| component.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn remote_paths_use_slashes() {
+        assert_eq!(remote_path(&Path::new("iPhone 8").join("16.7.8.ipsw")), "iPhone 8/16.7.8.ipsw");
+        assert!(is_remote("s3://archive/ipsw"));
+        assert!(is_remote("sftp://nas.local/srv/ipsw"));
+        assert!(!is_remote("./ipsw"));
+    }
+}
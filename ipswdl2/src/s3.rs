@@ -0,0 +1,385 @@
+//! Uploads finished downloads to S3, or S3 compatible storage like MinIO.
+//!
+//! Requests are signed with AWS Signature Version 4. Payloads aren't hashed, as they are sent over TLS.
+use std::fmt;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use tracing::{debug, error, info};
+use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::error::{Error, Result};
+
+/// Bytes uploaded in each part of a multipart upload. At most 10000 parts are allowed, so files may be up to 640GB.
+const PART_SIZE: usize = 64 * 1024 * 1024;
+
+/// Region used if none is configured.
+pub const DEFAULT_REGION: &str = "us-east-1";
+
+/// Sent instead of a payload hash, as the payload isn't signed.
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Keys to sign requests with.
+#[derive(Clone)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Token of temporary credentials, ex. from an assumed role.
+    pub session_token: Option<String>,
+}
+
+impl S3Credentials {
+    /// Reads credentials from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`.
+    ///
+    /// # Returns
+    /// * Err(S3) - The key id or secret isn't set.
+    pub fn from_env() -> Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+        match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(S3Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: var("AWS_SESSION_TOKEN"),
+            }),
+            _ => Err(Error::S3("AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set to upload to S3".to_string())),
+        }
+    }
+}
+
+impl fmt::Debug for S3Credentials {
+    //Keep secrets out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish()
+    }
+}
+
+/// A bucket, and the prefix under it uploads are stored at.
+#[derive(Clone, Debug)]
+pub struct S3Bucket {
+    bucket: String,
+    /// Prefix of every key, without slashes around it. May be empty.
+    prefix: String,
+    region: String,
+    /// URL of an S3 compatible server, addressed path style. Amazon's regional endpoint is used if `None`.
+    endpoint: Option<String>,
+    credentials: S3Credentials,
+    client: reqwest::Client,
+}
+
+impl S3Bucket {
+    /// Creates a bucket from a URL like `s3://bucket/prefix`.
+    ///
+    /// # Returns
+    /// * Err(S3) - The URL isn't an s3:// URL with a bucket.
+    /// * Err - The TLS backend failed to initialize.
+    pub fn new(url: &str, region: String, endpoint: Option<String>, credentials: S3Credentials) -> Result<Self> {
+        let (bucket, prefix) = parse_s3_url(url).ok_or_else(|| Error::S3(format!("not an s3://bucket/prefix URL: {}", url)))?;
+
+        Ok(S3Bucket {
+            bucket,
+            prefix,
+            region,
+            endpoint: endpoint.map(|endpoint| endpoint.trim_end_matches('/').to_string()),
+            credentials,
+            client: reqwest::ClientBuilder::new().build()?,
+        })
+    }
+
+    /// Gets the full key of a path under the prefix.
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+
+    /// Gets the s3:// URL of a path under the prefix, for messages.
+    pub fn url(&self, path: &str) -> String {
+        format!("s3://{}/{}", self.bucket, self.key(path))
+    }
+
+    /// True if an object exists at a path under the prefix.
+    pub async fn exists(&self, path: &str) -> Result<bool> {
+        let key = self.key(path);
+        let response = self.request(Method::HEAD, &key, &[])?.send().await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => check_status(response, &key).await.map(|_| true),
+        }
+    }
+
+    /// Uploads a file to a path under the prefix with a multipart upload, aborting it if any part fails.
+    pub async fn upload(&self, file: &Path, path: &str) -> Result<()> {
+        let key = self.key(path);
+
+        let response = self.request(Method::POST, &key, &[("uploads", "")])?.send().await?;
+        let body = check_status(response, &key).await?.text().await?;
+        let upload_id = xml_value(&body, "UploadId")
+            .ok_or_else(|| Error::S3(format!("no upload id in response to creating upload of {}", key)))?
+            .to_string();
+        debug!("started upload {} of {}", upload_id, key);
+
+        match self.upload_parts(file, &key, &upload_id).await {
+            Ok(()) => {
+                info!("uploaded {} to {}", file.display(), self.url(path));
+                Ok(())
+            }
+            Err(why) => {
+                //Abandoned uploads are billed for until aborted
+                let abort = self.request(Method::DELETE, &key, &[("uploadId", &upload_id)])?.send().await;
+                if let Err(abort_why) = abort {
+                    error!("failed to abort upload {} of {}: {}", upload_id, key, abort_why);
+                }
+                Err(why)
+            }
+        }
+    }
+
+    /// Uploads every part of a file, then completes the upload.
+    async fn upload_parts(&self, file: &Path, key: &str, upload_id: &str) -> Result<()> {
+        let mut reader = tokio::fs::File::open(file)
+            .await
+            .map_err(|why| Error::io("could not open file", file, why))?;
+        let mut etags = Vec::new();
+
+        loop {
+            let part = read_part(&mut reader).await.map_err(|why| Error::io("could not read file", file, why))?;
+            //Empty files still need a part
+            if part.is_empty() && !etags.is_empty() {
+                break;
+            }
+            let last = part.len() < PART_SIZE;
+
+            let part_number = (etags.len() + 1).to_string();
+            let response = self
+                .request(Method::PUT, key, &[("partNumber", &part_number), ("uploadId", upload_id)])?
+                .body(part)
+                .send()
+                .await?;
+            let response = check_status(response, key).await?;
+
+            let etag = response
+                .headers()
+                .get(reqwest::header::ETAG)
+                .and_then(|etag| etag.to_str().ok())
+                .ok_or_else(|| Error::S3(format!("no ETag for part {} of {}", part_number, key)))?;
+            etags.push(etag.to_string());
+
+            if last {
+                break;
+            }
+        }
+
+        let mut complete = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in etags.iter().enumerate() {
+            complete.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag));
+        }
+        complete.push_str("</CompleteMultipartUpload>");
+
+        let response = self.request(Method::POST, key, &[("uploadId", upload_id)])?.body(complete).send().await?;
+        //Completing can fail after the 200 is sent, which is reported in the body instead
+        let body = check_status(response, key).await?.text().await?;
+        if body.contains("<Error>") {
+            return Err(Error::S3(format!(
+                "completing upload of {} failed: {}",
+                key,
+                xml_value(&body, "Message").unwrap_or(&body)
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Builds a signed request for an object.
+    fn request(&self, method: Method, key: &str, query: &[(&str, &str)]) -> Result<RequestBuilder> {
+        let path = key.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let url = match &self.endpoint {
+            Some(endpoint) => format!("{}/{}/{}", endpoint, self.bucket, path),
+            None => format!("https://{}.s3.{}.amazonaws.com/{}", self.bucket, self.region, path),
+        };
+        let mut url = Url::parse(&url).map_err(|why| Error::S3(format!("invalid URL {}: {}", url, why)))?;
+
+        let mut query: Vec<(String, String)> = query.iter().map(|(k, v)| (uri_encode(k), uri_encode(v))).collect();
+        query.sort();
+        let query = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+        if !query.is_empty() {
+            url.set_query(Some(&query));
+        }
+
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(Error::S3(format!("no host in {}", url))),
+        };
+
+        let now = Utc::now();
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", UNSIGNED_PAYLOAD.to_string()),
+            ("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let authorization = self.authorization(method.as_str(), url.path(), &query, &headers, now);
+
+        let mut request = self.client.request(method, url).header("authorization", authorization);
+        //reqwest sets the host itself
+        for (name, value) in headers.into_iter().skip(1) {
+            request = request.header(name, value);
+        }
+
+        Ok(request)
+    }
+
+    /// Signs a request, giving its Authorization header. `headers` must be sorted by name.
+    fn authorization(
+        &self,
+        method: &str,
+        path: &str,
+        query: &str,
+        headers: &[(&str, String)],
+        now: DateTime<Utc>,
+    ) -> String {
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, path, query, canonical_headers, signed_headers, UNSIGNED_PAYLOAD
+        );
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            now.format("%Y%m%dT%H%M%SZ"),
+            scope,
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let key = signing_key(&self.credentials.secret_access_key, &date, &self.region, "s3");
+        let signature = hmac_sha256(&key, string_to_sign.as_bytes());
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key_id,
+            scope,
+            signed_headers,
+            hex(&signature)
+        )
+    }
+}
+
+/// Splits an `s3://bucket/prefix` URL into its bucket and prefix. The prefix may be empty.
+pub fn parse_s3_url(url: &str) -> Option<(String, String)> {
+    let rest = url.strip_prefix("s3://")?;
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+
+    if bucket.is_empty() {
+        return None;
+    }
+    Some((bucket.to_string(), prefix.trim_matches('/').to_string()))
+}
+
+/// Reads up to a part worth of bytes, less only at the end of the file.
+async fn read_part(reader: &mut tokio::fs::File) -> std::io::Result<Vec<u8>> {
+    let mut part = Vec::with_capacity(PART_SIZE);
+    (&mut *reader).take(PART_SIZE as u64).read_to_end(&mut part).await?;
+
+    Ok(part)
+}
+
+/// Turns a non-success response into an error, with S3's message if it sent one.
+async fn check_status(response: Response, key: &str) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    match xml_value(&body, "Message") {
+        Some(message) => Err(Error::S3(format!("request for {} failed with status {}: {}", key, status, message))),
+        None => Err(Error::Status {
+            what: key.to_string(),
+            status,
+            retry_after: None,
+        }),
+    }
+}
+
+/// Gets the text of the first `tag` element in an XML document.
+fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+
+    Some(&xml[start..end])
+}
+
+/// Derives the key requests on a day are signed with.
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent encodes everything but unreserved characters, as SigV4 requires.
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_s3_urls() {
+        assert_eq!(parse_s3_url("s3://archive/ipsw/"), Some(("archive".to_string(), "ipsw".to_string())));
+        assert_eq!(parse_s3_url("s3://archive"), Some(("archive".to_string(), String::new())));
+        assert_eq!(parse_s3_url("s3:///ipsw"), None);
+        assert_eq!(parse_s3_url("./ipsw"), None);
+    }
+
+    #[test]
+    fn derives_signing_keys() {
+        //Example from Amazon's SigV4 documentation
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+
+        assert_eq!(hex(&key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+    }
+
+    #[test]
+    fn reads_xml_values() {
+        let xml = "<InitiateMultipartUploadResult><Key>a</Key><UploadId>abc123</UploadId></InitiateMultipartUploadResult>";
+
+        assert_eq!(xml_value(xml, "UploadId"), Some("abc123"));
+        assert_eq!(xml_value(xml, "Message"), None);
+        assert_eq!(uri_encode("iPhone 8,1"), "iPhone%208%2C1");
+    }
+}
@@ -0,0 +1,368 @@
+//! Serves a download directory over HTTP, so other machines can install firmware straight from a mirror.
+//!
+//! `/` is an index page linking every downloaded firmware, `/index.json` lists them as JSON, and each file is served
+//! under `/files/`. Only files recorded in the index are served, so nothing else in the directory is exposed.
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use hyper::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use indicatif::HumanBytes;
+use tracing::{error, info};
+use serde::Serialize;
+use tokio::io::AsyncReadExt;
+
+use crate::error::Result;
+use crate::index::{Index, IndexEntry, INDEX_FILE_NAME};
+
+/// Address served on if none is configured, every interface on port 8080.
+pub const DEFAULT_SERVE_ADDR: &str = "0.0.0.0:8080";
+
+/// Bytes read from disk at once while sending a file.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// A downloaded firmware, as listed by `/index.json`.
+#[derive(Serialize, Debug)]
+struct ListedFirmware {
+    #[serde(flatten)]
+    entry: IndexEntry,
+    /// Path the file is served at, ex. "/files/iPhone%208/16.7.8.ipsw".
+    url: String,
+}
+
+/// The firmware served from a download directory.
+struct Library {
+    download_path: PathBuf,
+    /// Opened on the first request after the index exists, then shared by every request.
+    index: Mutex<Option<Index>>,
+}
+
+impl Library {
+    /// Gets every firmware in the index, with paths relative to the download directory.
+    /// Empty if nothing was ever downloaded there.
+    fn entries(&self) -> Result<Vec<IndexEntry>> {
+        let mut index = self.index.lock().expect("library lock poisoned");
+
+        if index.is_none() {
+            //Don't create an index in a directory that was never downloaded into
+            if !self.download_path.join(INDEX_FILE_NAME).exists() {
+                return Ok(Vec::new());
+            }
+            *index = Some(Index::open(&self.download_path)?);
+        }
+
+        index.as_ref().expect("index was just opened").entries()
+    }
+}
+
+/// Serves `download_path` on `addr` until ctrl-c is pressed.
+///
+/// # Returns
+/// * Err(Serve) - The address could not be bound, or the server failed.
+pub async fn serve(download_path: PathBuf, addr: SocketAddr) -> Result<()> {
+    let library = Arc::new(Library {
+        download_path,
+        index: Mutex::new(None),
+    });
+
+    let make_service = make_service_fn(move |_| {
+        let library = library.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(library.clone(), request))) }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!("serving on http://{}", server.local_addr());
+
+    server
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Answers a single request.
+async fn handle(library: Arc<Library>, request: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    info!("{} {}", request.method(), request.uri().path());
+
+    let entries = {
+        let library = library.clone();
+        tokio::task::spawn_blocking(move || library.entries())
+            .await
+            .expect("library task panicked")
+    };
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(why) => {
+            error!("failed to read the firmware index: {}", why);
+            return Ok(status(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    };
+
+    let response = match request.uri().path() {
+        "/" => Response::builder()
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(index_page(&entries))),
+        "/index.json" => {
+            let listed: Vec<_> = entries
+                .into_iter()
+                .map(|entry| ListedFirmware {
+                    url: file_url(&entry.path),
+                    entry,
+                })
+                .collect();
+
+            Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&listed).expect("firmware listing is always valid JSON")))
+        }
+        path => {
+            let requested = path.strip_prefix("/files/").and_then(percent_decode).map(PathBuf::from);
+
+            match requested.and_then(|requested| entries.into_iter().find(|entry| entry.path == requested)) {
+                Some(entry) => return Ok(send_file(&library.download_path, &entry, request.method() == Method::HEAD).await),
+                None => return Ok(status(StatusCode::NOT_FOUND)),
+            }
+        }
+    };
+
+    Ok(response.expect("response headers are always valid"))
+}
+
+/// Streams a downloaded file, or only its headers for HEAD requests.
+async fn send_file(download_path: &Path, entry: &IndexEntry, head: bool) -> Response<Body> {
+    let path = download_path.join(&entry.path);
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(why) => {
+            error!("could not open {}: {}", path.display(), why);
+            return status(StatusCode::NOT_FOUND);
+        }
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(why) => {
+            error!("could not read metadata of {}: {}", path.display(), why);
+            return status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let file_name = entry.path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+    let body = if head {
+        Body::empty()
+    } else {
+        //Once reading fails, end the stream after reporting it
+        Body::wrap_stream(futures::stream::unfold(Some(file), |file| async move {
+            let mut file = file?;
+            let mut buf = vec![0; CHUNK_SIZE];
+
+            match file.read(&mut buf).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    buf.truncate(read);
+                    Some((Ok(Bytes::from(buf)), Some(file)))
+                }
+                Err(why) => Some((Err(why), None)),
+            }
+        }))
+    };
+
+    let response = Response::builder()
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header(CONTENT_LENGTH, len)
+        .header(CONTENT_DISPOSITION, content_disposition(&file_name))
+        .body(body);
+
+    match response {
+        Ok(response) => response,
+        Err(why) => {
+            error!("could not build response for {}: {}", path.display(), why);
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Builds a Content-Disposition header downloading as `file_name`.
+///
+/// Header values must be visible ASCII, so the plain `filename` replaces anything else with `_`,
+/// while `filename*` carries the full name percent encoded as in RFC 5987.
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            ' ' => c,
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+
+    let mut encoded = String::new();
+    for byte in file_name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_'
+            | b'`' | b'|' | b'~' => encoded.push(byte as char),
+            _ => write!(encoded, "%{:02X}", byte).expect("writing to a String can't fail"),
+        }
+    }
+
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+/// Builds an empty response with a status.
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(status.to_string()))
+        .expect("response headers are always valid")
+}
+
+/// Renders the index page, a table linking every firmware.
+fn index_page(entries: &[IndexEntry]) -> String {
+    let mut page = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>ipswdl2</title></head>\n<body>\n\
+        <h1>Firmware</h1>\n<p><a href=\"/index.json\">JSON listing</a></p>\n<table>\n\
+        <tr><th>Device</th><th>Identifier</th><th>Version</th><th>Build</th><th>Size</th><th>SHA1</th></tr>\n",
+    );
+
+    for entry in entries {
+        writeln!(
+            page,
+            "<tr><td>{}</td><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+            escape_html(&entry.device),
+            escape_html(&entry.identifier),
+            file_url(&entry.path),
+            escape_html(&entry.version),
+            escape_html(&entry.buildid),
+            HumanBytes(entry.size),
+            escape_html(&entry.sha1)
+        )
+        .expect("writing to a String can't fail");
+    }
+
+    page.push_str("</table>\n</body>\n</html>\n");
+    page
+}
+
+/// Gets the URL a file in the library is served at, percent encoding each directory.
+fn file_url(path: &Path) -> String {
+    let mut url = String::from("/files");
+
+    for component in path.iter() {
+        url.push('/');
+        for byte in component.to_string_lossy().bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => url.push(byte as char),
+                _ => write!(url, "%{:02X}", byte).expect("writing to a String can't fail"),
+            }
+        }
+    }
+
+    url
+}
+
+/// Decodes a percent encoded URL path. `None` if it has an invalid escape, or isn't UTF-8.
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.bytes();
+
+    while let Some(byte) = rest.next() {
+        if byte == b'%' {
+            let hex = [rest.next()?, rest.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Escapes text for use in HTML.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_urls_round_trip() {
+        let path = Path::new("iPhone 8").join("16.7.8.ipsw");
+        let url = file_url(&path);
+
+        assert_eq!(url, "/files/iPhone%208/16.7.8.ipsw");
+        assert_eq!(
+            percent_decode(url.strip_prefix("/files/").unwrap()).map(PathBuf::from),
+            Some(path)
+        );
+        assert_eq!(percent_decode("bad%2"), None);
+    }
+
+    #[test]
+    fn library_opens_the_index_once_it_exists() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = Library {
+            download_path: dir.path().to_path_buf(),
+            index: Mutex::new(None),
+        };
+
+        assert!(library.entries().unwrap().is_empty());
+        assert!(!dir.path().join(INDEX_FILE_NAME).exists());
+
+        Index::open(dir.path())
+            .unwrap()
+            .record(&IndexEntry {
+                device: "iPhone 8".to_string(),
+                identifier: "iPhone10,1".to_string(),
+                version: "16.7.8".to_string(),
+                buildid: "20H343".to_string(),
+                sha1: "abc".to_string(),
+                size: 42,
+                path: PathBuf::from("iPhone 8/16.7.8.ipsw"),
+                downloaded: chrono::Utc::now(),
+            })
+            .unwrap();
+        assert_eq!(library.entries().unwrap().len(), 1);
+        assert!(library.index.lock().unwrap().is_some());
+    }
+
+    #[test]
+    fn content_disposition_is_a_valid_header() {
+        let header = content_disposition("iPhone \"8\"\n\u{e9}.ipsw");
+
+        assert_eq!(header, "attachment; filename=\"iPhone _8___.ipsw\"; filename*=UTF-8''iPhone%20%228%22%0A%C3%A9.ipsw");
+        assert!(hyper::header::HeaderValue::from_str(&header).is_ok());
+    }
+
+    #[test]
+    fn index_page_escapes_names() {
+        let entry = IndexEntry {
+            device: "<iPhone>".to_string(),
+            identifier: "iPhone10,1".to_string(),
+            version: "16.7.8".to_string(),
+            buildid: "20H343".to_string(),
+            sha1: "abc".to_string(),
+            size: 42,
+            path: PathBuf::from("iPhone 8/16.7.8.ipsw"),
+            downloaded: chrono::Utc::now(),
+        };
+        let page = index_page(&[entry]);
+
+        assert!(page.contains("&lt;iPhone&gt;"));
+        assert!(page.contains("href=\"/files/iPhone%208/16.7.8.ipsw\""));
+    }
+}
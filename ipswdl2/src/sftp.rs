@@ -0,0 +1,171 @@
+//! Uploads finished downloads over SFTP.
+//!
+//! Transfers are done by the system `sftp` client in batch mode, so keys, agents and `~/.ssh/config` work as usual.
+//! Passwords can't be typed in, so key authentication must be set up. Files are uploaded under a temporary `.part`
+//! name, then renamed into place, so a failed upload never looks finished.
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+
+use tracing::{debug, info};
+use reqwest::Url;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::error::{Error, Result};
+
+/// A directory on an SFTP server.
+#[derive(Clone, Debug)]
+pub struct Sftp {
+    /// Who to connect as, ex. `me@nas.local` or `nas.local`.
+    destination: String,
+    port: Option<u16>,
+    /// Directory uploads are stored under, without a trailing slash.
+    root: String,
+    /// Path of the sftp executable.
+    program: PathBuf,
+}
+
+impl Sftp {
+    /// Creates an SFTP destination from a URL like `sftp://user@host:port/path`, uploading with `program`.
+    ///
+    /// # Returns
+    /// * Err(Sftp) - The URL isn't an sftp:// URL with a host.
+    pub fn new(url: &str, program: PathBuf) -> Result<Self> {
+        let invalid = || Error::Sftp(format!("not an sftp://host/path URL: {}", url));
+
+        let parsed = Url::parse(url).map_err(|_| invalid())?;
+        if parsed.scheme() != "sftp" {
+            return Err(invalid());
+        }
+        let host = parsed.host_str().ok_or_else(invalid)?;
+
+        let destination = match parsed.username() {
+            "" => host.to_string(),
+            username => format!("{}@{}", username, host),
+        };
+
+        Ok(Sftp {
+            destination,
+            port: parsed.port(),
+            root: parsed.path().trim_end_matches('/').to_string(),
+            program,
+        })
+    }
+
+    /// Gets the path of a file under the directory on the server.
+    fn remote_path(&self, path: &str) -> String {
+        format!("{}/{}", self.root, path)
+    }
+
+    /// Gets the sftp:// URL of a path under the directory, for messages.
+    pub fn url(&self, path: &str) -> String {
+        match self.port {
+            Some(port) => format!("sftp://{}:{}{}", self.destination, port, self.remote_path(path)),
+            None => format!("sftp://{}{}", self.destination, self.remote_path(path)),
+        }
+    }
+
+    /// True if a file exists at a path under the directory.
+    pub async fn exists(&self, path: &str) -> Result<bool> {
+        let output = self.batch(&format!("ls {}\n", quote(&self.remote_path(path)))).await?;
+        if output.status.success() {
+            return Ok(true);
+        }
+
+        //sftp exits with 1 for both missing files and failed connections, so tell them apart by the message
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("not found") || stderr.contains("No such file") {
+            Ok(false)
+        } else {
+            Err(self.failed(&output))
+        }
+    }
+
+    /// Uploads a file to a path under the directory, creating missing directories.
+    pub async fn upload(&self, file: &Path, path: &str) -> Result<()> {
+        let remote_path = self.remote_path(path);
+        let part_path = format!("{}.part", remote_path);
+
+        //Directories that already exist fail to be made, which the leading - ignores
+        let mut commands = String::new();
+        let segments: Vec<&str> = path.split('/').collect();
+        let mut dir = self.root.clone();
+        for segment in &segments[..segments.len() - 1] {
+            dir = format!("{}/{}", dir, segment);
+            commands.push_str(&format!("-mkdir {}\n", quote(&dir)));
+        }
+        commands.push_str(&format!("put {} {}\n", quote(&file.to_string_lossy()), quote(&part_path)));
+        //A file already at the destination would make a plain rename fail
+        commands.push_str(&format!("-rm {}\n", quote(&remote_path)));
+        commands.push_str(&format!("rename {} {}\n", quote(&part_path), quote(&remote_path)));
+
+        let output = self.batch(&commands).await?;
+        if !output.status.success() {
+            return Err(self.failed(&output));
+        }
+
+        info!("uploaded {} to {}", file.display(), self.url(path));
+        Ok(())
+    }
+
+    /// Runs sftp commands in batch mode, stopping at the first failing command not prefixed with `-`.
+    async fn batch(&self, commands: &str) -> Result<Output> {
+        debug!("running sftp commands on {}:\n{}", self.destination, commands);
+
+        let mut command = Command::new(&self.program);
+        command.arg("-b").arg("-").arg("-o").arg("BatchMode=yes");
+        if let Some(port) = self.port {
+            command.arg("-P").arg(port.to_string());
+        }
+        let mut child = command
+            .arg(&self.destination)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|why| Error::io("could not run", &self.program, why))?;
+
+        let mut stdin = child.stdin.take().expect("sftp stdin is piped");
+        stdin
+            .write_all(commands.as_bytes())
+            .await
+            .map_err(|why| Error::io("could not write commands to", &self.program, why))?;
+        //Closing stdin ends the batch
+        drop(stdin);
+
+        child
+            .wait_with_output()
+            .await
+            .map_err(|why| Error::io("could not run", &self.program, why))
+    }
+
+    /// Describes a failed sftp run by the last thing it printed.
+    fn failed(&self, output: &Output) -> Error {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or("no output");
+
+        Error::Sftp(format!("sftp to {} exited with {}: {}", self.destination, output.status, reason))
+    }
+}
+
+/// Quotes a path for an sftp batch command.
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_sftp_urls() {
+        let sftp = Sftp::new("sftp://me@nas.local:2222/srv/ipsw/", PathBuf::from("sftp")).unwrap();
+
+        assert_eq!(sftp.destination, "me@nas.local");
+        assert_eq!(sftp.port, Some(2222));
+        assert_eq!(sftp.url("iPhone 8/16.7.8.ipsw"), "sftp://me@nas.local:2222/srv/ipsw/iPhone 8/16.7.8.ipsw");
+
+        assert!(Sftp::new("dav://nas.local/ipsw", PathBuf::from("sftp")).is_err());
+        assert_eq!(quote("a \"b\""), "\"a \\\"b\\\"\"");
+    }
+}
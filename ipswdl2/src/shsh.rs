@@ -0,0 +1,60 @@
+//! Saving SHSH2 blobs, the signing tickets needed to restore firmware after Apple stops signing it.
+//!
+//! Blobs are requested from Apple by [tsschecker](https://github.com/1Conan/tsschecker), which must be installed.
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+use tokio::process::Command;
+
+use crate::api_json_types::Firmware;
+use crate::error::{Error, Result};
+
+/// How to save blobs, and for which device.
+#[derive(Clone, Debug)]
+pub struct BlobOptions {
+    /// ECID of the device to save blobs for, in hex or decimal.
+    pub ecid: String,
+    /// Path of the tsschecker executable.
+    pub tsschecker: PathBuf,
+}
+
+/// Gets the directory the blobs of an ipsw are saved to, ex. `16.7.8.shsh2` for `16.7.8.ipsw`.
+pub fn blobs_dir(ipsw_path: &Path) -> PathBuf {
+    ipsw_path.with_extension("shsh2")
+}
+
+/// Requests blobs of `firmware` for the device in `opt` from Apple, saving them in `dir`.
+///
+/// # Returns
+/// * Err(Io) - tsschecker could not be run, or the directory could not be created.
+/// * Err(Blobs) - tsschecker failed, ex. as the firmware is no longer signed.
+pub async fn save_blobs(opt: &BlobOptions, boardconfig: &str, firmware: &Firmware, dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir).map_err(|why| Error::io("could not create directory", dir, why))?;
+
+    let output = Command::new(&opt.tsschecker)
+        .arg("--device")
+        .arg(&firmware.identifier)
+        .arg("--boardconfig")
+        .arg(boardconfig)
+        .arg("--ecid")
+        .arg(&opt.ecid)
+        .arg("--ios")
+        .arg(&firmware.version)
+        .arg("--buildid")
+        .arg(&firmware.buildid)
+        .arg("--save")
+        .arg("--save-path")
+        .arg(dir)
+        .output()
+        .await
+        .map_err(|why| Error::io("could not run", &opt.tsschecker, why))?;
+    debug!("tsschecker said: {}", String::from_utf8_lossy(&output.stdout));
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or("no output");
+        Err(Error::Blobs(format!("tsschecker exited with {}: {}", output.status, reason)))
+    }
+}
@@ -0,0 +1,74 @@
+//! `SHA1SUMS` files listing the checksums of the firmware in each directory, in the format of `sha1sum`, so the
+//! library can be audited with `sha1sum -c SHA1SUMS` without this tool.
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+/// Name of the checksum file in each directory holding firmware.
+pub const SHA1SUMS_FILE_NAME: &str = "SHA1SUMS";
+
+/// Records the SHA1 of the file at `file_path` in the `SHA1SUMS` file next to it.
+///
+/// An earlier line for the same file is replaced, and lines of files that no longer exist are dropped, so the list
+/// stays checkable after re-downloads and deletions.
+pub fn record_sha1(file_path: &Path, sha1: &str) -> Result<()> {
+    let (dir, file_name) = match (file_path.parent(), file_path.file_name()) {
+        (Some(dir), Some(file_name)) => (dir, file_name.to_string_lossy()),
+        _ => return Ok(()),
+    };
+    let sums_path = dir.join(SHA1SUMS_FILE_NAME);
+
+    let existing = match std::fs::read_to_string(&sums_path) {
+        Ok(existing) => existing,
+        Err(why) if why.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(why) => return Err(Error::io("could not read", &sums_path, why)),
+    };
+
+    let mut sums: String = existing
+        .lines()
+        .filter(|line| match parse_line(line) {
+            Some((_, name)) => name != file_name && dir.join(name).exists(),
+            None => false,
+        })
+        .map(|line| format!("{}\n", line))
+        .collect();
+    //Two spaces mark a text mode entry, which sha1sum expects by default
+    sums.push_str(&format!("{}  {}\n", sha1, file_name));
+
+    std::fs::write(&sums_path, sums).map_err(|why| Error::io("could not write", &sums_path, why))
+}
+
+/// Splits a line of a `SHA1SUMS` file into its checksum and file name.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let (sha1, name) = line.split_once(' ')?;
+    //Binary mode entries mark the name with '*' instead of a second space
+    let name = name.strip_prefix(' ').or_else(|| name.strip_prefix('*'))?;
+
+    Some((sha1, name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replaces_entries_of_the_same_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("16.7.7.ipsw");
+        let new = dir.path().join("16.7.8.ipsw");
+        std::fs::write(&old, "old").unwrap();
+        std::fs::write(&new, "new").unwrap();
+
+        record_sha1(&old, "aaaa").unwrap();
+        record_sha1(&new, "bbbb").unwrap();
+        record_sha1(&new, "cccc").unwrap();
+        let sums = std::fs::read_to_string(dir.path().join(SHA1SUMS_FILE_NAME)).unwrap();
+        assert_eq!(sums, "aaaa  16.7.7.ipsw\ncccc  16.7.8.ipsw\n");
+
+        //Deleted files are dropped on the next write
+        std::fs::remove_file(&old).unwrap();
+        record_sha1(&new, "dddd").unwrap();
+        let sums = std::fs::read_to_string(dir.path().join(SHA1SUMS_FILE_NAME)).unwrap();
+        assert_eq!(sums, "dddd  16.7.8.ipsw\n");
+    }
+}
@@ -0,0 +1,238 @@
+//! Mirrors the downloaded library to another directory or a remote.
+//!
+//! Every file is checked against the SHA1 recorded when it was downloaded before being copied, so a corrupt file
+//! never spreads to the mirror.
+use std::path::{Path, PathBuf};
+
+use tracing::{error, info};
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::export::library_manifest;
+use crate::index::{Index, IndexEntry};
+use crate::lock::DirLock;
+use crate::part::part_path;
+use crate::remote::{remote_path, Remote};
+use crate::verify::sha1_file;
+
+/// Where a library is mirrored to.
+#[derive(Debug)]
+pub enum SyncTarget {
+    /// Another directory, which gets its own index so it is a library itself.
+    Dir(PathBuf),
+    /// A remote destination. Files already there are assumed to be current.
+    Remote(Remote),
+}
+
+/// What was done to a file.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncAction {
+    /// The file was new or changed, and was copied.
+    Copied,
+    /// The mirror already has the file.
+    Unchanged,
+    /// The file is no longer in the library, so was deleted from the mirror.
+    Deleted,
+    /// The file could not be mirrored.
+    Failed,
+}
+
+/// The result of mirroring a single file.
+#[derive(Serialize, Debug)]
+pub struct SyncResult {
+    /// Path of the file, relative to the library.
+    pub path: PathBuf,
+    pub action: SyncAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SyncResult {
+    fn new(path: &Path, action: SyncAction) -> Self {
+        SyncResult {
+            path: path.to_path_buf(),
+            action,
+            error: None,
+        }
+    }
+
+    fn failed(path: &Path, why: impl std::fmt::Display) -> Self {
+        error!("failed to sync {}: {}", path.display(), why);
+
+        SyncResult {
+            path: path.to_path_buf(),
+            action: SyncAction::Failed,
+            error: Some(why.to_string()),
+        }
+    }
+}
+
+/// Copies every new or changed file in the library at `download_path` to `target`.
+///
+/// If `delete` is set, files the mirror holds that are no longer in the library are deleted from it. Only directory
+/// targets can be deleted from, as their index says what was synced there.
+///
+/// # Returns
+/// * Ok(Vec< SyncResult >) - What was done to each file. Files failing to sync don't stop the others.
+/// * Err(Locked) - Another run is downloading into the library or the target directory.
+/// * Err - The library or the target's index could not be read.
+pub async fn sync(download_path: &Path, target: &SyncTarget, delete: bool) -> Result<Vec<SyncResult>> {
+    //Downloads are persisted in steps, so never sync while one is running on either side
+    let _lock = DirLock::acquire(download_path)?;
+    let (_target_lock, target_index) = match target {
+        SyncTarget::Dir(dir) => (Some(DirLock::acquire(dir)?), Some(Index::open(dir)?)),
+        SyncTarget::Remote(_) => (None, None),
+    };
+
+    let library = library_manifest(download_path)?;
+    let mut results = Vec::new();
+
+    for mut entry in library.iter().cloned() {
+        let source = entry.path.clone();
+        entry.path = source.strip_prefix(download_path).unwrap_or(&source).to_path_buf();
+
+        let result = match target {
+            SyncTarget::Dir(dir) => sync_to_dir(&source, &entry, dir, target_index.as_ref().expect("dirs have an index")).await,
+            SyncTarget::Remote(remote) => sync_to_remote(&source, &entry, remote).await,
+        };
+        results.push(result.unwrap_or_else(|why| SyncResult::failed(&entry.path, why)));
+    }
+
+    if let (true, SyncTarget::Dir(dir), Some(index)) = (delete, target, &target_index) {
+        for entry in index.entries()? {
+            let in_library = library
+                .iter()
+                .any(|source| source.identifier == entry.identifier && source.buildid == entry.buildid);
+            if in_library {
+                continue;
+            }
+
+            let path = dir.join(&entry.path);
+            let result = match std::fs::remove_file(&path) {
+                Ok(()) => index.remove_path(&entry.path).map(|_| SyncResult::new(&entry.path, SyncAction::Deleted)),
+                Err(why) => Err(Error::io("could not delete", &path, why)),
+            };
+            results.push(result.unwrap_or_else(|why| SyncResult::failed(&entry.path, why)));
+        }
+    }
+
+    Ok(results)
+}
+
+/// Copies a file to a directory, unless it is already there with the same checksum.
+async fn sync_to_dir(source: &Path, entry: &IndexEntry, dir: &Path, index: &Index) -> Result<SyncResult> {
+    let dest = dir.join(&entry.path);
+
+    if dest.exists() && sha1_file(dest.clone()).await? == entry.sha1 {
+        index.record(entry)?;
+        return Ok(SyncResult::new(&entry.path, SyncAction::Unchanged));
+    }
+    check_source(source, entry).await?;
+
+    //Copy next to the destination first, so an interrupted copy never looks finished
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent).map_err(|why| Error::io("could not create directory", parent, why))?;
+    }
+    let part = part_path(&dest);
+    tokio::fs::copy(source, &part)
+        .await
+        .map_err(|why| Error::io("could not copy to", &part, why))?;
+
+    if sha1_file(part.clone()).await? != entry.sha1 {
+        std::fs::remove_file(&part).ok();
+        return Err(Error::ChecksumMismatch(dest));
+    }
+    std::fs::rename(&part, &dest).map_err(|why| Error::io("could not move copy to", &dest, why))?;
+
+    index.record(entry)?;
+    info!("synced {} to {}", source.display(), dest.display());
+    Ok(SyncResult::new(&entry.path, SyncAction::Copied))
+}
+
+/// Uploads a file to a remote, unless it is already there.
+async fn sync_to_remote(source: &Path, entry: &IndexEntry, remote: &Remote) -> Result<SyncResult> {
+    let path = remote_path(&entry.path);
+
+    if remote.exists(&path).await? {
+        return Ok(SyncResult::new(&entry.path, SyncAction::Unchanged));
+    }
+    check_source(source, entry).await?;
+
+    remote.upload(source, &path).await?;
+    info!("synced {} to {}", source.display(), remote.url(&path));
+    Ok(SyncResult::new(&entry.path, SyncAction::Copied))
+}
+
+/// Makes sure a library file still matches the checksum it was downloaded with.
+async fn check_source(source: &Path, entry: &IndexEntry) -> Result<()> {
+    if sha1_file(source.to_path_buf()).await? == entry.sha1 {
+        Ok(())
+    } else {
+        Err(Error::ChecksumMismatch(source.to_path_buf()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Utc;
+
+    fn entry(path: &str, sha1: &str) -> IndexEntry {
+        IndexEntry {
+            device: "iPhone 8".to_string(),
+            identifier: "iPhone10,1".to_string(),
+            version: "16.7.8".to_string(),
+            buildid: path.to_string(),
+            sha1: sha1.to_string(),
+            size: 5,
+            path: PathBuf::from(path),
+            downloaded: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn syncs_to_dirs() {
+        let library = tempfile::tempdir().unwrap();
+        let mirror = tempfile::tempdir().unwrap();
+
+        //SHA1 of "hello"
+        std::fs::write(library.path().join("good.ipsw"), "hello").unwrap();
+        std::fs::write(library.path().join("bad.ipsw"), "oops!").unwrap();
+        let index = Index::open(library.path()).unwrap();
+        index.record(&entry("good.ipsw", "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d")).unwrap();
+        index.record(&entry("bad.ipsw", "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d")).unwrap();
+
+        //A file deleted from the library
+        let mirror_index = Index::open(mirror.path()).unwrap();
+        std::fs::write(mirror.path().join("old.ipsw"), "old").unwrap();
+        mirror_index.record(&entry("old.ipsw", "")).unwrap();
+
+        let target = SyncTarget::Dir(mirror.path().to_path_buf());
+        let results = sync(library.path(), &target, true).await.unwrap();
+        let action = |path: &str| results.iter().find(|r| r.path == Path::new(path)).unwrap().action;
+
+        assert_eq!(action("good.ipsw"), SyncAction::Copied);
+        assert_eq!(action("bad.ipsw"), SyncAction::Failed);
+        assert_eq!(action("old.ipsw"), SyncAction::Deleted);
+        assert!(mirror.path().join("good.ipsw").exists());
+        assert!(!mirror.path().join("bad.ipsw").exists());
+        assert!(!mirror.path().join("old.ipsw").exists());
+
+        let results = sync(library.path(), &target, true).await.unwrap();
+        assert_eq!(results.iter().find(|r| r.path == Path::new("good.ipsw")).unwrap().action, SyncAction::Unchanged);
+    }
+
+    #[tokio::test]
+    async fn waits_for_downloads() {
+        let library = tempfile::tempdir().unwrap();
+        let mirror = tempfile::tempdir().unwrap();
+        let target = SyncTarget::Dir(mirror.path().to_path_buf());
+
+        let lock = DirLock::acquire(library.path()).unwrap();
+        assert!(matches!(sync(library.path(), &target, false).await, Err(Error::Locked { .. })));
+
+        drop(lock);
+        sync(library.path(), &target, false).await.unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+//! Parsing of human readable quantities passed on the command line.
+use std::time::Duration;
+
+/// Parses a byte size such as `500K`, `10MB` or `1.5GiB` into bytes.
+///
+/// Suffixes are binary (1K = 1024 bytes), matching tools like curl. A trailing `B` or `iB` is optional,
+/// and a bare number is taken as bytes.
+pub fn parse_bytes(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {}", s))?;
+
+    let multiplier: u64 = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size suffix in: {}", s)),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a duration such as `90s`, `30m`, `6h` or `1.5d`.
+///
+/// A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {}", s))?;
+
+    let multiplier: f64 = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 60.0 * 60.0,
+        "d" => 60.0 * 60.0 * 24.0,
+        _ => return Err(format!("unknown duration suffix in: {}", s)),
+    };
+
+    Ok(Duration::from_secs_f64(number * multiplier))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_bytes_works() {
+        assert_eq!(parse_bytes("512").unwrap(), 512);
+        assert_eq!(parse_bytes("10MB").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_bytes("1.5g").unwrap(), 1024 * 1024 * 1024 * 3 / 2);
+        assert_eq!(parse_bytes(" 2 KiB ").unwrap(), 2048);
+        assert!(parse_bytes("ten MB").is_err());
+        assert!(parse_bytes("10XB").is_err());
+    }
+
+    #[test]
+    fn parse_duration_works() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_duration("6h").unwrap(), Duration::from_secs(6 * 60 * 60));
+        assert_eq!(parse_duration("1.5D").unwrap(), Duration::from_secs(36 * 60 * 60));
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("6w").is_err());
+    }
+}
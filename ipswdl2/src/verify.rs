@@ -0,0 +1,297 @@
+//! Checks downloaded ipsw files against the checksums reported by the API.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use md5::Md5;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+
+use crate::api_json_types::{Firmware, FirmwareListing};
+use crate::error::{Error, Result};
+use crate::paths::{self, PathTemplate};
+
+/// Which API checksums downloads are compared against.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Checksum {
+    Sha1,
+    Md5,
+    Both,
+}
+
+impl Checksum {
+    fn sha1(self) -> bool {
+        self != Checksum::Md5
+    }
+
+    fn md5(self) -> bool {
+        self != Checksum::Sha1
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sha1" => Ok(Checksum::Sha1),
+            "md5" => Ok(Checksum::Md5),
+            "both" => Ok(Checksum::Both),
+            _ => Err(format!("unknown checksum: {}, expected md5, sha1 or both", s)),
+        }
+    }
+}
+
+/// Hashes a download as it streams. SHA1 is always computed, as the index records it.
+pub struct DownloadHasher {
+    checksum: Checksum,
+    sha1: Sha1,
+    md5: Option<Md5>,
+}
+
+impl DownloadHasher {
+    pub fn new(checksum: Checksum) -> Self {
+        DownloadHasher {
+            checksum,
+            sha1: Sha1::new(),
+            md5: if checksum.md5() { Some(Md5::new()) } else { None },
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha1.update(data);
+        if let Some(md5) = &mut self.md5 {
+            md5.update(data);
+        }
+    }
+
+    pub fn finalize(self) -> Digests {
+        Digests {
+            checksum: self.checksum,
+            sha1: format!("{:x}", self.sha1.finalize()),
+            md5: self.md5.map(|md5| format!("{:x}", md5.finalize())),
+        }
+    }
+}
+
+impl Write for DownloadHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hex encoded hashes of a download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digests {
+    checksum: Checksum,
+    pub sha1: String,
+    /// Only computed if MD5 is checked.
+    pub md5: Option<String>,
+}
+
+impl Digests {
+    /// Compares the hashes to the checksums the API has for `firmware`. Checksums the API lacks are ignored.
+    ///
+    /// # Returns
+    /// * Ok(true) - Every checksum compared matched.
+    /// * Ok(false) - The API had none of the checksums to compare.
+    /// * Err(String) - A checksum didn't match, describing which.
+    pub fn check(&self, firmware: &Firmware) -> std::result::Result<bool, String> {
+        let mut compared = false;
+
+        if self.checksum.sha1() && !firmware.sha1sum.is_empty() {
+            if !firmware.sha1sum.eq_ignore_ascii_case(&self.sha1) {
+                return Err(format!("SHA1 mismatch, expected {} but got {}", firmware.sha1sum, self.sha1));
+            }
+            compared = true;
+        }
+
+        if let Some(md5) = self.md5.as_ref().filter(|_| !firmware.md5sum.is_empty()) {
+            if !firmware.md5sum.eq_ignore_ascii_case(md5) {
+                return Err(format!("MD5 mismatch, expected {} but got {}", firmware.md5sum, md5));
+            }
+            compared = true;
+        }
+
+        Ok(compared)
+    }
+}
+
+/// Outcome of verifying a single file.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum VerifyStatus {
+    /// The file matches the API checksum.
+    Ok,
+    /// The file does not match the API checksum, and is likely corrupt.
+    Mismatch { expected: String, actual: String },
+    /// The API has no checksum for this firmware, so the file could not be checked.
+    NoChecksum,
+    /// The file could not be read.
+    Error { error: String },
+}
+
+/// A downloaded file and the outcome of verifying it.
+#[derive(Serialize, Debug)]
+pub struct VerifyResult {
+    pub device: String,
+    pub version: String,
+    pub buildid: String,
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub status: VerifyStatus,
+}
+
+/// Verifies every downloaded file of a device against the checksums in its firmware listing.
+/// Firmware that hasn't been downloaded is ignored.
+pub async fn verify_listing(listing: &FirmwareListing, download_path: &Path, template: &PathTemplate) -> Vec<VerifyResult> {
+    //Files named only by version may belong to any build of that version, so group candidates by path
+    let mut candidates: BTreeMap<PathBuf, Vec<&Firmware>> = BTreeMap::new();
+    for firmware in &listing.firmwares {
+        for with_buildid in &[false, true] {
+            let path = paths::firmware_path(download_path, template, listing, firmware, *with_buildid);
+            candidates.entry(path).or_default().push(firmware);
+        }
+    }
+
+    let mut results = Vec::new();
+    for (path, firmwares) in candidates {
+        if !path.exists() {
+            continue;
+        }
+
+        let (firmware, status) = verify_file(&path, &firmwares).await;
+        results.push(VerifyResult {
+            device: listing.name.clone(),
+            version: firmware.version.clone(),
+            buildid: firmware.buildid.clone(),
+            path,
+            status,
+        });
+    }
+
+    results
+}
+
+/// Verifies a file that may be any of `firmwares`, returning the firmware it was matched to.
+async fn verify_file<'a>(path: &Path, firmwares: &[&'a Firmware]) -> (&'a Firmware, VerifyStatus) {
+    let newest = firmwares[0];
+
+    if firmwares.iter().all(|f| f.sha1sum.is_empty()) {
+        return (newest, VerifyStatus::NoChecksum);
+    }
+
+    let actual = match sha1_file(path.to_path_buf()).await {
+        Ok(actual) => actual,
+        Err(why) => return (newest, VerifyStatus::Error { error: why.to_string() }),
+    };
+
+    match firmwares.iter().find(|f| f.sha1sum.eq_ignore_ascii_case(&actual)) {
+        Some(firmware) => (firmware, VerifyStatus::Ok),
+        None => (
+            newest,
+            VerifyStatus::Mismatch {
+                expected: newest.sha1sum.clone(),
+                actual,
+            },
+        ),
+    }
+}
+
+/// Verifies a file downloaded for a single firmware.
+pub async fn verify_firmware(path: &Path, firmware: &Firmware) -> VerifyStatus {
+    verify_file(path, &[firmware]).await.1
+}
+
+/// Checks a file is a readable zip archive, as every ipsw is, without blocking the async runtime.
+///
+/// Only the structure is checked: the end of central directory must be present, and every entry's header readable.
+/// This catches truncated files and error pages even when the API has no checksum.
+pub async fn check_zip(path: PathBuf) -> Result<()> {
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let file = File::open(&path).map_err(|why| Error::io("could not open", &path, why))?;
+
+        let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+        for i in 0..archive.len() {
+            archive.by_index(i)?;
+        }
+
+        Ok(())
+    })
+    .await
+    .expect("zip check task panicked")
+}
+
+/// Computes the hex encoded SHA1 of a file, without blocking the async runtime.
+pub async fn sha1_file(path: PathBuf) -> Result<String> {
+    tokio::task::spawn_blocking(move || -> Result<String> {
+        let file = File::open(&path).map_err(|why| Error::io("could not open", &path, why))?;
+
+        let mut hasher = Sha1::new();
+        std::io::copy(&mut BufReader::new(file), &mut hasher).map_err(|why| Error::io("could not read", &path, why))?;
+
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .expect("hashing task panicked")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn firmware(sha1sum: &str, md5sum: &str) -> Firmware {
+        serde_json::from_value(serde_json::json!({
+            "identifier": "iPhone10,1", "version": "16.7.8", "buildid": "20H343", "sha1sum": sha1sum,
+            "md5sum": md5sum, "filesize": 3, "url": "", "uploaddate": "2024-05-13T17:00:00Z", "signed": true
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn checks_selected_checksums() {
+        let sha1 = "a9993e364706816aba3e25717850c26c9cd0d89d";
+        let md5 = "900150983cd24fb0d6963f7d28e17f72";
+
+        let hash = |checksum| {
+            let mut hasher = DownloadHasher::new(checksum);
+            hasher.update(b"abc");
+            hasher.finalize()
+        };
+
+        assert_eq!(hash(Checksum::Both).check(&firmware(sha1, md5)), Ok(true));
+        assert_eq!(hash(Checksum::Sha1).check(&firmware(sha1, "bad")), Ok(true));
+        assert!(hash(Checksum::Md5).check(&firmware(sha1, "bad")).is_err());
+        assert!(hash(Checksum::Both).check(&firmware("bad", md5)).is_err());
+        assert_eq!(hash(Checksum::Md5).check(&firmware(sha1, "")), Ok(false));
+    }
+
+    #[tokio::test]
+    async fn check_zip_catches_bad_archives() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let good = dir.path().join("good.ipsw");
+        let mut writer = zip::ZipWriter::new(File::create(&good).unwrap());
+        writer.start_file("BuildManifest.plist", Default::default()).unwrap();
+        writer.write_all(b"<plist/>").unwrap();
+        writer.finish().unwrap();
+        assert!(check_zip(good.clone()).await.is_ok());
+
+        //Truncated downloads lose the central directory at the end
+        let truncated = dir.path().join("truncated.ipsw");
+        let contents = std::fs::read(&good).unwrap();
+        std::fs::write(&truncated, &contents[..contents.len() / 2]).unwrap();
+        assert!(check_zip(truncated).await.is_err());
+
+        let error_page = dir.path().join("error.ipsw");
+        std::fs::write(&error_page, "<html>Access Denied</html>").unwrap();
+        assert!(check_zip(error_page).await.is_err());
+    }
+}
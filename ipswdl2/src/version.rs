@@ -0,0 +1,70 @@
+//! Ordering of firmware releases.
+use std::cmp::Ordering;
+
+use crate::api_json_types::Firmware;
+
+/// Compares dotted version strings numerically, so `16.10` is newer than `16.9`.
+///
+/// Missing components count as 0, so `17` equals `17.0`. Non numeric components are compared as text.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+
+    loop {
+        let ordering = match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (a_part, b_part) => {
+                let a_part = a_part.unwrap_or("0");
+                let b_part = b_part.unwrap_or("0");
+
+                match (a_part.parse::<u64>(), b_part.parse::<u64>()) {
+                    (Ok(a_num), Ok(b_num)) => a_num.cmp(&b_num),
+                    _ => a_part.cmp(b_part),
+                }
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// True if `version` is no newer than `max`, comparing only as many components as `max` has.
+///
+/// This makes a max of `17` allow every `17.x`, rather than only `17.0`.
+pub fn at_most(version: &str, max: &str) -> bool {
+    let components = max.split('.').count();
+    let truncated: Vec<&str> = version.split('.').take(components).collect();
+
+    compare_versions(&truncated.join("."), max) != Ordering::Greater
+}
+
+/// Orders firmware newest first, by upload date and then version.
+pub fn newest_first(a: &Firmware, b: &Firmware) -> Ordering {
+    b.uploaddate
+        .cmp(&a.uploaddate)
+        .then_with(|| compare_versions(&b.version, &a.version))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compare_versions_numerically() {
+        assert_eq!(compare_versions("16.10", "16.9"), Ordering::Greater);
+        assert_eq!(compare_versions("17", "17.0"), Ordering::Equal);
+        assert_eq!(compare_versions("15.7.1", "15.7"), Ordering::Greater);
+        assert_eq!(compare_versions("9.3.5", "10.0"), Ordering::Less);
+    }
+
+    #[test]
+    fn at_most_includes_every_minor_version() {
+        assert!(at_most("17.5.1", "17"));
+        assert!(at_most("16.7.8", "17"));
+        assert!(!at_most("18.0", "17"));
+        assert!(at_most("17.4", "17.4"));
+        assert!(!at_most("17.5", "17.4"));
+    }
+}
@@ -0,0 +1,147 @@
+//! Notifies a webhook when firmware is downloaded, fails to download, or a run completes.
+use std::str::FromStr;
+
+use indicatif::HumanBytes;
+use tracing::{debug, error};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::error::Result;
+use crate::output::{DownloadResult, Summary};
+
+/// Shape of the payload POSTed to the webhook.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WebhookFormat {
+    /// The event itself as a JSON object, for generic receivers.
+    Json,
+    /// A Discord webhook message.
+    Discord,
+    /// A Slack incoming webhook message.
+    Slack,
+}
+
+impl WebhookFormat {
+    /// Guesses the format from the webhook URL, falling back to generic JSON.
+    pub fn detect(url: &str) -> Self {
+        if url.contains("discord.com/api/webhooks") || url.contains("discordapp.com/api/webhooks") {
+            WebhookFormat::Discord
+        } else if url.contains("hooks.slack.com") {
+            WebhookFormat::Slack
+        } else {
+            WebhookFormat::Json
+        }
+    }
+}
+
+impl FromStr for WebhookFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(WebhookFormat::Json),
+            "discord" => Ok(WebhookFormat::Discord),
+            "slack" => Ok(WebhookFormat::Slack),
+            _ => Err(format!("unknown webhook format: {}", s)),
+        }
+    }
+}
+
+/// Something worth notifying the webhook about.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent<'a> {
+    /// A firmware was downloaded or failed to download.
+    Download(&'a DownloadResult),
+    /// A download run finished.
+    RunCompleted(&'a Summary),
+}
+
+impl WebhookEvent<'_> {
+    /// Describes the event in a single line, for chat messages.
+    pub fn message(&self) -> String {
+        match self {
+            WebhookEvent::Download(DownloadResult::Downloaded { device, version, buildid, size, path }) => format!(
+                "Downloaded {} {} ({}), {} to {}",
+                device,
+                version,
+                buildid,
+                HumanBytes(*size),
+                path.display()
+            ),
+            WebhookEvent::Download(DownloadResult::Failed { device, version, error }) => match version {
+                Some(version) => format!("Failed to download {} {}: {}", device, version, error),
+                None => format!("Failed to download {}: {}", device, error),
+            },
+            WebhookEvent::Download(DownloadResult::Skipped { device, reason, .. }) => {
+                format!("Skipped {}: {}", device, reason)
+            }
+            WebhookEvent::RunCompleted(summary) => format!(
+                "Finished in {} minutes. {} downloaded, {} skipped, {} failed.",
+                summary.minutes, summary.downloaded, summary.skipped, summary.failed
+            ),
+        }
+    }
+}
+
+/// A webhook to POST events to.
+pub struct Webhook {
+    url: String,
+    format: WebhookFormat,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    /// Creates a webhook posting to `url`. The format is guessed from the URL if not passed.
+    ///
+    /// # Returns
+    /// * Err - The TLS backend failed to initialize.
+    pub fn new(url: String, format: Option<WebhookFormat>) -> Result<Self> {
+        let format = format.unwrap_or_else(|| WebhookFormat::detect(&url));
+        let client = reqwest::ClientBuilder::new().build()?;
+
+        Ok(Webhook { url, format, client })
+    }
+
+    /// POSTs an event to the webhook. Failures are only logged, as a broken webhook shouldn't stop downloads.
+    pub async fn notify(&self, event: &WebhookEvent<'_>) {
+        let payload = match self.format {
+            WebhookFormat::Json => json!(event),
+            WebhookFormat::Discord => json!({ "content": event.message() }),
+            WebhookFormat::Slack => json!({ "text": event.message() }),
+        };
+        debug!("notifying webhook: {}", payload);
+
+        let response = self.client.post(&self.url).json(&payload).send().await;
+
+        match response.and_then(|r| r.error_for_status()) {
+            Ok(_) => {}
+            Err(why) => error!("Failed to notify webhook: {}", why),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(WebhookFormat::detect("https://discord.com/api/webhooks/1/abc"), WebhookFormat::Discord);
+        assert_eq!(WebhookFormat::detect("https://hooks.slack.com/services/T/B/X"), WebhookFormat::Slack);
+        assert_eq!(WebhookFormat::detect("https://example.com/hook"), WebhookFormat::Json);
+    }
+
+    #[test]
+    fn json_payload_is_tagged() {
+        let result = DownloadResult::Failed {
+            device: "iPhone 8".to_string(),
+            version: None,
+            error: "oops".to_string(),
+        };
+        let payload = json!(WebhookEvent::Download(&result));
+
+        assert_eq!(payload["event"], "download");
+        assert_eq!(payload["result"], "failed");
+        assert_eq!(payload["device"], "iPhone 8");
+    }
+}